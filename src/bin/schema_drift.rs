@@ -0,0 +1,287 @@
+//! Compares records returned by Teamwork against the structs generated by
+//! `teamwork_schema`, reporting fields that were added, removed or changed
+//! type.
+//!
+//! ```text
+//! schema-drift [--fixtures <dir>] [--rewrite <schemas dir>] [--samples <n>]
+//! ```
+//!
+//! Records are fetched from the Teamwork instance configured by `TEAMWORK_URL`
+//! and `API_KEY`, or read from a directory of recorded responses with
+//! `--fixtures`. With `--rewrite` the records replace the sample JSON used by
+//! `generate_schema!`, scrubbed so that only their fields and JSON types are
+//! kept rather than account data. The process exits with status 1 when drift
+//! is found.
+
+use std::path::{Path, PathBuf};
+
+use teamwork_schema::meta::{self, FieldDiff};
+
+#[allow(clippy::enum_variant_names)]
+#[derive(thiserror::Error, Debug)]
+enum Error {
+    #[error("Invalid config {0:?}")]
+    ConfigError(#[from] config::ConfigError),
+    #[error("IOError {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("Invalid JSON in {0}: {1}")]
+    JsonError(String, serde_json::Error),
+    #[error("Request to Teamwork failed: {0}")]
+    RequestError(String),
+    #[error("Invalid arguments: {0}")]
+    ArgsError(String),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A resource proxied by the API along with where its records come from.
+struct Resource {
+    schema: &'static str,
+    route: &'static str,
+    response_key: &'static str,
+    /// The sample file, relative to the schemas directory, used by
+    /// `generate_schema!`.
+    sample_file: &'static str,
+}
+
+const RESOURCES: &[Resource] = &[
+    Resource {
+        schema: "Task",
+        route: "tasks.json",
+        response_key: "todo-items",
+        sample_file: "task.json",
+    },
+    Resource {
+        schema: "TimeEntry",
+        route: "time_entries.json",
+        response_key: "time-entries",
+        sample_file: "time_entry.json",
+    },
+    Resource {
+        schema: "TaskList",
+        route: "tasklists.json",
+        response_key: "tasklists",
+        sample_file: "task_list.json",
+    },
+    Resource {
+        schema: "Project",
+        route: "projects.json",
+        response_key: "projects",
+        sample_file: "project.json",
+    },
+];
+
+#[derive(Default)]
+struct Args {
+    fixtures: Option<PathBuf>,
+    rewrite: Option<PathBuf>,
+    samples: usize,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut args = Args {
+            samples: 5,
+            ..Args::default()
+        };
+
+        let mut iter = std::env::args().skip(1);
+
+        while let Some(arg) = iter.next() {
+            let mut value = || {
+                iter.next()
+                    .ok_or_else(|| Error::ArgsError(format!("{} expects a value", arg)))
+            };
+
+            match arg.as_str() {
+                "--fixtures" => args.fixtures = Some(value()?.into()),
+                "--rewrite" => args.rewrite = Some(value()?.into()),
+                "--samples" => {
+                    args.samples = value()?
+                        .parse()
+                        .map_err(|_| Error::ArgsError("--samples expects a number".into()))?
+                }
+                other => return Err(Error::ArgsError(format!("unexpected argument {}", other))),
+            }
+        }
+
+        Ok(args)
+    }
+}
+
+/// Extracts the records from a response body, which is either a Teamwork
+/// response, an array of records or a single record.
+fn records_from_body(body: serde_json::Value, response_key: &str) -> Vec<serde_json::Value> {
+    match body {
+        serde_json::Value::Object(mut obj) if obj.contains_key(response_key) => {
+            match obj.remove(response_key) {
+                Some(serde_json::Value::Array(records)) => records,
+                Some(record) => vec![record],
+                None => vec![],
+            }
+        }
+        serde_json::Value::Array(records) => records,
+        record => vec![record],
+    }
+}
+
+fn read_fixture(dir: &Path, resource: &Resource) -> Result<Option<Vec<serde_json::Value>>> {
+    let path = dir.join(resource.sample_file);
+
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let body = std::fs::read_to_string(&path)?;
+    let body =
+        serde_json::from_str(&body).map_err(|e| Error::JsonError(path.display().to_string(), e))?;
+
+    Ok(Some(records_from_body(body, resource.response_key)))
+}
+
+async fn fetch(
+    client: &surf::Client,
+    endpoint: &str,
+    auth: &str,
+    resource: &Resource,
+    samples: usize,
+) -> Result<Vec<serde_json::Value>> {
+    let url = format!("{}/{}?pageSize={}", endpoint, resource.route, samples);
+
+    let mut response = client
+        .get(&url)
+        .header("Authorization", auth)
+        .send()
+        .await
+        .map_err(|e| Error::RequestError(e.to_string()))?;
+
+    if !response.status().is_success() {
+        return Err(Error::RequestError(format!(
+            "{} returned {}",
+            url,
+            response.status()
+        )));
+    }
+
+    let body: serde_json::Value = response
+        .body_json()
+        .await
+        .map_err(|e| Error::RequestError(e.to_string()))?;
+
+    Ok(records_from_body(body, resource.response_key))
+}
+
+/// Replaces the values of a record with placeholders of the same JSON type, so
+/// that rewritten samples infer the same types without holding account data.
+fn scrub(value: &serde_json::Value) -> serde_json::Value {
+    use serde_json::Value;
+
+    match value {
+        Value::String(s) if s.is_empty() => Value::from(""),
+        Value::String(_) => Value::from("redacted"),
+        Value::Number(n) if n.is_f64() => Value::from(0.5),
+        Value::Number(_) => Value::from(1),
+        Value::Array(items) => items.iter().map(scrub).collect(),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), scrub(value)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+fn report(schema: &str, diffs: &[FieldDiff]) {
+    if diffs.is_empty() {
+        println!("{}: no drift", schema);
+        return;
+    }
+
+    println!("{}:", schema);
+
+    for diff in diffs {
+        match diff {
+            FieldDiff::Added { field, found } => println!("  + {} ({})", field, found),
+            FieldDiff::Removed { field } => println!("  - {}", field),
+            FieldDiff::TypeChanged {
+                field,
+                expected,
+                found,
+            } => println!("  ~ {} (expected {}, found {})", field, expected, found),
+        }
+    }
+}
+
+#[async_std::main]
+async fn main() -> Result<()> {
+    let args = Args::parse()?;
+
+    let live = if args.fixtures.is_none() {
+        let mut config = config::Config::new();
+
+        config
+            .merge(config::File::new(".env", config::FileFormat::Toml).required(false))?
+            .merge(config::Environment::new())?;
+
+        let endpoint = config.get_str("teamwork_url")?;
+        let auth = format!(
+            "Basic {}",
+            base64::encode(format!("{}: ", config.get_str("api_key")?))
+        );
+
+        Some((surf::Client::new(), endpoint, auth))
+    } else {
+        None
+    };
+
+    let mut drifted = false;
+
+    for resource in RESOURCES {
+        let schema = meta::find(resource.schema).expect("resources refer to generated schemas");
+
+        let records = match (&args.fixtures, &live) {
+            (Some(dir), _) => match read_fixture(dir, resource)? {
+                Some(records) => records,
+                None => {
+                    println!("{}: no fixture, skipping", resource.schema);
+                    continue;
+                }
+            },
+            (None, Some((client, endpoint, auth))) => {
+                fetch(client, endpoint, auth, resource, args.samples).await?
+            }
+            (None, None) => unreachable!("live config is created when fixtures are omitted"),
+        };
+
+        if records.is_empty() {
+            println!("{}: no records returned, skipping", resource.schema);
+            continue;
+        }
+
+        let diffs = meta::compare(schema, &records);
+        drifted |= !diffs.is_empty();
+
+        report(resource.schema, &diffs);
+
+        if let Some(dir) = &args.rewrite {
+            let path = dir.join(resource.sample_file);
+            let samples: Vec<serde_json::Value> =
+                records.iter().take(args.samples).map(scrub).collect();
+
+            std::fs::write(
+                &path,
+                serde_json::to_string_pretty(&samples).expect("JSON values always serialize")
+                    + "\n",
+            )?;
+
+            println!("  rewrote {}", path.display());
+        }
+    }
+
+    if drifted {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
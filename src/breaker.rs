@@ -0,0 +1,120 @@
+//! A circuit breaker per Teamwork endpoint. After repeated failures the circuit
+//! opens and requests fail fast rather than waiting on a Teamwork that's down,
+//! until a single probe request is let through to check if it recovered.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy)]
+enum Circuit {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A probe request is in flight, started at `since`.
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_for: Duration,
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+impl CircuitBreaker {
+    /// A breaker opening after `failure_threshold` consecutive failures and
+    /// staying open for `open_for`.
+    pub fn new(failure_threshold: u32, open_for: Duration) -> Self {
+        CircuitBreaker {
+            failure_threshold: failure_threshold.max(1),
+            open_for,
+            circuits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn with<T, F: FnOnce(&mut Circuit) -> T>(&self, endpoint: &str, f: F) -> T {
+        let mut circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        let circuit = circuits
+            .entry(endpoint.to_string())
+            .or_insert(Circuit::Closed { failures: 0 });
+
+        f(circuit)
+    }
+
+    /// Checks whether a request to `endpoint` may be made, returning how long
+    /// to wait before retrying when the circuit is open.
+    pub fn check(&self, endpoint: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let open_for = self.open_for;
+
+        self.with(endpoint, |circuit| match *circuit {
+            Circuit::Closed { .. } => Ok(()),
+            Circuit::Open { until } if now < until => Err(until - now),
+            // a probe that never reported back doesn't hold the circuit open
+            // forever
+            Circuit::HalfOpen { since } if now < since + open_for => Err(since + open_for - now),
+            Circuit::Open { .. } | Circuit::HalfOpen { .. } => {
+                *circuit = Circuit::HalfOpen { since: now };
+                Ok(())
+            }
+        })
+    }
+
+    pub fn success(&self, endpoint: &str) {
+        self.with(endpoint, |circuit| {
+            *circuit = Circuit::Closed { failures: 0 };
+        });
+    }
+
+    /// Records a failed request, returning whether it opened the circuit.
+    pub fn failure(&self, endpoint: &str) -> bool {
+        let until = Instant::now() + self.open_for;
+        let threshold = self.failure_threshold;
+
+        self.with(endpoint, |circuit| match *circuit {
+            Circuit::Closed { failures } if failures + 1 < threshold => {
+                *circuit = Circuit::Closed {
+                    failures: failures + 1,
+                };
+                false
+            }
+            Circuit::Closed { .. } | Circuit::HalfOpen { .. } => {
+                *circuit = Circuit::Open { until };
+                true
+            }
+            Circuit::Open { .. } => false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_after_threshold_and_probes_once() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(20));
+
+        assert!(!breaker.failure("tasks.json"));
+        assert!(breaker.check("tasks.json").is_ok());
+        assert!(breaker.failure("tasks.json"));
+        assert!(breaker.check("tasks.json").is_err());
+        assert!(breaker.check("tasklists.json").is_ok());
+
+        std::thread::sleep(Duration::from_millis(25));
+
+        assert!(breaker.check("tasks.json").is_ok());
+        assert!(breaker.check("tasks.json").is_err());
+
+        breaker.success("tasks.json");
+        assert!(breaker.check("tasks.json").is_ok());
+    }
+}
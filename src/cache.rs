@@ -0,0 +1,96 @@
+//! Keeps the last successful response of each request so it can be served,
+//! marked as stale, while the circuit to Teamwork is open.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Whether a request was answered from the stale cache, recorded in the logs
+/// and metrics.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CacheStatus {
+    Hit,
+    Miss,
+    /// Answered from the local mirror instead.
+    Mirror,
+}
+
+impl CacheStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CacheStatus::Hit => "hit",
+            CacheStatus::Miss => "miss",
+            CacheStatus::Mirror => "mirror",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cached {
+    pub body: Vec<u8>,
+    pub link: String,
+    pub stored: Instant,
+}
+
+#[derive(Debug)]
+pub struct StaleCache {
+    max_entries: usize,
+    max_age: Duration,
+    entries: Mutex<HashMap<String, Cached>>,
+}
+
+impl StaleCache {
+    pub fn new(max_entries: usize, max_age: Duration) -> Self {
+        StaleCache {
+            max_entries: max_entries.max(1),
+            max_age,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The key of a request. Responses differ by who's asking, so the
+    /// identity is part of the key.
+    pub fn key(identity: &str, url: &str) -> String {
+        format!("{} {}", identity, url)
+    }
+
+    pub fn get(&self, key: &str) -> Option<Cached> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+
+        entries
+            .get(key)
+            .filter(|cached| cached.stored.elapsed() <= self.max_age)
+            .cloned()
+    }
+
+    pub fn insert(&self, key: String, body: Vec<u8>, link: String) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            let max_age = self.max_age;
+            entries.retain(|_, cached| cached.stored.elapsed() <= max_age);
+
+            // still full, make room by evicting the oldest entry
+            if entries.len() >= self.max_entries {
+                if let Some(oldest) = entries
+                    .iter()
+                    .min_by_key(|(_, cached)| cached.stored)
+                    .map(|(key, _)| key.clone())
+                {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+
+        entries.insert(
+            key,
+            Cached {
+                body,
+                link,
+                stored: Instant::now(),
+            },
+        );
+    }
+}
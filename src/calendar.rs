@@ -0,0 +1,373 @@
+//! An iCalendar feed of task start and due dates that calendar apps can
+//! subscribe to.
+
+use std::collections::HashMap;
+
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use teamwork_schema::Task;
+use tide::{Request, Response};
+
+use crate::{
+    reports::{nonblank, task_date},
+    upstream::Upstream,
+    Error, State,
+};
+
+/// The format of iCalendar dates.
+const DATE_FORMAT: &str = "%Y%m%d";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Component {
+    /// All day events, supported by every calendar app.
+    #[default]
+    Event,
+    /// To-dos with a due date, supported by some calendar apps.
+    Todo,
+}
+
+/// Filters on the feed's tasks. Each accepts a comma separated list of values,
+/// matching tasks with any of them. Projects and assignees are filtered by
+/// Teamwork, while tags are matched here since Teamwork only filters them by
+/// id.
+#[derive(Debug, Default, Deserialize)]
+struct Filters {
+    /// Project ids.
+    project: Option<String>,
+    /// Ids of people the task is assigned to.
+    assignee: Option<String>,
+    /// Tag names.
+    tag: Option<String>,
+    #[serde(default, rename = "type")]
+    component: Component,
+}
+
+fn list(filter: &Option<String>) -> Option<Vec<String>> {
+    filter.as_ref().map(|f| {
+        f.split(',')
+            .map(|v| v.trim().to_lowercase())
+            .filter(|v| !v.is_empty())
+            .collect()
+    })
+}
+
+/// The names of a task's tags.
+fn tags(task: &Task) -> Vec<&str> {
+    task.tags
+        .iter()
+        .flatten()
+        .filter_map(|tag| nonblank(&tag.name))
+        .collect()
+}
+
+impl Filters {
+    /// The params filtering Teamwork's tasks by project and assignee.
+    fn teamwork_params(&self) -> HashMap<String, Value> {
+        let mut params = HashMap::new();
+
+        for (param, filter) in &[
+            ("projectIds", &self.project),
+            ("responsible-party-ids", &self.assignee),
+        ] {
+            if let Some(values) = list(filter).filter(|values| !values.is_empty()) {
+                params.insert(param.to_string(), values.join(",").into());
+            }
+        }
+
+        params
+    }
+
+    /// Whether a task matches every filter, checked again locally so that
+    /// the feed doesn't depend on Teamwork honouring the params.
+    fn matches(&self, task: &Task) -> bool {
+        let any = |filter: &Option<String>, values: Vec<&str>| match list(filter) {
+            Some(wanted) => values
+                .iter()
+                .any(|v| wanted.contains(&v.trim().to_lowercase())),
+            None => true,
+        };
+
+        let project = task.project_id.map(|id| id.to_string());
+
+        let assignees = nonblank(&task.responsible_party_ids)
+            .map(|ids| ids.split(',').collect())
+            .unwrap_or_default();
+
+        any(&self.project, project.iter().map(String::as_str).collect())
+            && any(&self.assignee, assignees)
+            && any(&self.tag, tags(task))
+    }
+}
+
+/// Escapes a value of a text property.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// Appends a content line, folding it at 75 octets as required by RFC 5545.
+fn push_line(ics: &mut String, line: &str) {
+    let mut width = 0;
+
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            ics.push_str("\r\n ");
+            width = 1;
+        }
+
+        ics.push(c);
+        width += c.len_utf8();
+    }
+
+    ics.push_str("\r\n");
+}
+
+/// The component of a task, `None` when it has neither a start nor due date.
+fn component(task: &Task, kind: Component, stamp: &str) -> Option<Vec<String>> {
+    let id = task.id?;
+    let start = task_date(&task.start_date);
+    let due = task_date(&task.due_date);
+
+    if start.is_none() && due.is_none() {
+        return None;
+    }
+
+    let completed = task.completed.unwrap_or(false);
+
+    let mut lines = vec![
+        match kind {
+            Component::Event => "BEGIN:VEVENT".to_string(),
+            Component::Todo => "BEGIN:VTODO".to_string(),
+        },
+        format!("UID:task-{}@teamwork_api", id),
+        format!(
+            "DTSTAMP:{}",
+            nonblank(&task.updated_at)
+                .map(|updated| updated.replace(['-', ':'], ""))
+                .unwrap_or_else(|| stamp.to_string())
+        ),
+        format!(
+            "SUMMARY:{}",
+            escape(nonblank(&task.content).unwrap_or_default())
+        ),
+    ];
+
+    match kind {
+        // all day events ending the day after they're due, since the end date
+        // is exclusive
+        Component::Event => {
+            let first = start.or(due)?;
+            let last = due.filter(|due| *due >= first).unwrap_or(first);
+
+            lines.push(format!("DTSTART;VALUE=DATE:{}", first.format(DATE_FORMAT)));
+            lines.push(format!(
+                "DTEND;VALUE=DATE:{}",
+                (last + Duration::days(1)).format(DATE_FORMAT)
+            ));
+        }
+        Component::Todo => {
+            if let Some(start) = start {
+                lines.push(format!("DTSTART;VALUE=DATE:{}", start.format(DATE_FORMAT)));
+            }
+
+            if let Some(due) = due {
+                lines.push(format!("DUE;VALUE=DATE:{}", due.format(DATE_FORMAT)));
+            }
+
+            lines.push(
+                if completed {
+                    "STATUS:COMPLETED"
+                } else {
+                    "STATUS:NEEDS-ACTION"
+                }
+                .to_string(),
+            );
+        }
+    }
+
+    if let Some(description) = nonblank(&task.description) {
+        lines.push(format!("DESCRIPTION:{}", escape(description)));
+    }
+
+    let categories: Vec<String> = tags(task).into_iter().map(escape).collect();
+
+    if !categories.is_empty() {
+        lines.push(format!("CATEGORIES:{}", categories.join(",")));
+    }
+
+    if let Some(project) = nonblank(&task.project_name) {
+        lines.push(format!("LOCATION:{}", escape(project)));
+    }
+
+    lines.push(match kind {
+        Component::Event => "END:VEVENT".to_string(),
+        Component::Todo => "END:VTODO".to_string(),
+    });
+
+    Some(lines)
+}
+
+/// Renders the tasks matching the filters as an iCalendar.
+fn to_ics(tasks: &[Task], filters: &Filters) -> String {
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+
+    let mut ics = String::new();
+
+    for line in &[
+        "BEGIN:VCALENDAR",
+        "VERSION:2.0",
+        "PRODID:-//teamwork_api//Tasks//EN",
+        "CALSCALE:GREGORIAN",
+        "X-WR-CALNAME:Teamwork tasks",
+    ] {
+        push_line(&mut ics, line);
+    }
+
+    for task in tasks.iter().filter(|t| filters.matches(t)) {
+        for line in component(task, filters.component, &stamp).unwrap_or_default() {
+            push_line(&mut ics, &line);
+        }
+    }
+
+    push_line(&mut ics, "END:VCALENDAR");
+
+    ics
+}
+
+/// Serves every task with a start or due date as an iCalendar feed.
+pub async fn tasks(req: Request<State>) -> tide::Result {
+    let filters: Filters = req.query().map_err(|e| Error::QueryError(e.to_string()))?;
+
+    let upstream = Upstream::new(&req)?;
+    let tasks = upstream.get_all::<Task>(&filters.teamwork_params()).await?;
+
+    Ok(Response::builder(200)
+        .body(to_ics(&tasks, &filters))
+        .content_type("text/calendar; charset=utf-8")
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn filters(query: &str) -> Filters {
+        let url = tide::http::Url::parse(&format!("http://localhost/?{}", query)).unwrap();
+        tide::http::Request::new(tide::http::Method::Get, url)
+            .query()
+            .unwrap()
+    }
+
+    fn task() -> Task {
+        serde_json::from_value(json!({
+            "id": 7,
+            "content": "Ship; then, celebrate",
+            "project-id": 12,
+            "project-name": "Website",
+            "responsible-party-ids": "1,2",
+            "tags": [{ "name": "Launch" }],
+            "start-date": "20240301",
+            "due-date": "20240305",
+            "completed": false,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn filters_match_any_listed_value() {
+        let task = task();
+
+        assert!(filters("").matches(&task));
+        assert!(filters("project=3,12&assignee=2&tag=launch").matches(&task));
+        assert!(!filters("project=3").matches(&task));
+        assert!(!filters("assignee=3").matches(&task));
+        assert!(!filters("tag=docs").matches(&task));
+        let untagged: Task = serde_json::from_value(json!({ "id": 8, "tags": null })).unwrap();
+        assert!(!filters("tag=launch").matches(&untagged));
+    }
+
+    #[test]
+    fn projects_and_assignees_are_filtered_by_teamwork() {
+        let params = filters("project= 3, 12&assignee=2&tag=launch").teamwork_params();
+
+        assert_eq!(params.len(), 2);
+        assert_eq!(params["projectIds"], "3,12");
+        assert_eq!(params["responsible-party-ids"], "2");
+        assert!(filters("project=").teamwork_params().is_empty());
+    }
+
+    #[test]
+    fn events_span_whole_days() {
+        let lines = component(&task(), Component::Event, "20240101T000000Z").unwrap();
+
+        assert_eq!(lines[0], "BEGIN:VEVENT");
+        assert!(lines.contains(&"UID:task-7@teamwork_api".to_string()));
+        assert!(lines.contains(&"DTSTAMP:20240101T000000Z".to_string()));
+        assert!(lines.contains(&"SUMMARY:Ship\\; then\\, celebrate".to_string()));
+        assert!(lines.contains(&"DTSTART;VALUE=DATE:20240301".to_string()));
+        // the end of an all day event is exclusive
+        assert!(lines.contains(&"DTEND;VALUE=DATE:20240306".to_string()));
+        assert_eq!(lines.last().unwrap(), "END:VEVENT");
+
+        let mut due_only = task();
+        due_only.start_date = Some(String::new());
+
+        let lines = component(&due_only, Component::Event, "20240101T000000Z").unwrap();
+
+        assert!(lines.contains(&"DTSTART;VALUE=DATE:20240305".to_string()));
+        assert!(lines.contains(&"DTEND;VALUE=DATE:20240306".to_string()));
+    }
+
+    #[test]
+    fn todos_are_due_and_carry_their_status() {
+        let lines = component(&task(), Component::Todo, "20240101T000000Z").unwrap();
+
+        assert_eq!(lines[0], "BEGIN:VTODO");
+        assert!(lines.contains(&"DTSTART;VALUE=DATE:20240301".to_string()));
+        assert!(lines.contains(&"DUE;VALUE=DATE:20240305".to_string()));
+        assert!(lines.contains(&"STATUS:NEEDS-ACTION".to_string()));
+        assert!(!lines.iter().any(|l| l.starts_with("DTEND")));
+
+        let mut completed = task();
+        completed.completed = Some(true);
+
+        let lines = component(&completed, Component::Todo, "20240101T000000Z").unwrap();
+
+        assert!(lines.contains(&"STATUS:COMPLETED".to_string()));
+    }
+
+    #[test]
+    fn tasks_without_dates_are_left_out() {
+        let mut undated = task();
+        undated.start_date = Some(String::new());
+        undated.due_date = None;
+
+        assert_eq!(
+            component(&undated, Component::Event, "20240101T000000Z"),
+            None
+        );
+    }
+
+    #[test]
+    fn long_lines_are_folded() {
+        let mut ics = String::new();
+        push_line(&mut ics, &format!("SUMMARY:{}", "é".repeat(60)));
+
+        for line in ics.trim_end().split("\r\n") {
+            assert!(line.len() <= 75);
+        }
+
+        assert_eq!(
+            ics.replace("\r\n ", ""),
+            format!("SUMMARY:{}\r\n", "é".repeat(60))
+        );
+    }
+}
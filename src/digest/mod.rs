@@ -0,0 +1,242 @@
+//! A daily digest for each person of their tasks due today, their overdue
+//! tasks and the time they logged yesterday, delivered through the configured
+//! sink. Run as `app digest [YYYY-MM-DD]`, defaulting to today.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Duration, Local, NaiveDate};
+use teamwork_schema::{Task, TimeEntry};
+
+use crate::{
+    reports::{
+        assignees, entry_date, minutes, nonblank, person, round, task_date, DateRange, DATE_FORMAT,
+    },
+    upstream::Upstream,
+    Error, Result, State,
+};
+
+mod render;
+mod sink;
+
+pub use sink::SinkConfig;
+
+#[derive(Debug, Clone)]
+pub struct TaskLine {
+    pub task: String,
+    pub project: Option<String>,
+    pub task_list: Option<String>,
+    pub due: NaiveDate,
+}
+
+#[derive(Debug, Clone)]
+pub struct EntryLine {
+    pub project: Option<String>,
+    pub task: Option<String>,
+    pub description: Option<String>,
+    pub hours: f64,
+}
+
+/// A person's digest for a day.
+#[derive(Debug, Default)]
+pub struct Digest {
+    pub person_id: String,
+    pub name: Option<String>,
+    pub date: NaiveDate,
+    pub due: Vec<TaskLine>,
+    pub overdue: Vec<TaskLine>,
+    pub logged: Vec<EntryLine>,
+}
+
+impl Digest {
+    pub fn subject(&self) -> String {
+        format!("Your Teamwork digest for {}", self.date.format(DATE_FORMAT))
+    }
+
+    /// The name of the person, falling back to their id.
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.person_id)
+    }
+
+    pub fn logged_hours(&self) -> f64 {
+        round(self.logged.iter().fold(0.0, |hours, e| hours + e.hours))
+    }
+}
+
+/// The digest of a person, created on first use.
+fn digest(
+    digests: &mut BTreeMap<String, Digest>,
+    date: NaiveDate,
+    person_id: String,
+    name: Option<String>,
+) -> &mut Digest {
+    let digest = digests.entry(person_id.clone()).or_insert_with(|| Digest {
+        person_id,
+        date,
+        ..Digest::default()
+    });
+
+    digest.name = digest.name.take().or(name);
+    digest
+}
+
+/// Builds the digest of everybody with tasks due or overdue on `date`, or time
+/// logged the day before, ordered by person id.
+fn collect(tasks: &[Task], entries: &[TimeEntry], date: NaiveDate) -> Vec<Digest> {
+    let mut digests: BTreeMap<String, Digest> = BTreeMap::new();
+
+    for task in tasks.iter().filter(|t| !t.completed.unwrap_or_default()) {
+        let due = match task_date(&task.due_date) {
+            Some(due) if due <= date => due,
+            _ => continue,
+        };
+
+        let line = TaskLine {
+            task: nonblank(&task.content).unwrap_or_default().to_string(),
+            project: nonblank(&task.project_name).map(str::to_string),
+            task_list: nonblank(&task.todo_list_name).map(str::to_string),
+            due,
+        };
+
+        for (person_id, name) in assignees(task) {
+            if let Some(person_id) = person_id {
+                let digest = digest(&mut digests, date, person_id, name);
+
+                if due == date {
+                    digest.due.push(line.clone());
+                } else {
+                    digest.overdue.push(line.clone());
+                }
+            }
+        }
+    }
+
+    let yesterday = date - Duration::days(1);
+
+    for entry in entries {
+        let person_id = match nonblank(&entry.person_id) {
+            Some(id) if entry_date(entry) == Some(yesterday) => id.to_string(),
+            _ => continue,
+        };
+
+        let name = Some(person(entry)).filter(|n| !n.is_empty());
+
+        digest(&mut digests, date, person_id, name)
+            .logged
+            .push(EntryLine {
+                project: nonblank(&entry.project_name).map(str::to_string),
+                task: nonblank(&entry.todo_item_name).map(str::to_string),
+                description: nonblank(&entry.description).map(str::to_string),
+                hours: round(minutes(entry) / 60.0),
+            });
+    }
+
+    for digest in digests.values_mut() {
+        digest.overdue.sort_by_key(|t| t.due);
+    }
+
+    digests.into_values().collect()
+}
+
+/// The params fetching the time entries logged yesterday. They cover a day
+/// either side of it, since Teamwork filters entries by their UTC date, and
+/// `collect` keeps the entries logged for yesterday.
+fn entry_params(date: NaiveDate) -> Result<HashMap<String, serde_json::Value>> {
+    let yesterday = date - Duration::days(1);
+    let range = DateRange::new(Some(yesterday), Some(yesterday)).map_err(Error::QueryError)?;
+
+    Ok(range.teamwork_params().into_iter().collect())
+}
+
+/// Delivers the digests for the date in `args`, or today.
+pub async fn run(state: State, args: &[String]) -> Result<()> {
+    let date = match args.first() {
+        Some(date) => NaiveDate::parse_from_str(date, DATE_FORMAT)
+            .map_err(|e| Error::QueryError(format!("invalid date {}: {}", date, e)))?,
+        None => Local::now().date_naive(),
+    };
+
+    let sink_config = state.config.digest_sink().await?;
+    let sink = sink_config.build(state.client.clone())?;
+
+    let upstream = Upstream::with_api_key(&state)?;
+
+    let tasks = upstream.get_all::<Task>(&HashMap::new()).await?;
+    let entries = upstream.get_all::<TimeEntry>(&entry_params(date)?).await?;
+
+    let digests: Vec<Digest> = collect(&tasks, &entries, date)
+        .into_iter()
+        .filter(|d| sink_config.delivers_to(&d.person_id))
+        .collect();
+
+    let mut failed = 0;
+
+    for digest in &digests {
+        if let Err(e) = sink.deliver(digest).await {
+            tide::log::error!("Failed to deliver digest", {
+                person_id: digest.person_id,
+                error: e.to_string(),
+            });
+            failed += 1;
+        }
+    }
+
+    tide::log::info!("Delivered digests", {
+        date: date.format(DATE_FORMAT).to_string(),
+        delivered: digests.len() - failed,
+        failed: failed,
+    });
+
+    if failed > 0 {
+        return Err(Error::DeliveryError(format!(
+            "{} of {} digests failed",
+            failed,
+            digests.len()
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn collects_due_overdue_and_logged_time_per_person() {
+        let date = NaiveDate::from_ymd_opt(2024, 5, 10).unwrap();
+
+        let tasks: Vec<Task> = serde_json::from_value(json!([
+            {"content": "Due", "due-date": "20240510", "responsible-party-ids": "1,2", "responsible-party-names": "Ann A.|Bob B."},
+            {"content": "Late", "due-date": "20240501", "responsible-party-ids": "1"},
+            {"content": "Done", "due-date": "20240501", "completed": true, "responsible-party-ids": "1"},
+            {"content": "Later", "due-date": "20240520", "responsible-party-ids": "1"},
+        ]))
+        .unwrap();
+        let entries: Vec<TimeEntry> = serde_json::from_value(json!([
+            {"person-id": "3", "dateUserPerspective": "2024-05-09T23:30:00Z", "date": "2024-05-10T06:30:00Z", "hours": "1", "minutes": "30"},
+            {"person-id": "3", "dateUserPerspective": "2024-05-08T09:00:00Z", "hours": "2", "minutes": "0"},
+            {"person-id": "3", "dateUserPerspective": "2024-05-10T00:30:00Z", "date": "2024-05-09T17:30:00Z", "hours": "4", "minutes": "0"},
+        ]))
+        .unwrap();
+
+        let digests = collect(&tasks, &entries, date);
+        let ids: Vec<_> = digests.iter().map(|d| d.person_id.as_str()).collect();
+        assert_eq!(ids, ["1", "2", "3"]);
+
+        assert_eq!(digests[0].name(), "Ann A.");
+        assert_eq!(digests[0].due.len(), 1);
+        assert_eq!(digests[0].overdue.len(), 1);
+        assert_eq!(digests[1].due.len(), 1);
+        assert_eq!(digests[2].logged_hours(), 1.5);
+    }
+
+    #[test]
+    fn fetches_the_entries_around_yesterday() {
+        let params = entry_params(NaiveDate::from_ymd_opt(2024, 5, 10).unwrap()).unwrap();
+
+        assert_eq!(params["fromdate"], "20240508");
+        assert_eq!(params["todate"], "20240510");
+    }
+}
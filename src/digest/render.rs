@@ -0,0 +1,221 @@
+//! Renders digests as Markdown, Slack's mrkdwn or HTML.
+
+use super::{Digest, EntryLine, TaskLine};
+use crate::reports::DATE_FORMAT;
+
+/// The flavor of plain text markup.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flavor {
+    Markdown,
+    Slack,
+}
+
+impl Flavor {
+    fn heading(self, heading: &str) -> String {
+        match self {
+            Flavor::Markdown => format!("## {}", heading),
+            Flavor::Slack => format!("*{}*", heading),
+        }
+    }
+
+    fn escape(self, text: &str) -> String {
+        match self {
+            Flavor::Markdown => text.to_string(),
+            Flavor::Slack => text
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;"),
+        }
+    }
+}
+
+/// The project and task list of a task, e.g. `Website / Design`.
+fn location(project: &Option<String>, task_list: &Option<String>) -> Option<String> {
+    let parts: Vec<&str> = [project, task_list]
+        .iter()
+        .filter_map(|p| p.as_deref())
+        .collect();
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(" / "))
+    }
+}
+
+fn task_line(task: &TaskLine, overdue: bool) -> String {
+    let mut line = task.task.clone();
+
+    if overdue {
+        line.push_str(&format!(", due {}", task.due.format(DATE_FORMAT)));
+    }
+
+    if let Some(location) = location(&task.project, &task.task_list) {
+        line.push_str(&format!(" ({})", location));
+    }
+
+    line
+}
+
+fn entry_line(entry: &EntryLine) -> String {
+    let what: Vec<&str> = [&entry.project, &entry.task, &entry.description]
+        .iter()
+        .filter_map(|p| p.as_deref())
+        .collect();
+
+    format!("{:.2}h {}", entry.hours, what.join(" / "))
+        .trim()
+        .to_string()
+}
+
+/// The sections of a digest, each with its heading, the message when it's
+/// empty and its lines.
+fn sections(digest: &Digest) -> Vec<(String, &'static str, Vec<String>)> {
+    vec![
+        (
+            format!("Due today ({})", digest.due.len()),
+            "Nothing due today.",
+            digest.due.iter().map(|t| task_line(t, false)).collect(),
+        ),
+        (
+            format!("Overdue ({})", digest.overdue.len()),
+            "Nothing overdue.",
+            digest.overdue.iter().map(|t| task_line(t, true)).collect(),
+        ),
+        (
+            format!("Logged yesterday ({:.2}h)", digest.logged_hours()),
+            "No time logged yesterday.",
+            digest.logged.iter().map(entry_line).collect(),
+        ),
+    ]
+}
+
+pub fn text(digest: &Digest, flavor: Flavor) -> String {
+    let mut text = match flavor {
+        Flavor::Markdown => format!(
+            "# Digest for {}, {}\n",
+            digest.name(),
+            digest.date.format(DATE_FORMAT)
+        ),
+        Flavor::Slack => format!(
+            "Digest for {}, {}\n",
+            flavor.escape(digest.name()),
+            digest.date.format(DATE_FORMAT)
+        ),
+    };
+
+    for (heading, empty, lines) in sections(digest) {
+        text.push('\n');
+        text.push_str(&flavor.heading(&heading));
+        text.push('\n');
+
+        if lines.is_empty() {
+            text.push_str(empty);
+            text.push('\n');
+        }
+
+        for line in lines {
+            text.push_str(&format!("- {}\n", flavor.escape(&line)));
+        }
+    }
+
+    text
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn html(digest: &Digest) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{}</title></head>\n<body>\n<h1>Digest for {}, {}</h1>\n",
+        escape_html(&digest.subject()),
+        escape_html(digest.name()),
+        digest.date.format(DATE_FORMAT)
+    );
+
+    for (heading, empty, lines) in sections(digest) {
+        html.push_str(&format!("<h2>{}</h2>\n", escape_html(&heading)));
+
+        if lines.is_empty() {
+            html.push_str(&format!("<p>{}</p>\n", empty));
+            continue;
+        }
+
+        html.push_str("<ul>\n");
+
+        for line in lines {
+            html.push_str(&format!("<li>{}</li>\n", escape_html(&line)));
+        }
+
+        html.push_str("</ul>\n");
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn digest() -> Digest {
+        let date = NaiveDate::from_ymd_opt(2024, 5, 10).unwrap();
+
+        Digest {
+            person_id: "1".into(),
+            name: Some("Ann <A&B>".into()),
+            date,
+            due: vec![TaskLine {
+                task: "Fix <script> & \"quotes\"".into(),
+                project: Some("Website".into()),
+                task_list: Some("Design".into()),
+                due: date,
+            }],
+            overdue: vec![],
+            logged: vec![EntryLine {
+                project: Some("R&D".into()),
+                task: None,
+                description: Some("a < b".into()),
+                hours: 1.5,
+            }],
+        }
+    }
+
+    #[test]
+    fn escapes_html() {
+        let html = html(&digest());
+
+        assert!(html.contains("<h1>Digest for Ann &lt;A&amp;B&gt;, 2024-05-10</h1>"));
+        assert!(html
+            .contains("<li>Fix &lt;script&gt; &amp; &quot;quotes&quot; (Website / Design)</li>"));
+        assert!(html.contains("<li>1.50h R&amp;D / a &lt; b</li>"));
+        assert!(html.contains("<h2>Overdue (0)</h2>\n<p>Nothing overdue.</p>"));
+        assert!(!html.contains("<script>"));
+    }
+
+    #[test]
+    fn escapes_slack_control_characters() {
+        let text = text(&digest(), Flavor::Slack);
+
+        assert!(text.starts_with("Digest for Ann &lt;A&amp;B&gt;, 2024-05-10\n"));
+        assert!(text.contains(
+            "*Due today (1)*\n- Fix &lt;script&gt; &amp; \"quotes\" (Website / Design)\n"
+        ));
+        assert!(text.contains("*Logged yesterday (1.50h)*\n- 1.50h R&amp;D / a &lt; b\n"));
+    }
+
+    #[test]
+    fn renders_markdown_as_is() {
+        let text = text(&digest(), Flavor::Markdown);
+
+        assert!(text.starts_with("# Digest for Ann <A&B>, 2024-05-10\n"));
+        assert!(text.contains("## Overdue (0)\nNothing overdue.\n"));
+        assert!(text.contains("- 1.50h R&D / a < b\n"));
+    }
+}
@@ -0,0 +1,410 @@
+//! Where digests are delivered: a file or stdout, email over SMTP or a Slack
+//! incoming webhook, chosen with `digest_sink`.
+
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use config::ConfigError;
+use lettre::{
+    message::{Mailbox, MultiPart},
+    transport::smtp::authentication::Credentials,
+    Message, SmtpTransport, Transport,
+};
+use serde_json::json;
+
+use super::{
+    render::{self, Flavor},
+    Digest,
+};
+use crate::{reports::DATE_FORMAT, Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileFormat {
+    Markdown,
+    Html,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SmtpTls {
+    /// Upgrades the connection with STARTTLS, usually on port 587.
+    Starttls,
+    /// Connects over TLS, usually on port 465.
+    Tls,
+    /// Sends in plain text, only meant for local relays.
+    None,
+}
+
+/// The configuration of the sink, read from the `digest_*`, `smtp_*` and
+/// `slack_*` keys.
+#[derive(Debug, Clone)]
+pub enum SinkConfig {
+    /// Writes a file per digest to the `path` directory, or every digest to
+    /// stdout when it's `-`.
+    File { path: String, format: FileFormat },
+    Smtp {
+        host: String,
+        port: u16,
+        tls: SmtpTls,
+        credentials: Option<(String, String)>,
+        from: String,
+        /// The email address of each person, by id.
+        recipients: HashMap<String, String>,
+    },
+    Slack {
+        webhook_url: String,
+        /// The Slack member id of each person whose digest is posted, by id,
+        /// mentioned in their digest.
+        recipients: HashMap<String, String>,
+    },
+}
+
+fn required(config: &config::Config, key: &str) -> std::result::Result<String, ConfigError> {
+    config
+        .get_str(key)
+        .ok()
+        .filter(|v| !v.is_empty())
+        .ok_or_else(|| ConfigError::Message(format!("{} is required by the digest sink", key)))
+}
+
+/// Parses `digest_recipients`, a comma separated list of `person_id=address`
+/// pairs.
+fn recipients(
+    config: &config::Config,
+) -> std::result::Result<HashMap<String, String>, ConfigError> {
+    config
+        .get_str("digest_recipients")?
+        .split(',')
+        .map(str::trim)
+        .filter(|r| !r.is_empty())
+        .map(|recipient| match recipient.split_once('=') {
+            Some((id, address)) if !id.trim().is_empty() && !address.trim().is_empty() => {
+                Ok((id.trim().to_string(), address.trim().to_string()))
+            }
+            _ => Err(ConfigError::Message(format!(
+                "invalid digest_recipients entry {}, expected person_id=address",
+                recipient
+            ))),
+        })
+        .collect()
+}
+
+impl SinkConfig {
+    pub fn from_config(config: &config::Config) -> std::result::Result<Self, ConfigError> {
+        match config.get_str("digest_sink")?.as_str() {
+            "file" => Ok(SinkConfig::File {
+                path: config.get_str("digest_path")?,
+                format: match config.get_str("digest_format")?.as_str() {
+                    "markdown" => FileFormat::Markdown,
+                    "html" => FileFormat::Html,
+                    other => {
+                        return Err(ConfigError::Message(format!(
+                            "unknown digest_format {}, expected markdown or html",
+                            other
+                        )))
+                    }
+                },
+            }),
+            "smtp" => Ok(SinkConfig::Smtp {
+                host: required(config, "smtp_host")?,
+                port: config.get_int("smtp_port")?.clamp(1, u16::MAX as i64) as u16,
+                tls: match config.get_str("smtp_tls")?.as_str() {
+                    "starttls" => SmtpTls::Starttls,
+                    "tls" => SmtpTls::Tls,
+                    "none" => SmtpTls::None,
+                    other => {
+                        return Err(ConfigError::Message(format!(
+                            "unknown smtp_tls {}, expected starttls, tls or none",
+                            other
+                        )))
+                    }
+                },
+                credentials: match (
+                    config.get_str("smtp_username"),
+                    config.get_str("smtp_password"),
+                ) {
+                    (Ok(username), Ok(password)) if !username.is_empty() => {
+                        Some((username, password))
+                    }
+                    _ => None,
+                },
+                from: required(config, "smtp_from")?,
+                recipients: recipients(config)?,
+            }),
+            "slack" => {
+                let webhook_url = required(config, "slack_webhook_url")?;
+                let recipients = recipients(config)?;
+
+                // the webhook posts to one channel, only the listed people's
+                // digests may go there
+                if recipients.is_empty() {
+                    return Err(ConfigError::Message(
+                        "digest_recipients is required by the digest sink".into(),
+                    ));
+                }
+
+                Ok(SinkConfig::Slack {
+                    webhook_url,
+                    recipients,
+                })
+            }
+            other => Err(ConfigError::Message(format!(
+                "unknown digest_sink {}, expected file, smtp or slack",
+                other
+            ))),
+        }
+    }
+
+    /// Whether a person's digest is delivered. Emails and Slack messages are
+    /// only sent to the listed recipients, files are written for everybody.
+    pub fn delivers_to(&self, person_id: &str) -> bool {
+        match self {
+            SinkConfig::File { .. } => true,
+            SinkConfig::Smtp { recipients, .. } | SinkConfig::Slack { recipients, .. } => {
+                recipients.contains_key(person_id)
+            }
+        }
+    }
+
+    pub fn build(&self, client: Arc<surf::Client>) -> Result<Box<dyn Sink>> {
+        Ok(match self.clone() {
+            SinkConfig::File { path, format } => Box::new(FileSink { path, format }),
+            SinkConfig::Smtp {
+                host,
+                port,
+                tls,
+                credentials,
+                from,
+                recipients,
+            } => {
+                let builder = match tls {
+                    SmtpTls::Starttls => SmtpTransport::starttls_relay(&host),
+                    SmtpTls::Tls => SmtpTransport::relay(&host),
+                    SmtpTls::None => Ok(SmtpTransport::builder_dangerous(&host)),
+                }
+                .map_err(|e| Error::DeliveryError(e.to_string()))?
+                .port(port);
+
+                let builder = match credentials {
+                    Some((username, password)) => {
+                        builder.credentials(Credentials::new(username, password))
+                    }
+                    None => builder,
+                };
+
+                Box::new(SmtpSink {
+                    transport: builder.build(),
+                    from: from
+                        .parse()
+                        .map_err(|e| Error::DeliveryError(format!("invalid smtp_from: {}", e)))?,
+                    recipients,
+                })
+            }
+            SinkConfig::Slack {
+                webhook_url,
+                recipients,
+            } => Box::new(SlackSink {
+                client,
+                webhook_url,
+                recipients,
+            }),
+        })
+    }
+}
+
+#[tide::utils::async_trait]
+pub trait Sink: Send + Sync {
+    async fn deliver(&self, digest: &Digest) -> Result<()>;
+}
+
+struct FileSink {
+    path: String,
+    format: FileFormat,
+}
+
+#[tide::utils::async_trait]
+impl Sink for FileSink {
+    async fn deliver(&self, digest: &Digest) -> Result<()> {
+        let (content, extension) = match self.format {
+            FileFormat::Markdown => (render::text(digest, Flavor::Markdown), "md"),
+            FileFormat::Html => (render::html(digest), "html"),
+        };
+
+        if self.path == "-" {
+            println!("{}", content);
+            return Ok(());
+        }
+
+        async_std::fs::create_dir_all(&self.path).await?;
+
+        let file = PathBuf::from(&self.path).join(format!(
+            "{}-{}.{}",
+            digest.date.format(DATE_FORMAT),
+            digest.person_id,
+            extension
+        ));
+
+        async_std::fs::write(file, content).await?;
+
+        Ok(())
+    }
+}
+
+struct SmtpSink {
+    transport: SmtpTransport,
+    from: Mailbox,
+    recipients: HashMap<String, String>,
+}
+
+#[tide::utils::async_trait]
+impl Sink for SmtpSink {
+    async fn deliver(&self, digest: &Digest) -> Result<()> {
+        let address = self
+            .recipients
+            .get(&digest.person_id)
+            .ok_or_else(|| Error::DeliveryError("no email address".into()))?;
+
+        let to = Mailbox::new(
+            digest.name.clone(),
+            address
+                .parse()
+                .map_err(|e| Error::DeliveryError(format!("invalid address {}: {}", address, e)))?,
+        );
+
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(digest.subject())
+            .multipart(MultiPart::alternative_plain_html(
+                render::text(digest, Flavor::Markdown),
+                render::html(digest),
+            ))
+            .map_err(|e| Error::DeliveryError(e.to_string()))?;
+
+        // the transport blocks, keep it off the executor's threads
+        let transport = self.transport.clone();
+        async_std::task::spawn_blocking(move || transport.send(&message))
+            .await
+            .map_err(|e| Error::DeliveryError(e.to_string()))?;
+
+        Ok(())
+    }
+}
+
+struct SlackSink {
+    client: Arc<surf::Client>,
+    webhook_url: String,
+    recipients: HashMap<String, String>,
+}
+
+#[tide::utils::async_trait]
+impl Sink for SlackSink {
+    async fn deliver(&self, digest: &Digest) -> Result<()> {
+        let mut text = render::text(digest, Flavor::Slack);
+
+        if let Some(member) = self.recipients.get(&digest.person_id) {
+            text = format!("<@{}> {}", member, text);
+        }
+
+        let body = surf::Body::from_json(&json!({ "text": text }))?;
+        let response = self.client.post(&self.webhook_url).body(body).await?;
+
+        if !response.status().is_success() {
+            return Err(Error::DeliveryError(format!(
+                "Slack returned {}",
+                response.status()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(settings: &[(&str, &str)]) -> std::result::Result<SinkConfig, ConfigError> {
+        let mut config = crate::default_config().unwrap();
+
+        for (key, value) in settings {
+            config.set(key, *value).unwrap();
+        }
+
+        SinkConfig::from_config(&config)
+    }
+
+    #[test]
+    fn defaults_to_markdown_on_stdout() {
+        match config(&[]).unwrap() {
+            SinkConfig::File { path, format } => {
+                assert_eq!(path, "-");
+                assert_eq!(format, FileFormat::Markdown);
+            }
+            other => panic!("unexpected sink {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_smtp_settings_and_recipients() {
+        let sink = config(&[
+            ("digest_sink", "smtp"),
+            ("smtp_host", "mail.example.com"),
+            ("smtp_from", "digest@example.com"),
+            ("smtp_tls", "tls"),
+            (
+                "digest_recipients",
+                " 1=ann@example.com, 2=bob@example.com ,",
+            ),
+        ])
+        .unwrap();
+
+        match &sink {
+            SinkConfig::Smtp {
+                host,
+                port,
+                tls,
+                credentials,
+                recipients,
+                ..
+            } => {
+                assert_eq!(host, "mail.example.com");
+                assert_eq!(*port, 587);
+                assert_eq!(*tls, SmtpTls::Tls);
+                assert_eq!(*credentials, None);
+                assert_eq!(recipients["2"], "bob@example.com");
+            }
+            other => panic!("unexpected sink {:?}", other),
+        }
+
+        assert!(sink.delivers_to("1"));
+        assert!(!sink.delivers_to("3"));
+    }
+
+    #[test]
+    fn slack_requires_recipients() {
+        let slack = [
+            ("digest_sink", "slack"),
+            ("slack_webhook_url", "https://hooks.slack.com/services/x"),
+        ];
+
+        assert!(config(&slack).is_err());
+
+        let sink = config(&[slack[0], slack[1], ("digest_recipients", "1=U123")]).unwrap();
+        assert!(sink.delivers_to("1"));
+        assert!(!sink.delivers_to("2"));
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        assert!(config(&[("digest_format", "pdf")]).is_err());
+        assert!(config(&[("digest_sink", "fax")]).is_err());
+        // smtp_host and smtp_from are required
+        assert!(config(&[("digest_sink", "smtp")]).is_err());
+        assert!(config(&[
+            ("digest_sink", "smtp"),
+            ("smtp_host", "mail.example.com"),
+            ("smtp_from", "digest@example.com"),
+            ("digest_recipients", "ann@example.com"),
+        ])
+        .is_err());
+    }
+}
@@ -0,0 +1,348 @@
+//! Alternative representations of the collection routes, selected with the
+//! `format` query parameter or the `Accept` header.
+
+use teamwork_schema::{
+    meta::{self, FieldKind, Schema, SchemaMeta},
+    TimeEntry,
+};
+use tide::{Request, Response};
+
+use crate::xlsx::{self, Sheets};
+
+/// The query parameter selecting the format.
+pub const FORMAT_PARAM: &str = "format";
+
+/// Query parameters consumed by the proxy rather than forwarded to Teamwork.
+pub const PROXY_PARAMS: &[&str] = &[FORMAT_PARAM, xlsx::SHEETS_PARAM];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Csv,
+    Xlsx(Sheets),
+}
+
+impl Format {
+    /// The format requested by `?format=`, falling back to the `Accept`
+    /// header and then JSON.
+    pub fn from_request<S>(req: &Request<S>) -> Result<Self, String> {
+        let param = |name: &str| {
+            req.url()
+                .query_pairs()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.to_ascii_lowercase())
+        };
+
+        let xlsx = || match param(xlsx::SHEETS_PARAM) {
+            Some(sheets) => Sheets::parse(&sheets)
+                .map(Format::Xlsx)
+                .ok_or_else(|| format!("unknown sheets {}, expected project or person", sheets)),
+            None => Ok(Format::Xlsx(Sheets::Project)),
+        };
+
+        match param(FORMAT_PARAM).as_deref() {
+            Some("json") => return Ok(Format::Json),
+            Some("csv") => return Ok(Format::Csv),
+            Some("xlsx") => return xlsx(),
+            Some(other) => return Err(format!("unknown format {}", other)),
+            None => {}
+        }
+
+        let accept = req
+            .header("Accept")
+            .map(|h| h.as_str().to_ascii_lowercase())
+            .unwrap_or_default();
+
+        if accept.contains("text/csv") {
+            Ok(Format::Csv)
+        } else if accept.contains(xlsx::MIME) {
+            xlsx()
+        } else {
+            Ok(Format::Json)
+        }
+    }
+}
+
+/// A column of the CSV, the path of the value within the normalized record.
+#[derive(Debug, Clone)]
+pub struct Column {
+    path: Vec<&'static str>,
+}
+
+impl Column {
+    pub fn header(&self) -> String {
+        self.path.join(".")
+    }
+
+    fn value<'a>(&self, record: &'a serde_json::Value) -> Option<&'a serde_json::Value> {
+        self.path
+            .iter()
+            .try_fold(record, |value, segment| value.get(segment))
+    }
+}
+
+/// The columns of a schema in the order of its fields, with nested structs
+/// flattened into a column per field, e.g. `board_column.name`.
+pub fn columns(schema: &SchemaMeta) -> Vec<Column> {
+    fn push(schema: &SchemaMeta, prefix: &[&'static str], columns: &mut Vec<Column>) {
+        for field in schema.fields {
+            let mut path = prefix.to_vec();
+            path.push(field.name);
+
+            match field.kind {
+                FieldKind::Object(name) => match meta::find(name) {
+                    Some(nested) => push(nested, &path, columns),
+                    None => columns.push(Column { path }),
+                },
+                _ => columns.push(Column { path }),
+            }
+        }
+    }
+
+    let mut columns = vec![];
+    push(schema, &[], &mut columns);
+    columns
+}
+
+/// Text starting with these is evaluated as a formula by spreadsheets.
+const FORMULA_PREFIXES: &[char] = &['=', '+', '-', '@', '\t', '\r'];
+
+/// Formats a value for a cell. Arrays are joined, using the `name` of objects
+/// such as tags. Text that a spreadsheet would run as a formula is prefixed
+/// with `'` so it's shown as entered.
+fn cell(value: Option<&serde_json::Value>) -> String {
+    use serde_json::Value;
+
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) if s.starts_with(FORMULA_PREFIXES) => format!("'{}", s),
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Array(items)) => items
+            .iter()
+            .map(|item| match item.get("name") {
+                Some(name) => cell(Some(name)),
+                None => cell(Some(item)),
+            })
+            .collect::<Vec<_>>()
+            .join(", "),
+        Some(value) => value.to_string(),
+    }
+}
+
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Renders the normalized records as CSV with a header row.
+pub fn to_csv(schema: &SchemaMeta, records: &[serde_json::Value]) -> String {
+    let columns = columns(schema);
+
+    let mut csv = String::new();
+
+    let mut push_row = |row: Vec<String>| {
+        let row = row.iter().map(|f| escape(f)).collect::<Vec<_>>();
+        csv.push_str(&row.join(","));
+        csv.push_str("\r\n");
+    };
+
+    push_row(columns.iter().map(Column::header).collect());
+
+    for record in records {
+        push_row(columns.iter().map(|c| cell(c.value(record))).collect());
+    }
+
+    csv
+}
+
+/// Moves the pagination of the `ApiResponse` into headers, since exports only
+/// hold the records.
+fn paginate(res: &mut Response, body: &serde_json::Value) {
+    for (header, key) in &[("X-Page", "page"), ("X-Pages", "total_pages")] {
+        if let Some(value) = body["meta"].get(key) {
+            res.insert_header(*header, value.to_string());
+        }
+    }
+}
+
+fn records(body: &serde_json::Value) -> &[serde_json::Value] {
+    body["data"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+/// Renders the `ApiResponse` serialized in `body` as CSV.
+pub fn csv_response(schema: &SchemaMeta, body: &[u8]) -> tide::Result<Response> {
+    let body: serde_json::Value = serde_json::from_slice(body)?;
+
+    let mut res = Response::builder(200)
+        .body(to_csv(schema, records(&body)))
+        .content_type("text/csv; charset=utf-8")
+        .build();
+
+    paginate(&mut res, &body);
+
+    Ok(res)
+}
+
+/// Renders the time entries of the `ApiResponse` serialized in `body` as an
+/// Excel workbook.
+pub fn xlsx_response(sheets: Sheets, body: &[u8]) -> tide::Result<Response> {
+    let body: serde_json::Value = serde_json::from_slice(body)?;
+
+    let entries = records(&body)
+        .iter()
+        .map(|record| serde_json::from_value(TimeEntry::META.denormalize(record)))
+        .collect::<Result<Vec<TimeEntry>, _>>()?;
+
+    let workbook = xlsx::time_entries(&entries, sheets)
+        .map_err(|e| tide::Error::from_str(500, e.to_string()))?;
+
+    let mut res = Response::builder(200)
+        .body(workbook)
+        .content_type(xlsx::MIME)
+        .header(
+            "Content-Disposition",
+            "attachment; filename=\"time-entries.xlsx\"",
+        )
+        .build();
+
+    paginate(&mut res, &body);
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use teamwork_schema::meta::FieldMeta;
+
+    use super::*;
+
+    const fn field(name: &'static str, kind: FieldKind) -> FieldMeta {
+        FieldMeta {
+            name,
+            source: name,
+            kind,
+            required: false,
+        }
+    }
+
+    const TASK: SchemaMeta = SchemaMeta {
+        name: "Task",
+        fields: &[
+            field("id", FieldKind::Integer),
+            field("content", FieldKind::String),
+            field("board_column", FieldKind::Object("BoardColumn")),
+            field("tags", FieldKind::Array(&FieldKind::Any)),
+        ],
+        skipped: &[],
+        extra: false,
+    };
+
+    #[test]
+    fn flattens_nested_structs_into_columns() {
+        let headers: Vec<String> = columns(&TASK).iter().map(Column::header).collect();
+        let nested: Vec<&str> = meta::find("BoardColumn")
+            .unwrap()
+            .fields
+            .iter()
+            .map(|f| f.name)
+            .collect();
+
+        assert_eq!(headers[..2], ["id", "content"]);
+        assert_eq!(headers.last().unwrap(), "tags");
+        assert_eq!(
+            headers[2..headers.len() - 1],
+            nested
+                .iter()
+                .map(|name| format!("board_column.{}", name))
+                .collect::<Vec<_>>()[..]
+        );
+        assert!(headers.contains(&"board_column.name".to_string()));
+    }
+
+    #[test]
+    fn escapes_fields() {
+        assert_eq!(escape("plain"), "plain");
+        assert_eq!(escape("a, b"), "\"a, b\"");
+        assert_eq!(escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(escape("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn neutralizes_formulas() {
+        for formula in &["=SUM(A1:A2)", "+1", "-1+1", "@cmd", "\t=1"] {
+            assert_eq!(cell(Some(&json!(formula))), format!("'{}", formula));
+        }
+
+        assert_eq!(cell(Some(&json!(-1.5))), "-1.5");
+        assert_eq!(
+            cell(Some(&json!([{ "name": "=x" }, { "name": "y" }]))),
+            "'=x, y"
+        );
+    }
+
+    #[test]
+    fn renders_records_under_the_header() {
+        const FLAT: SchemaMeta = SchemaMeta {
+            name: "Flat",
+            fields: &[
+                field("id", FieldKind::Integer),
+                field("content", FieldKind::String),
+                field("tags", FieldKind::Array(&FieldKind::Any)),
+            ],
+            skipped: &[],
+            extra: false,
+        };
+
+        let records = [
+            json!({
+                "id": 1,
+                "content": "Write, \"docs\"",
+                "tags": [{ "name": "docs" }, { "name": "ops" }],
+            }),
+            json!({ "id": 2, "content": "=HYPERLINK(\"x\")", "tags": null }),
+        ];
+
+        assert_eq!(
+            to_csv(&FLAT, &records),
+            "id,content,tags\r\n\
+             1,\"Write, \"\"docs\"\"\",\"docs, ops\"\r\n\
+             2,\"'=HYPERLINK(\"\"x\"\")\",\r\n"
+        );
+    }
+
+    #[test]
+    fn exports_the_normalized_time_entries_as_a_workbook() {
+        let entry: TimeEntry = serde_json::from_value(json!({
+            "id": "1",
+            "person-first-name": "Holly",
+            "project-name": "Website",
+            "dateUserPerspective": "2024-03-02T00:30:00Z",
+            "hours": "1",
+            "minutes": "",
+            "tags": [{ "id": 2, "name": "docs" }],
+            "unknown": true,
+        }))
+        .unwrap();
+
+        // the normalized records are read back into time entries
+        let record = serde_json::to_value(&entry).unwrap();
+        let read: TimeEntry = serde_json::from_value(TimeEntry::META.denormalize(&record)).unwrap();
+        let mut expected = record.clone();
+        assert_eq!(expected["extra"], json!({ "unknown": true }));
+        expected.as_object_mut().unwrap().remove("extra");
+        assert_eq!(serde_json::to_value(&read).unwrap(), expected);
+
+        let body = json!({ "data": [record], "meta": { "page": 1, "total_pages": 1 } });
+        let res = xlsx_response(Sheets::Person, &serde_json::to_vec(&body).unwrap()).unwrap();
+
+        assert_eq!(res.content_type().unwrap().essence(), xlsx::MIME);
+        assert_eq!(res["X-Pages"], "1");
+    }
+}
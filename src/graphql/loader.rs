@@ -0,0 +1,190 @@
+//! Batches and dedupes the lookups made while resolving a query, so a field
+//! like `task_list` fetches each task list once however many tasks refer to
+//! it. Lookups are made through the collection routes, narrowed with Teamwork's
+//! id filters, and the records are matched to the keys here so every batch
+//! costs one listing. Time entries can't be filtered by task, so those are
+//! listed per task, concurrently.
+
+use std::collections::HashMap;
+
+use async_graphql::dataloader::Loader;
+use futures::{stream, StreamExt, TryStreamExt};
+use serde_json::Value;
+use teamwork_schema::{Task, TaskList, TimeEntry};
+
+use super::graphql_error;
+use crate::{
+    reports::{text, to_values},
+    upstream::{Resource, Upstream},
+    Error,
+};
+
+/// The most lookups of one batch made at once, when a batch takes a request
+/// per key.
+const MAX_CONCURRENT: usize = 10;
+
+/// A task, by id.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TaskId(pub String);
+
+/// A task list, by id.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TaskListId(pub String);
+
+/// The tasks of a task list, by the task list's id.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TaskListTasks(pub String);
+
+/// The time logged on a task, by the task's id.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TaskTimeEntries(pub String);
+
+/// Errors are shared by every lookup in the batch that failed.
+pub type LoadError = async_graphql::Error;
+
+pub struct TeamworkLoader {
+    upstream: Upstream<'static>,
+}
+
+impl TeamworkLoader {
+    pub fn new(upstream: Upstream<'static>) -> Self {
+        TeamworkLoader { upstream }
+    }
+
+    pub fn upstream(&self) -> &Upstream<'static> {
+        &self.upstream
+    }
+
+    async fn fetch<R: Resource>(&self, params: &[(&str, String)]) -> Result<Vec<Value>, LoadError> {
+        self.fetch_at::<R>(R::ROUTE, params).await
+    }
+
+    async fn fetch_at<R: Resource>(
+        &self,
+        teamwork_route: &str,
+        params: &[(&str, String)],
+    ) -> Result<Vec<Value>, LoadError> {
+        let params: HashMap<String, Value> = params
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::from(v.as_str())))
+            .collect();
+
+        let records = self
+            .upstream
+            .get_all_at::<R>(teamwork_route, &params)
+            .await
+            .map_err(|e| graphql_error(&e))?;
+
+        to_values(&records).map_err(|e| graphql_error(&Error::SchemaError(e.to_string())))
+    }
+}
+
+fn ids<'a>(keys: impl Iterator<Item = &'a String>) -> String {
+    keys.map(String::as_str).collect::<Vec<_>>().join(",")
+}
+
+/// The records whose `field` is one of `keys`, by key.
+fn by_key<K, F>(records: Vec<Value>, field: &str, keys: &[K], key: F) -> HashMap<K, Value>
+where
+    K: Clone + Eq + std::hash::Hash,
+    F: Fn(String) -> K,
+{
+    records
+        .into_iter()
+        .filter_map(|record| Some((key(text(&record, field)?), record)))
+        .filter(|(key, _)| keys.contains(key))
+        .collect()
+}
+
+/// The records grouped by `field`, with an empty group for keys without any.
+fn grouped<K, F>(records: Vec<Value>, field: &str, keys: &[K], key: F) -> HashMap<K, Vec<Value>>
+where
+    K: Clone + Eq + std::hash::Hash,
+    F: Fn(String) -> K,
+{
+    let mut groups: HashMap<K, Vec<Value>> = keys.iter().map(|k| (k.clone(), vec![])).collect();
+
+    for record in records {
+        if let Some(group) = text(&record, field).and_then(|k| groups.get_mut(&key(k))) {
+            group.push(record);
+        }
+    }
+
+    groups
+}
+
+impl Loader<TaskId> for TeamworkLoader {
+    type Value = Value;
+    type Error = LoadError;
+
+    async fn load(&self, keys: &[TaskId]) -> Result<HashMap<TaskId, Value>, LoadError> {
+        let records = self
+            .fetch::<Task>(&[
+                ("taskIds", ids(keys.iter().map(|TaskId(id)| id))),
+                ("includeCompletedTasks", "true".into()),
+            ])
+            .await?;
+
+        Ok(by_key(records, "id", keys, TaskId))
+    }
+}
+
+impl Loader<TaskListId> for TeamworkLoader {
+    type Value = Value;
+    type Error = LoadError;
+
+    async fn load(&self, keys: &[TaskListId]) -> Result<HashMap<TaskListId, Value>, LoadError> {
+        let records = self
+            .fetch::<TaskList>(&[("tasklistIds", ids(keys.iter().map(|TaskListId(id)| id)))])
+            .await?;
+
+        Ok(by_key(records, "id", keys, TaskListId))
+    }
+}
+
+impl Loader<TaskListTasks> for TeamworkLoader {
+    type Value = Vec<Value>;
+    type Error = LoadError;
+
+    async fn load(
+        &self,
+        keys: &[TaskListTasks],
+    ) -> Result<HashMap<TaskListTasks, Vec<Value>>, LoadError> {
+        let records = self
+            .fetch::<Task>(&[
+                ("tasklistIds", ids(keys.iter().map(|TaskListTasks(id)| id))),
+                ("includeCompletedTasks", "true".into()),
+            ])
+            .await?;
+
+        Ok(grouped(records, "todo_list_id", keys, TaskListTasks))
+    }
+}
+
+impl Loader<TaskTimeEntries> for TeamworkLoader {
+    type Value = Vec<Value>;
+    type Error = LoadError;
+
+    async fn load(
+        &self,
+        keys: &[TaskTimeEntries],
+    ) -> Result<HashMap<TaskTimeEntries, Vec<Value>>, LoadError> {
+        let entries = keys.iter().cloned().map(|TaskTimeEntries(id)| async move {
+            // the id is part of the route, anything but a number can't be
+            // a task
+            let records = if !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()) {
+                let route = format!("tasks/{}/time_entries.json", id);
+                self.fetch_at::<TimeEntry>(&route, &[]).await?
+            } else {
+                vec![]
+            };
+
+            Ok::<_, LoadError>((TaskTimeEntries(id), records))
+        });
+
+        stream::iter(entries)
+            .buffer_unordered(MAX_CONCURRENT)
+            .try_collect()
+            .await
+    }
+}
@@ -0,0 +1,412 @@
+//! A GraphQL endpoint over the normalized schemas. The types are built from the
+//! metadata generated alongside the schemas, so they follow the REST routes'
+//! fields, and the relations between records are resolved through batched
+//! lookups, e.g.
+//!
+//! ```graphql
+//! { task_list(id: "1") { name tasks { content time_entries { hours minutes } } } }
+//! ```
+//!
+//! Queries are `POST`ed as JSON, while `GET` returns the schema.
+
+use std::collections::HashMap;
+
+use async_graphql::{
+    dataloader::{DataLoader, HashMapCache, Loader},
+    dynamic::{
+        Field, FieldFuture, FieldValue, InputValue, Object, ResolverContext, Scalar, Schema,
+        TypeRef,
+    },
+    ErrorExtensions,
+};
+use serde_json::Value;
+use teamwork_schema::{
+    meta::{FieldKind, Schema as _, SchemaMeta},
+    Task, TaskList, TimeEntry, SCHEMAS,
+};
+use tide::{http::mime, Body, Request, Response};
+
+use self::loader::{LoadError, TaskId, TaskListId, TaskListTasks, TaskTimeEntries, TeamworkLoader};
+use crate::{
+    reports::{text, to_values},
+    upstream::{Resource, Upstream},
+    Error, Query, State,
+};
+
+mod loader;
+
+/// Holds the fields typed `any` in the schemas.
+const JSON: &str = "JSON";
+
+/// The deepest a query may nest, which bounds the lookups it can make.
+const MAX_DEPTH: usize = 10;
+
+/// The most keys fetched in one lookup, which keeps the id filters sent to
+/// Teamwork to a sensible length.
+const MAX_BATCH: usize = 100;
+
+lazy_static::lazy_static! {
+    static ref SCHEMA: Schema = schema().expect("The GraphQL schema should be valid");
+}
+
+type Loaders = DataLoader<TeamworkLoader, HashMapCache>;
+
+fn graphql_error(e: &Error) -> async_graphql::Error {
+    let code = e.code();
+    async_graphql::Error::new(e.to_string()).extend_with(|_, ext| ext.set("code", code))
+}
+
+fn loaders<'a>(ctx: &ResolverContext<'a>) -> async_graphql::Result<&'a Loaders> {
+    ctx.data::<Loaders>()
+}
+
+fn type_ref(kind: FieldKind) -> TypeRef {
+    match kind {
+        FieldKind::String | FieldKind::StringOrNumber => TypeRef::named(TypeRef::STRING),
+        FieldKind::Integer => TypeRef::named(TypeRef::INT),
+        FieldKind::Float => TypeRef::named(TypeRef::FLOAT),
+        FieldKind::Bool => TypeRef::named(TypeRef::BOOLEAN),
+        FieldKind::Object(name) => TypeRef::named(name),
+        FieldKind::Array(inner) => TypeRef::List(Box::new(type_ref(*inner))),
+        FieldKind::Any => TypeRef::named(JSON),
+    }
+}
+
+/// The value of a field, coerced to its GraphQL type since the lenient
+/// schemas let through values of other types.
+fn field_value(kind: FieldKind, value: &Value) -> Option<FieldValue<'static>> {
+    use async_graphql::Value as GqlValue;
+
+    let value = match (kind, value) {
+        (_, Value::Null) => return None,
+        (FieldKind::Object(_), Value::Object(_)) => {
+            return Some(FieldValue::owned_any(value.clone()))
+        }
+        (FieldKind::Array(inner), Value::Array(items)) => {
+            return Some(FieldValue::list(
+                items
+                    .iter()
+                    .map(|item| field_value(*inner, item).unwrap_or(FieldValue::NULL)),
+            ))
+        }
+        (FieldKind::String | FieldKind::StringOrNumber, Value::String(s)) => {
+            GqlValue::from(s.as_str())
+        }
+        (FieldKind::String | FieldKind::StringOrNumber, Value::Number(n)) => {
+            GqlValue::from(n.to_string())
+        }
+        (FieldKind::Integer, _) => match value {
+            Value::Number(n) => GqlValue::from(n.as_i64()?),
+            Value::String(s) => GqlValue::from(s.trim().parse::<i64>().ok()?),
+            _ => return None,
+        },
+        (FieldKind::Float, _) => match value {
+            Value::Number(n) => GqlValue::from(n.as_f64()?),
+            Value::String(s) => GqlValue::from(s.trim().parse::<f64>().ok()?),
+            _ => return None,
+        },
+        (FieldKind::Bool, Value::Bool(b)) => GqlValue::from(*b),
+        (FieldKind::Any, _) => GqlValue::from_json(value.clone()).ok()?,
+        _ => return None,
+    };
+
+    Some(FieldValue::value(value))
+}
+
+/// The type generated for a schema, with a field per normalized field.
+fn object(meta: &'static SchemaMeta) -> Object {
+    meta.fields
+        .iter()
+        .fold(Object::new(meta.name), |object, field| {
+            let ty = if field.required {
+                TypeRef::NonNull(Box::new(type_ref(field.kind)))
+            } else {
+                type_ref(field.kind)
+            };
+
+            object.field(Field::new(field.name, ty, move |ctx| {
+                FieldFuture::new(async move {
+                    let record = ctx.parent_value.try_downcast_ref::<Value>()?;
+                    Ok(record
+                        .get(field.name)
+                        .and_then(|value| field_value(field.kind, value)))
+                })
+            }))
+        })
+}
+
+/// A field resolving the record a record refers to with `field`.
+fn one<K>(name: &str, meta: SchemaMeta, field: &'static str, key: fn(String) -> K) -> Field
+where
+    K: Send + Sync + std::hash::Hash + Eq + Clone + 'static,
+    TeamworkLoader: Loader<K, Value = Value, Error = LoadError>,
+{
+    Field::new(name, TypeRef::named(meta.name), move |ctx| {
+        FieldFuture::new(async move {
+            let id = match text(ctx.parent_value.try_downcast_ref::<Value>()?, field) {
+                Some(id) => id,
+                None => return Ok(None),
+            };
+
+            let record = loaders(&ctx)?.load_one(key(id)).await?;
+
+            Ok(record.map(FieldValue::owned_any))
+        })
+    })
+}
+
+/// A root field looking a record up by its `id`.
+fn lookup<K>(name: &str, meta: SchemaMeta, key: fn(String) -> K) -> Field
+where
+    K: Send + Sync + std::hash::Hash + Eq + Clone + 'static,
+    TeamworkLoader: Loader<K, Value = Value, Error = LoadError>,
+{
+    Field::new(name, TypeRef::named(meta.name), move |ctx| {
+        FieldFuture::new(async move {
+            let id = ctx.args.try_get("id")?.string()?.to_string();
+
+            let record = loaders(&ctx)?.load_one(key(id)).await?;
+
+            Ok(record.map(FieldValue::owned_any))
+        })
+    })
+    .argument(InputValue::new("id", TypeRef::named_nn(TypeRef::ID)))
+}
+
+/// A field resolving the records referring to a record by its id.
+fn many<K>(name: &str, meta: SchemaMeta, key: fn(String) -> K) -> Field
+where
+    K: Send + Sync + std::hash::Hash + Eq + Clone + 'static,
+    TeamworkLoader: Loader<K, Value = Vec<Value>, Error = LoadError>,
+{
+    Field::new(name, TypeRef::named_nn_list_nn(meta.name), move |ctx| {
+        FieldFuture::new(async move {
+            let id = match text(ctx.parent_value.try_downcast_ref::<Value>()?, "id") {
+                Some(id) => id,
+                None => return Ok(None),
+            };
+
+            let records = loaders(&ctx)?.load_one(key(id)).await?.unwrap_or_default();
+
+            Ok(Some(FieldValue::list(
+                records.into_iter().map(FieldValue::owned_any),
+            )))
+        })
+    })
+}
+
+/// A root field listing a page of a collection, with the Teamwork params the
+/// REST routes forward given as `params`.
+fn collection<R: Resource>(name: &str) -> Field {
+    Field::new(name, TypeRef::named_nn_list_nn(R::META.name), |ctx| {
+        FieldFuture::new(async move {
+            let page = match ctx.args.get("page") {
+                Some(page) => page.u64()? as usize,
+                None => 1,
+            };
+
+            let per_page = match ctx.args.get("page_size") {
+                Some(size) => Some(size.u64()? as usize),
+                None => None,
+            };
+
+            let other = match ctx.args.get("params") {
+                Some(params) => params.deserialize::<HashMap<String, Value>>()?,
+                None => HashMap::new(),
+            };
+
+            let query = Query {
+                page,
+                per_page,
+                other,
+            };
+
+            let upstream = loaders(&ctx)?.loader().upstream();
+            let (records, _) = upstream
+                .get_page::<R>(&query)
+                .await
+                .map_err(|e| graphql_error(&e))?;
+            let records = to_values(&records)?;
+
+            Ok(Some(FieldValue::list(
+                records.into_iter().map(FieldValue::owned_any),
+            )))
+        })
+    })
+    .argument(InputValue::new("page", TypeRef::named(TypeRef::INT)))
+    .argument(InputValue::new("page_size", TypeRef::named(TypeRef::INT)))
+    .argument(InputValue::new("params", TypeRef::named(JSON)))
+}
+
+fn schema() -> Result<Schema, async_graphql::dynamic::SchemaError> {
+    let query = Object::new("Query")
+        .field(collection::<Task>("tasks"))
+        .field(collection::<TaskList>("task_lists"))
+        .field(collection::<TimeEntry>("time_entries"))
+        .field(lookup("task", Task::META, TaskId))
+        .field(lookup("task_list", TaskList::META, TaskListId));
+
+    let mut builder = Schema::build("Query", None, None)
+        .register(query)
+        .register(Scalar::new(JSON))
+        .limit_depth(MAX_DEPTH);
+
+    for meta in SCHEMAS {
+        let object = object(meta);
+
+        let object = match meta.name {
+            name if name == Task::META.name => object
+                .field(one("task_list", TaskList::META, "todo_list_id", TaskListId))
+                .field(many("time_entries", TimeEntry::META, TaskTimeEntries)),
+            name if name == TaskList::META.name => {
+                object.field(many("tasks", Task::META, TaskListTasks))
+            }
+            name if name == TimeEntry::META.name => object
+                .field(one("task", Task::META, "todo_item_id", TaskId))
+                .field(one("task_list", TaskList::META, "todo_list_id", TaskListId)),
+            _ => object,
+        };
+
+        builder = builder.register(object);
+    }
+
+    builder.finish()
+}
+
+/// Executes a `POST`ed query, or returns the schema for a `GET`.
+pub async fn handler(mut req: Request<State>) -> tide::Result {
+    if req.method() == tide::http::Method::Get {
+        return Ok(Response::builder(200)
+            .body(SCHEMA.sdl())
+            .content_type(mime::PLAIN)
+            .build());
+    }
+
+    let request: async_graphql::Request = req
+        .body_json()
+        .await
+        .map_err(|e| Error::QueryError(e.to_string()))?;
+
+    let upstream = Upstream::new(&req)?.into_owned();
+
+    let loaders = DataLoader::with_cache(
+        TeamworkLoader::new(upstream),
+        async_std::task::spawn,
+        HashMapCache::default(),
+    )
+    .max_batch_size(MAX_BATCH);
+
+    let response = SCHEMA.execute(request.data(loaders)).await;
+
+    Ok(Response::builder(200)
+        .body(Body::from_json(&response)?)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::json;
+    use tide::listener::Listener;
+
+    use super::*;
+
+    /// A stand-in Teamwork serving a task list with two tasks, recording the
+    /// routes requested.
+    async fn teamwork() -> (String, Arc<Mutex<Vec<String>>>) {
+        let requested = Arc::new(Mutex::new(vec![]));
+        let mut app = tide::with_state(requested.clone());
+
+        app.at("*")
+            .get(|req: Request<Arc<Mutex<Vec<String>>>>| async move {
+                let route = req.url().path().trim_start_matches('/').to_string();
+                req.state().lock().unwrap().push(route.clone());
+
+                let body = match route.as_str() {
+                    "tasklists.json" => json!({"tasklists": [{"id": "1", "name": "Launch"}]}),
+                    "tasks.json" => json!({"todo-items": [
+                        {"id": 1, "content": "Design", "todo-list-id": 1},
+                        {"id": 2, "content": "Build", "todo-list-id": 1},
+                    ]}),
+                    "tasks/1/time_entries.json" => {
+                        json!({"time-entries": [{"id": "10", "todo-item-id": "1", "hours": "2"}]})
+                    }
+                    _ => json!({"time-entries": []}),
+                };
+
+                Ok(Response::builder(200)
+                    .header("X-Pages", "1")
+                    .body(Body::from_json(&body)?)
+                    .build())
+            });
+
+        let mut listener = tide::listener::ToListener::to_listener("127.0.0.1:0").unwrap();
+        tide::listener::Listener::bind(&mut listener, app)
+            .await
+            .unwrap();
+        let endpoint = listener.info()[0].connection().to_string();
+
+        async_std::task::spawn(async move { listener.accept().await });
+
+        (endpoint, requested)
+    }
+
+    #[test]
+    fn resolves_relations_fetching_each_key_once() {
+        async_std::task::block_on(async {
+            let (endpoint, requested) = teamwork().await;
+
+            let state = crate::test_state(&[("teamwork_url", &endpoint), ("api_key", "key")]);
+            let loaders = DataLoader::with_cache(
+                TeamworkLoader::new(Upstream::with_api_key(&state).unwrap().into_owned()),
+                async_std::task::spawn,
+                HashMapCache::default(),
+            );
+
+            let response = SCHEMA
+                .execute(
+                    async_graphql::Request::new(
+                        r#"{ task_list(id: "1") { name tasks { content task_list { name } time_entries { hours } } } }"#,
+                    )
+                    .data(loaders),
+                )
+                .await;
+
+            assert!(response.errors.is_empty(), "{:?}", response.errors);
+            assert_eq!(
+                response.data.into_json().unwrap(),
+                json!({"task_list": {"name": "Launch", "tasks": [
+                    {"content": "Design", "task_list": {"name": "Launch"}, "time_entries": [{"hours": "2"}]},
+                    {"content": "Build", "task_list": {"name": "Launch"}, "time_entries": []},
+                ]}})
+            );
+
+            let mut requested = requested.lock().unwrap().clone();
+            requested.sort();
+            assert_eq!(
+                requested,
+                [
+                    "tasklists.json",
+                    "tasks.json",
+                    "tasks/1/time_entries.json",
+                    "tasks/2/time_entries.json",
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn builds_types_and_relations_from_the_schemas() {
+        let sdl = SCHEMA.sdl();
+
+        assert!(sdl.contains("type BoardColumn"));
+        assert!(sdl.contains("time_entries: [TimeEntry!]!"));
+        assert!(sdl.contains("task_list: TaskList"));
+
+        // lenient schemas can hold numbers sent as strings
+        let value = field_value(FieldKind::Integer, &Value::from("12")).unwrap();
+        assert_eq!(value.as_value(), Some(&async_graphql::Value::from(12)));
+        assert!(field_value(FieldKind::Integer, &Value::from("n/a")).is_none());
+    }
+}
@@ -0,0 +1,183 @@
+//! Liveness and readiness endpoints used by Cloud Run and the load balancer.
+
+use std::time::Instant;
+
+use serde::Serialize;
+use tide::{http::Url, Body, Request, Response};
+
+use crate::State;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum Check {
+    Ok {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        latency_ms: Option<u64>,
+    },
+    Skipped {
+        reason: &'static str,
+    },
+    Error {
+        error: String,
+    },
+}
+
+impl Check {
+    fn is_error(&self) -> bool {
+        matches!(self, Check::Error { .. })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Checks {
+    config: Check,
+    upstream: Check,
+}
+
+/// Reports that the process is alive.
+pub async fn healthz(_req: Request<State>) -> tide::Result {
+    Ok(Response::builder(200)
+        .body(serde_json::json!({ "status": "ok" }))
+        .build())
+}
+
+fn check_config(state: &State) -> Check {
+    let config = &state.config;
+
+    if let Err(e) = config.port().parse::<u16>() {
+        return Check::Error {
+            error: format!("invalid port {}: {}", config.port(), e),
+        };
+    }
+
+    match Url::parse(config.endpoint()) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {
+            Check::Ok { latency_ms: None }
+        }
+        Ok(url) => Check::Error {
+            error: format!("unsupported teamwork_url scheme {}", url.scheme()),
+        },
+        Err(e) => Check::Error {
+            error: format!("invalid teamwork_url: {}", e),
+        },
+    }
+}
+
+/// Makes a lightweight authenticated call to Teamwork using the configured API
+/// key, bounded by the readiness timeout.
+async fn check_upstream(state: &State) -> Check {
+    let config = &state.config;
+
+    if !config.readiness_check_upstream() {
+        return Check::Skipped {
+            reason: "readiness_check_upstream is disabled",
+        };
+    }
+
+    let auth = match config.api_key_auth() {
+        Some(auth) => auth,
+        None => {
+            return Check::Skipped {
+                reason: "API_KEY is unset",
+            }
+        }
+    };
+
+    let start = Instant::now();
+
+    let request = state
+        .client
+        .get(format!("{}/me.json", config.endpoint()))
+        .header("Authorization", auth)
+        .send();
+
+    match async_std::future::timeout(config.readiness_timeout(), request).await {
+        Ok(Ok(response)) if response.status().is_success() => Check::Ok {
+            latency_ms: Some(start.elapsed().as_millis() as u64),
+        },
+        Ok(Ok(response)) => Check::Error {
+            error: format!("Teamwork returned {}", response.status()),
+        },
+        Ok(Err(e)) => Check::Error {
+            error: e.to_string(),
+        },
+        Err(_) => Check::Error {
+            error: format!(
+                "Teamwork didn't respond within {}ms",
+                config.readiness_timeout().as_millis()
+            ),
+        },
+    }
+}
+
+/// Reports whether the service is ready to receive traffic, verifying the
+/// config and, with `readiness_check_upstream`, that Teamwork is reachable.
+/// The upstream check is off by default since the stale cache and the mirror
+/// keep answering while Teamwork is down, which they can't do once the load
+/// balancer stops sending traffic.
+pub async fn readyz(req: Request<State>) -> tide::Result {
+    let state = req.state();
+
+    let config = check_config(state);
+
+    let upstream = if config.is_error() {
+        Check::Skipped {
+            reason: "config is invalid",
+        }
+    } else {
+        check_upstream(state).await
+    };
+
+    let ready = !config.is_error() && !upstream.is_error();
+
+    let body = serde_json::json!({
+        "status": if ready { "ready" } else { "not_ready" },
+        "checks": Checks { config, upstream },
+    });
+
+    Ok(Response::builder(if ready { 200 } else { 503 })
+        .body(Body::from_json(&body)?)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use tide::http::{Method, Request, Url};
+
+    use super::*;
+    use crate::test_state;
+
+    fn readyz_status(settings: &[(&str, &str)]) -> (u16, serde_json::Value) {
+        let mut app = tide::with_state(test_state(settings));
+        app.at("readyz").get(readyz);
+
+        async_std::task::block_on(async {
+            let req = Request::new(Method::Get, Url::parse("http://localhost/readyz").unwrap());
+            let mut res: tide::http::Response = app.respond(req).await.unwrap();
+            (res.status().into(), res.body_json().await.unwrap())
+        })
+    }
+
+    #[test]
+    fn readiness_doesnt_depend_on_teamwork_by_default() {
+        let (status, body) = readyz_status(&[("api_key", "key")]);
+
+        assert_eq!(status, 200);
+        assert_eq!(body["checks"]["upstream"]["status"], "skipped");
+    }
+
+    #[test]
+    fn reports_invalid_config_and_an_unreachable_teamwork() {
+        let (status, body) = readyz_status(&[("teamwork_url", "ftp://example.com")]);
+        assert_eq!(status, 503);
+        assert_eq!(body["checks"]["config"]["status"], "error");
+
+        let (status, body) = readyz_status(&[
+            ("api_key", "key"),
+            ("readiness_check_upstream", "true"),
+            ("readiness_timeout_ms", "500"),
+        ]);
+        assert_eq!(status, 503);
+        assert_eq!(body["checks"]["upstream"]["status"], "error");
+    }
+}
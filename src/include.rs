@@ -0,0 +1,365 @@
+//! Embeds the records a page refers to, requested with `?include=`, e.g.
+//! `/tasks?include=task_list,project`, so consumers don't look them up one by
+//! one. They're returned in the `included` section of the response by type and
+//! id, JSON:API-style. Each related record is fetched once however many
+//! records refer to it, and the lookups are made concurrently. A failed
+//! lookup leaves out the records of its type, reported in `included_errors`,
+//! rather than failing the page.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use serde_json::{Map, Value};
+use teamwork_schema::{Project, Task, TaskList, TimeEntry};
+
+use crate::{
+    mirror::Mirror,
+    reports::{text, to_values},
+    upstream::{self, Resource, Upstream},
+    Error, Query, Result,
+};
+
+/// The query parameter listing the relations to include.
+pub const INCLUDE_PARAM: &str = "include";
+
+/// The most ids sent in one of Teamwork's id filters, larger lookups are
+/// split.
+pub const MAX_IDS: usize = 100;
+
+/// The type of an included record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Target {
+    Task,
+    TaskList,
+    Project,
+}
+
+impl Target {
+    /// The key of the records in `included`.
+    fn name(self) -> &'static str {
+        match self {
+            Target::Task => "task",
+            Target::TaskList => "task_list",
+            Target::Project => "project",
+        }
+    }
+
+    fn route(self) -> &'static str {
+        match self {
+            Target::Task => Task::ROUTE,
+            Target::TaskList => TaskList::ROUTE,
+            Target::Project => Project::ROUTE,
+        }
+    }
+
+    /// Fetches the records with the given ids, from the mirror when it's
+    /// given and holds them, otherwise narrowed with Teamwork's id filters.
+    async fn fetch(
+        self,
+        upstream: &Upstream<'_>,
+        mirror: Option<&Arc<Mirror>>,
+        ids: &[String],
+    ) -> Result<Vec<Value>> {
+        if let Some(mirror) = mirror {
+            let mirror = mirror.clone();
+            let ids = ids.to_vec();
+
+            // SQLite blocks, keep the reads off the executor's threads
+            let records =
+                async_std::task::spawn_blocking(move || mirror.records(self.route(), &ids)).await?;
+
+            if let Some(records) = records {
+                return Ok(records);
+            }
+        }
+
+        let joined = ids.join(",");
+
+        match self {
+            Target::Task => {
+                fetch::<Task>(
+                    upstream,
+                    &[("taskIds", &joined), ("includeCompletedTasks", "true")],
+                )
+                .await
+            }
+            Target::TaskList => fetch::<TaskList>(upstream, &[("tasklistIds", &joined)]).await,
+            Target::Project => {
+                fetch::<Project>(upstream, &[("projectIds", &joined), ("status", "ALL")]).await
+            }
+        }
+    }
+}
+
+/// Fetches a page of the records, which holds every record looked up since
+/// lookups are split at `MAX_IDS`.
+async fn fetch<R: Resource>(
+    upstream: &Upstream<'_>,
+    params: &[(&str, &str)],
+) -> Result<Vec<Value>> {
+    let query = Query {
+        page: 1,
+        per_page: Some(upstream::MAX_PAGE_SIZE),
+        other: params
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::from(*v)))
+            .collect(),
+    };
+
+    let (records, _) = upstream.get_page::<R>(&query).await?;
+
+    to_values(&records).map_err(|e| Error::SchemaError(e.to_string()))
+}
+
+/// A record that the records of a route refer to.
+#[derive(Debug, Clone, Copy)]
+pub struct Relation {
+    name: &'static str,
+    /// The field holding the id of the related record.
+    field: &'static str,
+    target: Target,
+}
+
+const TASK_RELATIONS: &[Relation] = &[
+    Relation {
+        name: "task_list",
+        field: "todo_list_id",
+        target: Target::TaskList,
+    },
+    Relation {
+        name: "project",
+        field: "project_id",
+        target: Target::Project,
+    },
+    Relation {
+        name: "parent_task",
+        field: "parent_task_id",
+        target: Target::Task,
+    },
+];
+
+const TIME_ENTRY_RELATIONS: &[Relation] = &[Relation {
+    name: "task",
+    field: "todo_item_id",
+    target: Target::Task,
+}];
+
+fn relations(teamwork_route: &str) -> &'static [Relation] {
+    match teamwork_route {
+        r if r == Task::ROUTE => TASK_RELATIONS,
+        r if r == TimeEntry::ROUTE => TIME_ENTRY_RELATIONS,
+        _ => &[],
+    }
+}
+
+/// Parses the comma separated relations in `include` for a route.
+pub fn parse(teamwork_route: &str, include: &str) -> std::result::Result<Vec<Relation>, String> {
+    let available = relations(teamwork_route);
+
+    include
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            available
+                .iter()
+                .copied()
+                .find(|r| r.name == name)
+                .ok_or_else(|| {
+                    let names: Vec<_> = available.iter().map(|r| r.name).collect();
+                    format!(
+                        "can't include {}, expected one of {}",
+                        name,
+                        if names.is_empty() {
+                            "none".to_string()
+                        } else {
+                            names.join(", ")
+                        }
+                    )
+                })
+        })
+        .collect()
+}
+
+/// The records a page refers to, by type and id, and why those of a type
+/// couldn't be looked up.
+#[derive(Debug, Default)]
+pub struct Included {
+    pub records: Map<String, Value>,
+    pub errors: BTreeMap<&'static str, Error>,
+}
+
+/// Groups the ids `records` refer to through `relations` into lookups of at
+/// most `MAX_IDS` ids.
+fn lookups(relations: &[Relation], records: &[Value]) -> Vec<(Target, Vec<String>)> {
+    // relations to the same type, like tasks and their parents, share lookups
+    let mut ids: BTreeMap<Target, BTreeSet<String>> = BTreeMap::new();
+
+    for relation in relations {
+        ids.entry(relation.target)
+            .or_default()
+            .extend(records.iter().filter_map(|r| text(r, relation.field)));
+    }
+
+    ids.into_iter()
+        .flat_map(|(target, ids)| {
+            let ids: Vec<String> = ids.into_iter().collect();
+            ids.chunks(MAX_IDS)
+                .map(|chunk| (target, chunk.to_vec()))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Files the fetched records under their type by id, keeping only those
+/// with one of the ids looked up.
+fn collect(
+    relations: &[Relation],
+    fetched: Vec<(Target, Vec<String>, Result<Vec<Value>>)>,
+) -> Included {
+    let mut included = Included::default();
+
+    for relation in relations {
+        included.records.insert(
+            relation.target.name().to_string(),
+            Value::Object(Map::new()),
+        );
+    }
+
+    for (target, ids, result) in fetched {
+        let records = match result {
+            Ok(records) => records,
+            Err(e) => {
+                tide::log::warn!("Failed to include records", {
+                    type: target.name(),
+                    error: e.to_string(),
+                });
+                included.errors.entry(target.name()).or_insert(e);
+                continue;
+            }
+        };
+
+        if let Some(Value::Object(by_id)) = included.records.get_mut(target.name()) {
+            for record in records {
+                match text(&record, "id") {
+                    Some(id) if ids.contains(&id) => {
+                        by_id.insert(id, record);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    included
+}
+
+/// The records `records` refer to through `relations`, read from the mirror
+/// where it's given and holds them.
+pub async fn included(
+    upstream: &Upstream<'_>,
+    mirror: Option<&Arc<Mirror>>,
+    relations: &[Relation],
+    records: &[Value],
+) -> Included {
+    let fetched = futures::future::join_all(lookups(relations, records).into_iter().map(
+        |(target, ids)| async move {
+            let result = target.fetch(upstream, mirror, &ids).await;
+            (target, ids, result)
+        },
+    ))
+    .await;
+
+    collect(relations, fetched)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn looks_each_record_up_once() {
+        let relations = parse(Task::ROUTE, "task_list,parent_task,project").unwrap();
+        let records = vec![
+            json!({"id": 1, "todo_list_id": 5, "project_id": 9}),
+            json!({"id": 2, "todo_list_id": "5", "parent_task_id": 1, "project_id": 9}),
+            json!({"id": 3, "parent_task_id": 4}),
+        ];
+
+        assert_eq!(
+            lookups(&relations, &records),
+            [
+                (Target::Task, vec!["1".to_string(), "4".to_string()]),
+                (Target::TaskList, vec!["5".to_string()]),
+                (Target::Project, vec!["9".to_string()]),
+            ]
+        );
+
+        let records: Vec<Value> = (0..250).map(|id| json!({ "project_id": id })).collect();
+        let chunks: Vec<usize> = lookups(&relations, &records)
+            .iter()
+            .map(|(_, ids)| ids.len())
+            .collect();
+        assert_eq!(chunks, [MAX_IDS, MAX_IDS, 50]);
+    }
+
+    #[test]
+    fn files_the_records_looked_up_by_type_and_id() {
+        let relations = parse(Task::ROUTE, "task_list,project").unwrap();
+
+        let included = collect(
+            &relations,
+            vec![
+                (
+                    Target::TaskList,
+                    vec!["5".into()],
+                    // only the records looked up are kept
+                    Ok(vec![
+                        json!({"id": 5, "name": "Launch"}),
+                        json!({"id": 6, "name": "Other"}),
+                    ]),
+                ),
+                (
+                    Target::Project,
+                    vec!["9".into()],
+                    Err(Error::UpstreamTimeout),
+                ),
+            ],
+        );
+
+        assert_eq!(
+            Value::Object(included.records),
+            json!({
+                "task_list": {"5": {"id": 5, "name": "Launch"}},
+                "project": {},
+            })
+        );
+        assert_eq!(included.errors.keys().collect::<Vec<_>>(), [&"project"]);
+    }
+
+    #[test]
+    fn parses_the_relations_of_a_route() {
+        let names = |include: &str| {
+            parse(Task::ROUTE, include)
+                .unwrap()
+                .iter()
+                .map(|r| r.name)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names("task_list, parent_task,"),
+            ["task_list", "parent_task"]
+        );
+        assert!(names("").is_empty());
+        assert_eq!(
+            parse(TimeEntry::ROUTE, "project").unwrap_err(),
+            "can't include project, expected one of task"
+        );
+        assert!(parse(TaskList::ROUTE, "task").is_err());
+    }
+}
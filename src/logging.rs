@@ -0,0 +1,206 @@
+//! Structured request logging. Every request is logged as a single JSON line
+//! including the request id, the upstream Teamwork call made while handling it
+//! and how long each took.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tide::{Middleware, Next, Request};
+
+use crate::{cache::CacheStatus, telemetry::Trace};
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// The longest request id propagated from a client.
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// The id of the request, propagated from the `X-Request-Id` header or
+/// generated when the header is missing or invalid.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// A call made to the Teamwork API while handling a request.
+#[derive(Debug, Clone, Serialize)]
+pub struct UpstreamCall {
+    pub url: String,
+    /// The status returned by Teamwork, `None` when the request failed before
+    /// a response was received.
+    pub status: Option<u16>,
+    pub latency_ms: u128,
+}
+
+#[derive(Debug, Default)]
+struct Recorded {
+    identity: Option<String>,
+    upstream: Vec<UpstreamCall>,
+    cache: Option<CacheStatus>,
+}
+
+/// A hash identifying the holder of the credentials without revealing them.
+pub fn identity_hash(credentials: &str) -> String {
+    Sha256::digest(credentials.as_bytes())
+        .iter()
+        .take(8)
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Collects the details of a request as it's handled, inserted into the
+/// request's extensions by the `RequestLogger`.
+#[derive(Debug, Clone, Default)]
+pub struct RequestLog(Arc<Mutex<Recorded>>);
+
+impl RequestLog {
+    fn with<F: FnOnce(&mut Recorded)>(&self, f: F) {
+        let mut recorded = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        f(&mut recorded);
+    }
+
+    /// Records the identity making the request. Only a hash of the credentials
+    /// is logged.
+    pub fn identity(&self, credentials: &str) {
+        let identity = identity_hash(credentials);
+        self.with(|r| r.identity = Some(identity));
+    }
+
+    pub fn cache(&self, status: CacheStatus) {
+        self.with(|r| r.cache = Some(status));
+    }
+
+    pub fn upstream(&self, url: &str, status: Option<u16>, latency: Duration) {
+        self.with(|r| {
+            r.upstream.push(UpstreamCall {
+                url: url.to_string(),
+                status,
+                latency_ms: latency.as_millis(),
+            })
+        });
+    }
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    time: u128,
+    level: &'static str,
+    msg: &'static str,
+    request_id: &'a str,
+    trace_id: Option<String>,
+    method: String,
+    route: &'a str,
+    status: u16,
+    latency_ms: u128,
+    identity: Option<&'a str>,
+    upstream: &'a [UpstreamCall],
+    cache: Option<&'static str>,
+}
+
+/// Middleware logging every request as a JSON line on stdout.
+#[derive(Debug, Default)]
+pub struct RequestLogger;
+
+fn generate_request_id() -> String {
+    uuid::Uuid::new_v4().to_simple().to_string()
+}
+
+/// Whether a client supplied request id can be logged and forwarded to
+/// Teamwork as it is.
+fn valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for RequestLogger {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let start = Instant::now();
+
+        let request_id = req
+            .header(REQUEST_ID_HEADER)
+            .map(|h| h.as_str().to_string())
+            .filter(|id| valid_request_id(id))
+            .unwrap_or_else(generate_request_id);
+
+        let log = RequestLog::default();
+        let method = req.method().to_string();
+        let route = req.url().path().to_string();
+        let trace_id = req.ext::<Trace>().and_then(Trace::trace_id);
+
+        req.set_ext(RequestId(request_id.clone()));
+        req.set_ext(log.clone());
+
+        let mut res = next.run(req).await;
+
+        res.insert_header(REQUEST_ID_HEADER, request_id.as_str());
+
+        let recorded = log.0.lock().unwrap_or_else(|e| e.into_inner());
+
+        let entry = Entry {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or_default(),
+            level: if res.status().is_server_error() {
+                "error"
+            } else {
+                "info"
+            },
+            msg: "request",
+            request_id: &request_id,
+            trace_id,
+            method,
+            route: &route,
+            status: res.status().into(),
+            latency_ms: start.elapsed().as_millis(),
+            identity: recorded.identity.as_deref(),
+            upstream: &recorded.upstream,
+            cache: recorded.cache.map(CacheStatus::as_str),
+        };
+
+        if let Ok(line) = serde_json::to_string(&entry) {
+            println!("{}", line);
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tide::http::{Method, Request, Response, Url};
+
+    use super::*;
+
+    fn request_id(header: Option<&str>) -> String {
+        let mut app = tide::new();
+        app.with(RequestLogger);
+        app.at("/").get(|_| async { Ok("") });
+
+        let mut req = Request::new(Method::Get, Url::parse("http://localhost/").unwrap());
+        if let Some(header) = header {
+            req.insert_header(REQUEST_ID_HEADER, header);
+        }
+
+        let res: Response = async_std::task::block_on(app.respond(req)).unwrap();
+        res[REQUEST_ID_HEADER].as_str().to_string()
+    }
+
+    #[test]
+    fn propagates_valid_request_ids_only() {
+        assert_eq!(request_id(Some("req-1.a_B")), "req-1.a_B");
+
+        for invalid in &["", "id with spaces", "<script>", &"a".repeat(65)] {
+            let id = request_id(Some(invalid));
+            assert_ne!(&id, invalid);
+            assert!(valid_request_id(&id));
+        }
+
+        assert_eq!(request_id(None).len(), 32);
+    }
+}
@@ -1,20 +1,239 @@
-use std::{borrow::Cow, convert::TryInto, str::FromStr, sync::Arc};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
 
 use async_std::sync::RwLock;
 use serde::{Deserialize, Serialize};
-use teamwork_schema::{Task, TaskList, TimeEntry};
-use tide::{prelude::*, Body, Request, Response};
+use teamwork_schema::{meta::Schema, Task, TaskList, TimeEntry};
+use tide::{Body, Request, Response};
 
+use crate::{
+    breaker::CircuitBreaker,
+    cache::{CacheStatus, StaleCache},
+    export::Format,
+    logging::RequestLogger,
+    metrics::{route, RequestMetrics},
+    mirror::{query::Filter, Mirror, MirrorPage},
+    telemetry::{SpanKind, Tracer, Tracing},
+    upstream::Upstream,
+};
+
+mod breaker;
+mod cache;
+mod calendar;
+mod digest;
+mod export;
+mod graphql;
+mod health;
+mod include;
+mod logging;
+mod metrics;
+mod mirror;
+mod reports;
+mod search;
+mod telemetry;
+mod upstream;
+mod xlsx;
+
+#[allow(clippy::enum_variant_names)]
 #[derive(thiserror::Error, Debug)]
 enum Error {
     #[error("Teamwork response missing expected header {0}")]
     MissingHeader(&'static str),
+    #[error("Teamwork response has an invalid {0} header: {1}")]
+    InvalidHeader(&'static str, String),
     #[error("Invalid config {0:?}")]
     ConfigError(#[from] config::ConfigError),
     #[error("IOError {0}")]
     IOError(#[from] std::io::Error),
     #[error("Teamwork API returned an error: status {0} message {1}")]
     TeamworkError(u16, &'static str, Option<serde_json::Value>),
+    #[error("Request to Teamwork failed: {0}")]
+    UpstreamError(String),
+    #[error("Request to Teamwork timed out")]
+    UpstreamTimeout,
+    #[error("Teamwork is unavailable, retry after {}s", retry_after_secs(*.0))]
+    CircuitOpen(Duration),
+    #[error("Teamwork response doesn't match the expected schema: {0}")]
+    SchemaError(String),
+    #[error("Invalid query: {0}")]
+    QueryError(String),
+    #[error("Request missing authorization header and API_KEY is unset")]
+    AuthError,
+    #[error("Forbidden: {0}")]
+    Forbidden(&'static str),
+    #[error("{0} isn't available for this route")]
+    FormatError(&'static str),
+    #[error("Digest delivery failed: {0}")]
+    DeliveryError(String),
+    #[error("Local mirror failed: {0}")]
+    MirrorError(#[from] rusqlite::Error),
+    #[error("Local mirror unavailable: {0}")]
+    MirrorUnavailable(String),
+}
+
+impl Error {
+    /// The name of the variant, used to label metrics.
+    fn variant(&self) -> &'static str {
+        match self {
+            Error::MissingHeader(_) => "MissingHeader",
+            Error::InvalidHeader(..) => "InvalidHeader",
+            Error::ConfigError(_) => "ConfigError",
+            Error::IOError(_) => "IOError",
+            Error::TeamworkError(..) => "TeamworkError",
+            Error::UpstreamError(_) => "UpstreamError",
+            Error::UpstreamTimeout => "UpstreamTimeout",
+            Error::CircuitOpen(_) => "CircuitOpen",
+            Error::SchemaError(_) => "SchemaError",
+            Error::QueryError(_) => "QueryError",
+            Error::AuthError => "AuthError",
+            Error::Forbidden(_) => "Forbidden",
+            Error::FormatError(_) => "FormatError",
+            Error::DeliveryError(_) => "DeliveryError",
+            Error::MirrorError(_) => "MirrorError",
+            Error::MirrorUnavailable(_) => "MirrorUnavailable",
+        }
+    }
+
+    /// The status of the response. Teamwork's client errors are passed through
+    /// while problems talking to Teamwork are reported as a bad gateway.
+    fn status(&self) -> u16 {
+        match self {
+            Error::TeamworkError(status, ..) if (400..500).contains(status) => *status,
+            Error::MissingHeader(_)
+            | Error::InvalidHeader(..)
+            | Error::TeamworkError(..)
+            | Error::UpstreamError(_)
+            | Error::SchemaError(_) => 502,
+            Error::UpstreamTimeout => 504,
+            Error::CircuitOpen(_) | Error::MirrorUnavailable(_) => 503,
+            Error::QueryError(_) => 400,
+            Error::AuthError => 401,
+            Error::Forbidden(_) => 403,
+            Error::FormatError(_) => 406,
+            Error::ConfigError(_)
+            | Error::IOError(_)
+            | Error::DeliveryError(_)
+            | Error::MirrorError(_) => 500,
+        }
+    }
+
+    /// The stable, machine readable code identifying the error.
+    fn code(&self) -> &'static str {
+        match self {
+            Error::TeamworkError(401, ..) | Error::AuthError => "unauthorized",
+            Error::TeamworkError(403, ..) | Error::Forbidden(_) => "forbidden",
+            Error::TeamworkError(404, ..) => "not_found",
+            Error::TeamworkError(429, ..) => "rate_limited",
+            Error::TeamworkError(..) => "upstream_error",
+            Error::UpstreamError(_) | Error::CircuitOpen(_) => "upstream_unavailable",
+            Error::UpstreamTimeout => "upstream_timeout",
+            Error::MissingHeader(_) | Error::InvalidHeader(..) => "upstream_protocol_error",
+            Error::SchemaError(_) => "upstream_schema_mismatch",
+            Error::MirrorUnavailable(_) => "mirror_unavailable",
+            Error::QueryError(_) => "invalid_query",
+            Error::FormatError(_) => "unsupported_format",
+            Error::ConfigError(_)
+            | Error::IOError(_)
+            | Error::DeliveryError(_)
+            | Error::MirrorError(_) => "internal_error",
+        }
+    }
+
+    /// Whether Teamwork couldn't answer, rather than rejecting the request.
+    fn is_unavailable(&self) -> bool {
+        match self {
+            Error::TeamworkError(status, ..) => *status >= 500,
+            Error::UpstreamError(_) | Error::UpstreamTimeout | Error::CircuitOpen(_) => true,
+            _ => false,
+        }
+    }
+
+    /// How long clients should wait before retrying.
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::CircuitOpen(retry_after) => Some(*retry_after),
+            _ => None,
+        }
+    }
+
+    /// The body of Teamwork's error response.
+    fn details(&self) -> Option<&serde_json::Value> {
+        match self {
+            Error::TeamworkError(_, _, body) => body.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl From<surf::Error> for Error {
+    fn from(e: surf::Error) -> Self {
+        match e.downcast_ref::<isahc::Error>() {
+            Some(isahc::Error::Timeout) => Error::UpstreamTimeout,
+            _ => Error::UpstreamError(e.to_string()),
+        }
+    }
+}
+
+/// Whole seconds for the `Retry-After` header, rounded up so clients don't
+/// retry early.
+fn retry_after_secs(duration: Duration) -> u64 {
+    (duration.as_millis() as u64).div_ceil(1000).max(1)
+}
+
+/// The code of errors that didn't originate from an `Error`, based on the
+/// status tide assigned to them.
+fn status_code(status: tide::StatusCode) -> &'static str {
+    use tide::StatusCode;
+
+    match status {
+        StatusCode::BadRequest | StatusCode::UnprocessableEntity => "bad_request",
+        StatusCode::Unauthorized => "unauthorized",
+        StatusCode::Forbidden => "forbidden",
+        StatusCode::NotFound => "not_found",
+        StatusCode::MethodNotAllowed => "method_not_allowed",
+        StatusCode::TooManyRequests => "rate_limited",
+        status if status.is_client_error() => "bad_request",
+        _ => "internal_error",
+    }
+}
+
+/// The JSON body of every error response.
+#[derive(Debug, Serialize)]
+struct ErrorEnvelope {
+    error: ErrorBody,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    code: &'static str,
+    status: u16,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    teamwork_response: Option<serde_json::Value>,
+}
+
+impl ErrorBody {
+    fn new(e: &Error) -> Self {
+        // config, IO and mirror errors can name paths and internals, so only
+        // the logs see the details of them
+        let message = if e.status() == 500 {
+            tide::log::error!("Internal error", {
+                error: e.to_string(),
+                variant: e.variant(),
+            });
+            tide::StatusCode::InternalServerError
+                .canonical_reason()
+                .to_string()
+        } else {
+            e.to_string()
+        };
+
+        ErrorBody {
+            code: e.code(),
+            status: e.status(),
+            message,
+            teamwork_response: e.details().cloned(),
+        }
+    }
 }
 
 type Result<T> = std::result::Result<T, Error>;
@@ -30,6 +249,23 @@ struct CachedConfig {
     port: String,
     endpoint: String,
     api_key: Option<String>,
+    readiness_check_upstream: bool,
+    readiness_timeout: Duration,
+    otlp_endpoint: Option<String>,
+    service_name: String,
+    otlp_export_interval: Duration,
+    otlp_export_timeout: Duration,
+    upstream_connect_timeout: Duration,
+    upstream_timeout: Duration,
+    circuit_failure_threshold: u32,
+    circuit_open_for: Duration,
+    stale_cache: bool,
+    stale_cache_max_entries: usize,
+    stale_cache_max_age: Duration,
+    estimate_variance_threshold: f64,
+    mirror_path: Option<String>,
+    mirror_sync_interval: Duration,
+    mirror_full_sync_interval: Duration,
 }
 
 impl Config {
@@ -39,6 +275,44 @@ impl Config {
             port: config.get_str("port")?,
             endpoint: config.get_str("teamwork_url")?,
             api_key: config.get_str("api_key").ok(),
+            readiness_check_upstream: config.get_bool("readiness_check_upstream")?,
+            readiness_timeout: Duration::from_millis(
+                config.get_int("readiness_timeout_ms")?.max(0) as u64,
+            ),
+            otlp_endpoint: config
+                .get_str("otel_exporter_otlp_endpoint")
+                .ok()
+                .filter(|e| !e.is_empty()),
+            service_name: config.get_str("otel_service_name")?,
+            otlp_export_interval: Duration::from_millis(
+                config.get_int("otel_export_interval_ms")?.max(1) as u64,
+            ),
+            otlp_export_timeout: Duration::from_millis(
+                config.get_int("otel_exporter_otlp_timeout")?.max(1) as u64,
+            ),
+            upstream_connect_timeout: Duration::from_millis(
+                config.get_int("upstream_connect_timeout_ms")?.max(1) as u64,
+            ),
+            upstream_timeout: Duration::from_millis(
+                config.get_int("upstream_timeout_ms")?.max(1) as u64
+            ),
+            circuit_failure_threshold: config.get_int("circuit_failure_threshold")?.max(1) as u32,
+            circuit_open_for: Duration::from_millis(
+                config.get_int("circuit_open_ms")?.max(0) as u64
+            ),
+            stale_cache: config.get_bool("stale_cache")?,
+            stale_cache_max_entries: config.get_int("stale_cache_max_entries")?.max(1) as usize,
+            stale_cache_max_age: Duration::from_secs(
+                config.get_int("stale_cache_max_age_secs")?.max(0) as u64,
+            ),
+            estimate_variance_threshold: config.get_float("estimate_variance_threshold")?.max(0.0),
+            mirror_path: config.get_str("mirror_path").ok().filter(|p| !p.is_empty()),
+            mirror_sync_interval: Duration::from_secs(
+                config.get_int("mirror_sync_interval_secs")?.max(1) as u64,
+            ),
+            mirror_full_sync_interval: Duration::from_secs(
+                config.get_int("mirror_full_sync_interval_secs")?.max(0) as u64,
+            ),
         };
 
         Ok(Config {
@@ -62,20 +336,121 @@ impl Config {
     fn api_key(&self) -> Option<&str> {
         self.cached.api_key.as_deref()
     }
+
+    /// The authorization header for the configured API key.
+    fn api_key_auth(&self) -> Option<String> {
+        self.api_key()
+            .map(|key| format!("Basic {}", base64::encode(format!("{}: ", &key))))
+    }
+
+    /// Whether the readiness check calls Teamwork with the configured API key.
+    fn readiness_check_upstream(&self) -> bool {
+        self.cached.readiness_check_upstream
+    }
+
+    fn readiness_timeout(&self) -> Duration {
+        self.cached.readiness_timeout
+    }
+
+    /// How far over its estimate a task's logged time may go, as a share of
+    /// the estimate, before the estimates report flags it.
+    fn estimate_variance_threshold(&self) -> f64 {
+        self.cached.estimate_variance_threshold
+    }
+
+    /// The digest sink, parsed when it's used so that only the digest
+    /// subcommand fails over its settings.
+    async fn digest_sink(&self) -> Result<digest::SinkConfig> {
+        Ok(digest::SinkConfig::from_config(&*self.config.read().await)?)
+    }
+
+    /// The client used for Teamwork requests, bounded by the configured
+    /// timeouts.
+    fn client(&self) -> Result<surf::Client> {
+        use isahc::config::Configurable;
+
+        let client = isahc::HttpClient::builder()
+            .connect_timeout(self.cached.upstream_connect_timeout)
+            .timeout(self.cached.upstream_timeout)
+            .build()
+            .map_err(|e| Error::UpstreamError(e.to_string()))?;
+
+        Ok(surf::Client::with_http_client(
+            http_client::isahc::IsahcClient::from_client(client),
+        ))
+    }
+
+    fn circuit_breaker(&self) -> CircuitBreaker {
+        CircuitBreaker::new(
+            self.cached.circuit_failure_threshold,
+            self.cached.circuit_open_for,
+        )
+    }
+
+    /// The cache of responses served while a circuit is open, `None` when
+    /// `stale_cache` is disabled.
+    fn stale_cache(&self) -> Option<StaleCache> {
+        if self.cached.stale_cache {
+            Some(StaleCache::new(
+                self.cached.stale_cache_max_entries,
+                self.cached.stale_cache_max_age,
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// The local mirror of the collections, `None` when `mirror_path` is
+    /// unset.
+    fn mirror(&self) -> Result<Option<Mirror>> {
+        Ok(match &self.cached.mirror_path {
+            Some(path) => Some(Mirror::open(path)?),
+            None => None,
+        })
+    }
+
+    fn mirror_sync_interval(&self) -> Duration {
+        self.cached.mirror_sync_interval
+    }
+
+    fn mirror_full_sync_interval(&self) -> Duration {
+        self.cached.mirror_full_sync_interval
+    }
+
+    /// The tracer exporting spans to the configured OTLP collector, or
+    /// discarding them when no collector is configured.
+    fn tracer(&self) -> Result<Tracer> {
+        match &self.cached.otlp_endpoint {
+            Some(endpoint) => Tracer::new(
+                endpoint,
+                &self.cached.service_name,
+                self.cached.otlp_export_interval,
+                self.cached.otlp_export_timeout,
+            )
+            .map_err(|e| config::ConfigError::Message(e.to_string()).into()),
+            None => Ok(Tracer::default()),
+        }
+    }
 }
 
 #[derive(Clone)]
 struct State {
     client: Arc<surf::Client>,
+    breaker: Arc<CircuitBreaker>,
+    cache: Option<Arc<StaleCache>>,
+    mirror: Option<Arc<Mirror>>,
     config: Config,
 }
 
 impl State {
-    fn new(config: Config) -> Self {
-        State {
-            client: Arc::new(surf::Client::new()),
+    fn new(config: Config) -> Result<Self> {
+        Ok(State {
+            client: Arc::new(config.client()?),
+            breaker: Arc::new(config.circuit_breaker()),
+            cache: config.stale_cache().map(Arc::new),
+            mirror: config.mirror()?.map(Arc::new),
             config,
-        }
+        })
     }
 }
 
@@ -95,6 +470,33 @@ impl Query {
     fn default_page() -> usize {
         1
     }
+
+    /// The query string with the params sorted, so that it's the same however
+    /// the request ordered them.
+    fn canonical(&self) -> String {
+        let mut params: std::collections::BTreeMap<&str, String> = self
+            .other
+            .iter()
+            .map(|(param, value)| {
+                let value = match value {
+                    serde_json::Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+
+                (param.as_str(), value)
+            })
+            .collect();
+
+        params.insert("page", self.page.to_string());
+
+        if let Some(per_page) = self.per_page {
+            params.insert("pageSize", per_page.to_string());
+        }
+
+        let mut url = tide::http::Url::parse("http://localhost/").expect("a valid URL");
+        url.query_pairs_mut().extend_pairs(params);
+        url.query().unwrap_or_default().to_string()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -110,6 +512,39 @@ struct ApiResponse<T> {
     data: Vec<T>,
     meta: Meta,
     links: Links,
+    /// The related records asked for with `?include=`, by type and id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    included: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Why the related records of a type couldn't be included, by type.
+    #[serde(default, skip_serializing_if = "Option::is_none", skip_deserializing)]
+    included_errors: Option<BTreeMap<&'static str, ErrorBody>>,
+}
+
+/// The `included` and `included_errors` sections of a response.
+type IncludedSections = (
+    Option<serde_json::Map<String, serde_json::Value>>,
+    Option<BTreeMap<&'static str, ErrorBody>>,
+);
+
+fn included_sections(included: Option<include::Included>) -> IncludedSections {
+    let included = match included {
+        Some(included) => included,
+        None => return (None, None),
+    };
+
+    let errors = if included.errors.is_empty() {
+        None
+    } else {
+        Some(
+            included
+                .errors
+                .iter()
+                .map(|(target, e)| (*target, ErrorBody::new(e)))
+                .collect(),
+        )
+    };
+
+    (Some(included.records), errors)
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -130,16 +565,19 @@ impl Links {
             .expect("The URL object on an HTTP request should have a valid host");
         let route = url.path();
 
-        let params = url
-            .query_pairs()
-            .filter(|(param, _)| param != "page")
-            .map(|(k, v)| format!("{}={}", k, v))
-            .collect::<Vec<String>>();
+        // re-encoded so values like filters stay valid in the links and the
+        // Link header
+        let mut encoded = url.clone();
+        encoded
+            .query_pairs_mut()
+            .clear()
+            .extend_pairs(url.query_pairs().filter(|(param, _)| param != "page"));
+        let params = encoded.query().unwrap_or_default();
 
         let params = if params.is_empty() {
             "".to_string()
         } else {
-            format!("{}&", params.join("&"))
+            format!("{}&", params)
         };
 
         let link = format!("{}://{}{}?{}", &scheme, &host, &route, &params);
@@ -164,6 +602,22 @@ impl Links {
             next,
         }
     }
+
+    /// The links formatted as a `Link` header.
+    fn header(&self) -> String {
+        let mut header = format!("<{}>;rel=self,<{}>;rel=first", self.curr, self.first);
+
+        if let Some(prev) = &self.prev {
+            header.push_str(&format!(",<{}>;rel=prev", prev));
+        }
+
+        if let Some(next) = &self.next {
+            header.push_str(&format!(",<{}>;rel=next", next));
+        }
+
+        header.push_str(&format!(",<{}>;rel=last", self.last));
+        header
+    }
 }
 
 trait TeamworkResponse: Serialize + serde::de::DeserializeOwned {
@@ -172,107 +626,377 @@ trait TeamworkResponse: Serialize + serde::de::DeserializeOwned {
     fn data(self) -> Vec<Self::Data>;
 }
 
+/// Responds with the serialized `ApiResponse` in the requested format.
+fn respond<T: Schema>(format: Format, body: Vec<u8>, link_header: &str) -> tide::Result {
+    let mut res = match format {
+        Format::Json => Response::builder(200)
+            .body(Body::from_bytes(body))
+            .content_type(tide::http::mime::JSON)
+            .build(),
+        Format::Csv => export::csv_response(&T::META, &body)?,
+        Format::Xlsx(sheets) if T::META.name == TimeEntry::META.name => {
+            export::xlsx_response(sheets, &body)?
+        }
+        Format::Xlsx(_) => Err(Error::FormatError("XLSX"))?,
+    };
+
+    res.insert_header("Link", link_header);
+
+    Ok(res)
+}
+
+/// Whether the request is made with the configured API key, whose view of
+/// Teamwork is what the mirror holds.
+fn uses_api_key(req: &Request<State>, upstream: &Upstream<'_>) -> bool {
+    let api_key_identity = req
+        .state()
+        .config
+        .api_key_auth()
+        .map(|a| logging::identity_hash(&a));
+
+    api_key_identity.as_deref() == Some(upstream.identity().as_str())
+}
+
+/// Responds with a page read from the mirror, in the same envelope and formats
+/// as the pages read from Teamwork.
+fn mirror_page_response<T: Schema>(
+    req: &Request<State>,
+    page: MirrorPage,
+    page_number: usize,
+    format: Format,
+    included: Option<include::Included>,
+) -> tide::Result {
+    let meta = Meta {
+        page: page_number,
+        total_pages: page.total_pages,
+    };
+
+    let links = Links::new(req.url(), &meta);
+    let link_header = links.header();
+
+    let (included, included_errors) = included_sections(included);
+
+    let body = serde_json::to_vec(&ApiResponse {
+        data: page.records,
+        meta,
+        links,
+        included,
+        included_errors,
+    })?;
+
+    let mut res = respond::<T>(format, body, &link_header)?;
+    res.insert_header("X-Mirror-Synced-At", page.synced_at);
+
+    Ok(res)
+}
+
+/// Answers from the local mirror while Teamwork is unavailable. Only requests
+/// made with the configured API key are answered, since the mirror holds what
+/// it can see, and only without Teamwork's filters, which the mirror can't
+/// apply.
+async fn mirror_response<T: Schema>(
+    req: &Request<State>,
+    upstream: &Upstream<'_>,
+    teamwork_route: &str,
+    query: &Query,
+    format: Format,
+    relations: &[include::Relation],
+) -> tide::Result<Option<Response>> {
+    let mirror = match &req.state().mirror {
+        Some(mirror) => mirror.clone(),
+        None => return Ok(None),
+    };
+
+    if !uses_api_key(req, upstream) || !query.other.is_empty() {
+        return Ok(None);
+    }
+
+    let route = teamwork_route.to_string();
+    let page_number = query.page;
+    let per_page = query
+        .per_page
+        .unwrap_or(upstream::MAX_PAGE_SIZE)
+        .clamp(1, upstream::MAX_PAGE_SIZE);
+
+    // SQLite blocks, keep the reads off the executor's threads
+    let reader = mirror.clone();
+    let page =
+        match async_std::task::spawn_blocking(move || reader.page(&route, page_number, per_page))
+            .await
+        {
+            Ok(Some(page)) => page,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                tide::log::error!("Failed to read the mirror", {
+                    route: teamwork_route,
+                    error: e.to_string(),
+                });
+                return Ok(None);
+            }
+        };
+
+    upstream.log().cache(CacheStatus::Mirror);
+    metrics::mirror_response(teamwork_route);
+
+    // the related records are read from the mirror too, where it holds them
+    let included = if relations.is_empty() {
+        None
+    } else {
+        Some(include::included(upstream, Some(&mirror), relations, &page.records).await)
+    };
+
+    let mut res = mirror_page_response::<T>(req, page, query.page, format, included)?;
+    res.insert_header("Warning", "110 - \"Response is Stale\"");
+
+    Ok(Some(res))
+}
+
+/// Answers a `filter` from the local mirror, searching every mirrored record
+/// including completed tasks. Filters replace Teamwork's own, so the two can't
+/// be combined.
+async fn filtered_response<T: Schema>(
+    req: &Request<State>,
+    upstream: &Upstream<'_>,
+    teamwork_route: &str,
+    query: &Query,
+    format: Format,
+    filter: &str,
+) -> tide::Result {
+    let mirror = req.state().mirror.clone().ok_or_else(|| {
+        Error::MirrorUnavailable(format!(
+            "{} needs the local mirror, which isn't configured",
+            mirror::query::FILTER_PARAM
+        ))
+    })?;
+
+    if !uses_api_key(req, upstream) {
+        Err(Error::Forbidden(
+            "filters are only available with the configured API key",
+        ))?;
+    }
+
+    // Teamwork's page size is the only param kept alongside a filter
+    let page_size = query
+        .other
+        .get("pageSize")
+        .map(|size| match size.as_str().map(str::parse::<usize>) {
+            Some(Ok(size)) if size > 0 => Ok(size.min(upstream::MAX_PAGE_SIZE)),
+            _ => Err(Error::QueryError(format!("invalid pageSize {}", size))),
+        })
+        .transpose()?;
+
+    if let Some(param) = query.other.keys().find(|p| *p != "pageSize") {
+        Err(Error::QueryError(format!(
+            "{} can't be combined with {}",
+            param,
+            mirror::query::FILTER_PARAM
+        )))?;
+    }
+
+    let filter = Filter::parse(filter).map_err(Error::QueryError)?;
+
+    // exports hold every match rather than a page
+    let (page_number, per_page) = if format != Format::Json {
+        (1, i64::MAX as usize)
+    } else {
+        (
+            query.page,
+            page_size
+                .or(query.per_page)
+                .unwrap_or(upstream::MAX_PAGE_SIZE)
+                .clamp(1, upstream::MAX_PAGE_SIZE),
+        )
+    };
+
+    let route = teamwork_route.to_string();
+    let page = async_std::task::spawn_blocking(move || {
+        mirror.filter(&route, &filter, page_number, per_page)
+    })
+    .await?
+    .ok_or_else(|| {
+        Error::MirrorUnavailable(format!("{} hasn't been mirrored yet", teamwork_route))
+    })?;
+
+    upstream.log().cache(CacheStatus::Mirror);
+    metrics::mirror_response(teamwork_route);
+
+    mirror_page_response::<T>(req, page, page_number, format, None)
+}
+
+/// Responds with every record matching Teamwork's params rather than a page,
+/// since exports are read as a whole instead of paged through.
+async fn export_response<T: upstream::Resource>(
+    req: &Request<State>,
+    upstream: &Upstream<'_>,
+    query: &Query,
+    format: Format,
+) -> tide::Result {
+    if matches!(format, Format::Xlsx(_)) && T::META.name != TimeEntry::META.name {
+        Err(Error::FormatError("XLSX"))?;
+    }
+
+    let mut params = query.other.clone();
+    params.remove("pageSize");
+
+    let data = upstream.get_all::<T>(&params).await?;
+
+    let meta = Meta {
+        page: 1,
+        total_pages: 1,
+    };
+
+    let links = Links::new(req.url(), &meta);
+    let link_header = links.header();
+
+    let body = serde_json::to_vec(&ApiResponse {
+        data,
+        meta,
+        links,
+        included: None,
+        included_errors: None,
+    })?;
+
+    respond::<T>(format, body, &link_header)
+}
+
 /// This is the base handler responsible for proxying the data from the teamwork
 /// API. The data is converted into a more standard and consistent format.
 async fn base_handler<T, T2>(teamwork_route: &str, req: Request<State>) -> tide::Result
 where
     T2: TeamworkResponse<Data = T>,
-    T: Serialize,
+    T: upstream::Resource,
 {
-    let auth: Cow<'_, str> = if let Some(header) = req.header("authorization") {
-        Cow::Borrowed(header.as_str())
-    } else {
-        req.state()
-            .config
-            .api_key()
-            .ok_or_else(|| {
-                tide::Error::from_str(
-                    400,
-                    "Request missing authorization header and API_KEY is unnset",
-                )
-            })
-            .map(|key| Cow::Owned(format!("Basic {}", base64::encode(format!("{}: ", &key)))))?
-    };
+    let upstream = Upstream::new(&req)?;
 
-    let query: Query = req.query()?;
+    let format = Format::from_request(&req).map_err(Error::QueryError)?;
 
-    let params = req
-        .url()
-        .query()
-        .map_or_else(|| "".into(), |q| format!("?{}", q));
+    let mut query: Query = req.query().map_err(|e| Error::QueryError(e.to_string()))?;
 
-    let mut response = req
-        .state()
-        .client
-        .get(&format!(
-            "{}/{}{}",
+    // the export params are handled by the proxy, so they aren't forwarded to
+    // Teamwork
+    for param in export::PROXY_PARAMS {
+        query.other.remove(*param);
+    }
+
+    if let Some(filter) = query.other.remove(mirror::query::FILTER_PARAM) {
+        let filter = filter.as_str().unwrap_or_default().to_string();
+
+        return filtered_response::<T>(&req, &upstream, teamwork_route, &query, format, &filter)
+            .await;
+    }
+
+    // keyed before `include` is taken out of the params, so stale pages are
+    // served with the related records they were stored with
+    let cache_key = StaleCache::key(
+        &upstream.identity(),
+        &format!(
+            "{}/{}?{}",
             req.state().config.endpoint(),
             teamwork_route,
-            params
-        ))
-        .query(&query)?
-        .header("Authorization", auth)
-        .send()
-        .await?;
-
-    if !response.status().is_success() {
-        let body = response
-            .body_string()
-            .await
-            .map(|b| {
-                serde_json::from_str::<serde_json::Value>(&b)
-                    .unwrap_or_else(|_| serde_json::Value::String(b))
-            })
-            .ok();
-        let status: u16 = response
-            .status()
-            .try_into()
-            .expect("converting StatusCode to u16 is infallible");
-        let message = response.status().canonical_reason();
-        Err(Error::TeamworkError(status, message, body))?
+            query.canonical()
+        ),
+    );
+
+    let relations = match query.other.remove(include::INCLUDE_PARAM) {
+        Some(include) => include::parse(teamwork_route, include.as_str().unwrap_or_default())
+            .map_err(Error::QueryError)?,
+        None => vec![],
+    };
+
+    if !relations.is_empty() && !matches!(format, Format::Json) {
+        Err(Error::QueryError(format!(
+            "{} is only available with JSON responses",
+            include::INCLUDE_PARAM
+        )))?;
     }
 
-    let meta = Meta {
-        page: response
-            .header("X-Page")
-            .map(|page| usize::from_str(page.as_str()).ok())
-            .flatten()
-            .unwrap_or_else(|| 1),
-        total_pages: usize::from_str(
-            response
-                .header("X-Pages")
-                .ok_or_else(|| Error::MissingHeader("X-Pages"))?
-                .as_str(),
-        )?,
+    if format != Format::Json {
+        return export_response::<T>(&req, &upstream, &query, format).await;
+    }
+
+    let page = match upstream.get(teamwork_route, &query).await {
+        Ok(page) => page,
+        Err(e) => {
+            if let (Error::CircuitOpen(_), Some(cache)) = (&e, &req.state().cache) {
+                let cached = cache.get(&cache_key);
+                let status = if cached.is_some() {
+                    CacheStatus::Hit
+                } else {
+                    CacheStatus::Miss
+                };
+
+                upstream.log().cache(status);
+                metrics::stale_cache(teamwork_route, status);
+
+                if let Some(cached) = cached {
+                    let mut res = respond::<T>(format, cached.body, &cached.link)?;
+                    res.insert_header("Age", cached.stored.elapsed().as_secs().to_string());
+                    res.insert_header("Warning", "110 - \"Response is Stale\"");
+
+                    return Ok(res);
+                }
+            }
+
+            if e.is_unavailable() {
+                if let Some(res) = mirror_response::<T>(
+                    &req,
+                    &upstream,
+                    teamwork_route,
+                    &query,
+                    format,
+                    &relations,
+                )
+                .await?
+                {
+                    return Ok(res);
+                }
+            }
+
+            Err(e)?
+        }
     };
 
-    let response: T2 = response.body_json().await?;
+    let response: T2 = upstream.deserialize(teamwork_route, &page.body)?;
+    let meta = page.meta;
+
+    let mut span = upstream.trace().span("normalize", SpanKind::Internal);
 
     let links = Links::new(req.url(), &meta);
 
-    let mut link_header = format!("<{}>;rel=self,<{}>;rel=first", links.curr, links.first);
+    let link_header = links.header();
 
-    if let Some(prev) = &links.prev {
-        link_header.push_str(&format!(",<{}>;rel=prev", prev));
-    }
+    let data = response.data();
 
-    if let Some(next) = &links.next {
-        link_header.push_str(&format!(",<{}>;rel=next", next));
-    }
+    let included = if relations.is_empty() {
+        None
+    } else {
+        let records = reports::to_values(&data).map_err(|e| Error::SchemaError(e.to_string()))?;
+        Some(include::included(&upstream, None, &relations, &records).await)
+    };
+
+    let (included, included_errors) = included_sections(included);
 
-    link_header.push_str(&format!(",<{}>;rel=last", links.last));
+    // pages missing some of their related records aren't served again
+    let complete = included_errors.is_none();
 
     let response = ApiResponse {
-        data: response.data(),
+        data,
         links,
         meta,
+        included,
+        included_errors,
     };
 
-    let response = Response::builder(200)
-        .body(Body::from_json(&response)?)
-        .header("Link", &link_header);
+    span.set_attribute("records", response.data.len());
+    drop(span);
 
-    Ok(response.build())
+    let body = serde_json::to_vec(&response)?;
+
+    if let (Some(cache), true) = (&req.state().cache, complete) {
+        cache.insert(cache_key, body.clone(), link_header.clone());
+    }
+
+    respond::<T>(format, body, &link_header)
 }
 
 teamwork_macros::generate_route!(all_tasks, Task, "tasks.json", "todo-items");
@@ -284,66 +1008,431 @@ teamwork_macros::generate_route!(
 );
 teamwork_macros::generate_route!(all_task_lists, TaskList, "tasklists.json", "tasklists");
 
-/// Intercepts errors emitted from the handler. If the error came from teamwork,
-/// the status code and message are used rather than treating it as an internal
-/// server error.
+/// Reports the schema drift observed since the process started.
+async fn schema_drift(_req: Request<State>) -> tide::Result {
+    Ok(Response::builder(200)
+        .body(Body::from_json(&serde_json::json!({
+            "data": teamwork_schema::drift::summaries()
+        }))?)
+        .build())
+}
+
+/// Intercepts errors emitted from the handlers, responding with the
+/// `ErrorEnvelope` and the status of the error. Errors that didn't come from an
+/// `Error` keep the status tide gave them.
 async fn error_handler(mut res: Response) -> tide::Result {
     // rust complains about `mutable_borrow_reservation_conflict` when
     // borrowing the error from the response while also setting the
     // response body. Doing the matching separate from setting the response
     // body, resolves the problem.
-    let error = res
-        .downcast_error::<Error>()
-        .map(|e| match e {
-            Error::TeamworkError(status, message, res_body) => {
-                Some((*status, *message, res_body.clone()))
-            }
-            err => None,
-        })
-        .flatten();
+    let body = if let Some(e) = res.downcast_error::<Error>() {
+        metrics::error(e.variant());
 
-    if let Some((status, message, res_body)) = error {
-        res.set_status(status);
+        ErrorBody::new(e)
+    } else if let Some(e) = res.error() {
+        metrics::error("Other");
 
-        res.set_body(serde_json::json!({
-            "error": {
-                "code": status,
-                "message": message,
-                "teamwork_response": res_body
-            }
+        let status = e.status();
+
+        // the details of internal errors aren't exposed to clients
+        let message = if status.is_server_error() {
+            tide::log::error!("Unhandled error", { error: e.to_string() });
+            status.canonical_reason().to_string()
+        } else {
+            e.to_string()
+        };
 
-        }));
+        ErrorBody {
+            code: status_code(status),
+            status: status.into(),
+            message,
+            teamwork_response: None,
+        }
+    } else if (res.status().is_client_error() || res.status().is_server_error())
+        && res.is_empty() != Some(false)
+    {
+        // errors without a body, such as unmatched routes
+        ErrorBody {
+            code: status_code(res.status()),
+            status: res.status().into(),
+            message: res.status().canonical_reason().to_string(),
+            teamwork_response: None,
+        }
+    } else {
         return Ok(res);
+    };
+
+    if let Some(retry_after) = res.downcast_error::<Error>().and_then(Error::retry_after) {
+        res.insert_header("Retry-After", retry_after_secs(retry_after).to_string());
     }
 
+    res.set_status(body.status);
+    res.set_body(Body::from_json(&ErrorEnvelope { error: body })?);
+
     Ok(res)
 }
 
-#[async_std::main]
-async fn main() -> Result<()> {
-    tide::log::start();
-
+/// The config with every setting's default, before `.env` and the environment
+/// are merged in.
+fn default_config() -> Result<config::Config> {
     let mut config = config::Config::new();
 
     config
         .set_default("host", "127.0.0.1")?
         .set_default("port", "3000")?
+        .set_default("readiness_check_upstream", false)?
+        .set_default("readiness_timeout_ms", 2000)?
+        .set_default("otel_service_name", "teamwork_api")?
+        .set_default("otel_export_interval_ms", 5000)?
+        .set_default("otel_exporter_otlp_timeout", 10000)?
+        .set_default("upstream_connect_timeout_ms", 5000)?
+        .set_default("upstream_timeout_ms", 30000)?
+        .set_default("circuit_failure_threshold", 5)?
+        .set_default("circuit_open_ms", 30000)?
+        .set_default("stale_cache", false)?
+        .set_default("stale_cache_max_entries", 1000)?
+        .set_default("stale_cache_max_age_secs", 86400)?
+        .set_default("estimate_variance_threshold", 0.2)?
+        .set_default("digest_sink", "file")?
+        .set_default("digest_path", "-")?
+        .set_default("digest_format", "markdown")?
+        .set_default("digest_recipients", "")?
+        .set_default("smtp_port", 587)?
+        .set_default("smtp_tls", "starttls")?
+        .set_default("mirror_sync_interval_secs", 300)?
+        .set_default("mirror_full_sync_interval_secs", 86400)?;
+
+    Ok(config)
+}
+
+/// The state of a proxy configured with the defaults and `settings`, for
+/// testing handlers.
+#[cfg(test)]
+fn test_state(settings: &[(&str, &str)]) -> State {
+    let mut config = default_config().unwrap();
+    config.set("teamwork_url", "http://127.0.0.1:1").unwrap();
+
+    for (key, value) in settings {
+        config.set(key, *value).unwrap();
+    }
+
+    State::new(Config::new(config).unwrap()).unwrap()
+}
+
+#[async_std::main]
+async fn main() -> Result<()> {
+    femme::start();
+
+    let mut config = default_config()?;
+
+    config
         .merge(config::File::new(".env", config::FileFormat::Toml).required(false))?
         .merge(config::Environment::new())?;
 
     let config = Config::new(config)?;
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("digest") => return digest::run(State::new(config)?, &args[1..]).await,
+        Some("sync") => return mirror::sync::sync_all(&State::new(config)?).await,
+        _ => {}
+    }
+
     let addr = format!("{}:{}", config.host(), config.port());
 
-    let mut app = tide::with_state(State::new(config));
+    let tracer = config.tracer()?;
+    let state = State::new(config)?;
+
+    if state.mirror.is_some() {
+        if state.config.api_key().is_some() {
+            async_std::task::spawn(mirror::sync::run(state.clone()));
+        } else {
+            tide::log::warn!("The mirror isn't synced since API_KEY is unset");
+        }
+    }
 
+    let mut app = tide::with_state(state);
+
+    app.with(Tracing::new(tracer));
+    app.with(RequestLogger);
+    app.with(RequestMetrics);
     app.with(tide::utils::After(error_handler));
 
-    app.at("tasks").get(all_tasks);
-    app.at("time-entries").get(all_time_entries);
-    app.at("task-lists").get(all_task_lists);
+    route(&mut app, "tasks").get(all_tasks);
+    route(&mut app, "time-entries").get(all_time_entries);
+    route(&mut app, "task-lists").get(all_task_lists);
+    route(&mut app, "calendar/tasks.ics").get(calendar::tasks);
+    route(&mut app, "reports/estimates").get(reports::estimates::handler);
+    route(&mut app, "reports/overdue").get(reports::overdue::handler);
+    route(&mut app, "reports/time").get(reports::time::handler);
+    route(&mut app, "reports/utilization").get(reports::utilization::handler);
+    route(&mut app, "search").get(search::handler);
+    route(&mut app, "schema/drift").get(schema_drift);
+    route(&mut app, "metrics").get(metrics::handler);
+    route(&mut app, "graphql")
+        .get(graphql::handler)
+        .post(graphql::handler);
+    route(&mut app, "healthz").get(health::healthz);
+    route(&mut app, "readyz").get(health::readyz);
 
     app.listen(addr).await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use tide::http::{Method, Url};
+
+    use super::*;
+
+    #[test]
+    fn canonical_queries_ignore_param_order() {
+        let parse = |query: &str| -> Query {
+            let url = Url::parse(&format!("http://localhost/tasks?{}", query)).unwrap();
+            tide::http::Request::new(Method::Get, url).query().unwrap()
+        };
+
+        let query = parse("projectIds=1,2&filter=today&tag=a%26b&page=2");
+
+        assert_eq!(
+            query.canonical(),
+            parse("page=2&tag=a%26b&filter=today&projectIds=1,2").canonical()
+        );
+        assert_eq!(
+            query.canonical(),
+            "filter=today&page=2&projectIds=1%2C2&tag=a%26b"
+        );
+    }
+
+    #[test]
+    fn links_keep_the_params_encoded() {
+        let url = Url::parse(
+            "http://localhost/tasks?filter=content%20contains%20%22a%26b%22&page=2&pageSize=10",
+        )
+        .unwrap();
+        let links = Links::new(
+            &url,
+            &Meta {
+                page: 2,
+                total_pages: 3,
+            },
+        );
+
+        let link = "http://localhost/tasks?filter=content+contains+%22a%26b%22&pageSize=10";
+        assert_eq!(links.curr, format!("{}&page=2", link));
+        assert_eq!(links.prev, Some(format!("{}&page=1", link)));
+        assert_eq!(links.next, Some(format!("{}&page=3", link)));
+        assert_eq!(links.last, format!("{}&page=3", link));
+
+        let links = Links::new(
+            &Url::parse("http://localhost/tasks?page=1").unwrap(),
+            &Meta {
+                page: 1,
+                total_pages: 1,
+            },
+        );
+
+        assert_eq!(links.first, "http://localhost/tasks?page=1");
+        assert_eq!(links.prev, None);
+        assert_eq!(links.next, None);
+        assert_eq!(
+            links.header(),
+            "<http://localhost/tasks?page=1>;rel=self,<http://localhost/tasks?page=1>;rel=first,<http://localhost/tasks?page=1>;rel=last"
+        );
+    }
+
+    #[test]
+    fn errors_map_to_status_and_code() {
+        let io = || std::io::Error::other("/var/lib/teamwork/mirror.db: permission denied");
+
+        for (error, status, code) in vec![
+            (
+                Error::TeamworkError(401, "Unauthorized", None),
+                401,
+                "unauthorized",
+            ),
+            (
+                Error::TeamworkError(404, "Not Found", None),
+                404,
+                "not_found",
+            ),
+            (
+                Error::TeamworkError(429, "Too Many Requests", None),
+                429,
+                "rate_limited",
+            ),
+            (
+                Error::TeamworkError(500, "Internal Server Error", None),
+                502,
+                "upstream_error",
+            ),
+            (
+                Error::UpstreamError("refused".into()),
+                502,
+                "upstream_unavailable",
+            ),
+            (Error::UpstreamTimeout, 504, "upstream_timeout"),
+            (
+                Error::CircuitOpen(Duration::from_secs(1)),
+                503,
+                "upstream_unavailable",
+            ),
+            (
+                Error::MissingHeader("X-Page"),
+                502,
+                "upstream_protocol_error",
+            ),
+            (
+                Error::SchemaError("id".into()),
+                502,
+                "upstream_schema_mismatch",
+            ),
+            (
+                Error::MirrorUnavailable("not synced".into()),
+                503,
+                "mirror_unavailable",
+            ),
+            (Error::QueryError("page".into()), 400, "invalid_query"),
+            (Error::AuthError, 401, "unauthorized"),
+            (Error::Forbidden("reports"), 403, "forbidden"),
+            (Error::FormatError("CSV"), 406, "unsupported_format"),
+            (Error::IOError(io()), 500, "internal_error"),
+            (
+                Error::ConfigError(config::ConfigError::Frozen),
+                500,
+                "internal_error",
+            ),
+            (Error::DeliveryError("smtp".into()), 500, "internal_error"),
+            (
+                Error::MirrorError(rusqlite::Error::InvalidQuery),
+                500,
+                "internal_error",
+            ),
+        ] {
+            assert_eq!(
+                (error.status(), error.code()),
+                (status, code),
+                "{:?}",
+                error
+            );
+        }
+    }
+
+    fn respond(error: fn() -> Error) -> serde_json::Value {
+        let mut app = tide::new();
+        app.with(tide::utils::After(error_handler));
+        app.at("/")
+            .get(move |_| async move { Err::<String, _>(tide::Error::from(error())) });
+
+        let req = tide::http::Request::new(Method::Get, Url::parse("http://localhost/").unwrap());
+        let mut res: tide::http::Response = async_std::task::block_on(app.respond(req)).unwrap();
+
+        async_std::task::block_on(res.body_json()).unwrap()
+    }
+
+    #[test]
+    fn internal_errors_hide_their_details() {
+        let body = respond(|| {
+            Error::IOError(std::io::Error::other(
+                "/var/lib/teamwork/mirror.db: permission denied",
+            ))
+        });
+
+        assert_eq!(
+            body,
+            serde_json::json!({ "error": {
+                "code": "internal_error",
+                "status": 500,
+                "message": "Internal Server Error",
+            }})
+        );
+    }
+
+    #[test]
+    fn mirror_fallbacks_include_related_records() {
+        let state = test_state(&[("api_key", "key"), ("mirror_path", ":memory:")]);
+        let mirror = state.mirror.clone().unwrap();
+
+        mirror
+            .store::<Task>(
+                &[
+                    serde_json::json!({"id": 1, "content": "Design", "todo_list_id": 5, "project_id": 9}),
+                    serde_json::json!({"id": 2, "content": "Build", "todo_list_id": 5, "project_id": 9}),
+                ],
+                true,
+            )
+            .unwrap();
+        mirror
+            .store::<TaskList>(&[serde_json::json!({"id": "5", "name": "Launch"})], true)
+            .unwrap();
+
+        let mut app = tide::with_state(state);
+        app.with(tide::utils::After(error_handler));
+        app.at("tasks").get(all_tasks);
+
+        let url = Url::parse("http://localhost/tasks?include=task_list,project").unwrap();
+        let mut res: tide::http::Response =
+            async_std::task::block_on(app.respond(tide::http::Request::new(Method::Get, url)))
+                .unwrap();
+        let body: serde_json::Value = async_std::task::block_on(res.body_json()).unwrap();
+
+        assert_eq!(res.status(), 200);
+        assert_eq!(body["data"].as_array().unwrap().len(), 2);
+        // task lists are mirrored, projects are looked up in Teamwork, which
+        // is down
+        assert_eq!(body["included"]["task_list"]["5"]["name"], "Launch");
+        assert_eq!(body["included"]["project"], serde_json::json!({}));
+        assert_eq!(
+            body["included_errors"]["project"]["code"],
+            "upstream_unavailable"
+        );
+    }
+
+    #[test]
+    fn mirror_pages_clamp_the_page_size() {
+        let state = test_state(&[("api_key", "key"), ("mirror_path", ":memory:")]);
+        let mirror = state.mirror.clone().unwrap();
+
+        mirror
+            .store::<Task>(
+                &[
+                    serde_json::json!({"id": 1, "content": "Design"}),
+                    serde_json::json!({"id": 2, "content": "Build"}),
+                ],
+                true,
+            )
+            .unwrap();
+
+        let mut app = tide::with_state(state);
+        app.with(tide::utils::After(error_handler));
+        app.at("tasks").get(all_tasks);
+
+        // the offset of a page larger than the mirror's would overflow
+        for query in &[
+            "per_page=9223372036854775807&page=3",
+            "filter=content%20contains%20%22i%22&per_page=9223372036854775807&page=3",
+        ] {
+            let url = Url::parse(&format!("http://localhost/tasks?{}", query)).unwrap();
+            let mut res: tide::http::Response =
+                async_std::task::block_on(app.respond(tide::http::Request::new(Method::Get, url)))
+                    .unwrap();
+            let body: serde_json::Value = async_std::task::block_on(res.body_json()).unwrap();
+
+            assert_eq!(res.status(), 200, "{}", query);
+            assert_eq!(body["data"], serde_json::json!([]), "{}", query);
+        }
+    }
+
+    #[test]
+    fn client_errors_keep_their_message() {
+        let body = respond(|| Error::QueryError("page must be a number".into()));
+
+        assert_eq!(body["error"]["status"], 400);
+        assert_eq!(
+            body["error"]["message"],
+            "Invalid query: page must be a number"
+        );
+    }
+}
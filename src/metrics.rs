@@ -0,0 +1,301 @@
+//! Prometheus metrics for inbound requests, the upstream Teamwork calls and the
+//! errors encountered, exposed by the `/metrics` route.
+
+use std::time::{Duration, Instant};
+
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use teamwork_schema::drift::DriftSummary;
+use tide::{Middleware, Next, Request, Response, Route, Server};
+
+use crate::{cache::CacheStatus, State};
+
+lazy_static::lazy_static! {
+    static ref REGISTRY: Registry = Registry::new();
+
+    static ref HTTP_REQUESTS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("http_requests_total", "Inbound requests by route and status"),
+        &["route", "method", "status"],
+    ));
+
+    static ref HTTP_REQUEST_DURATION: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new("http_request_duration_seconds", "Inbound request latency by route"),
+        &["route"],
+    ));
+
+    static ref UPSTREAM_REQUESTS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("teamwork_requests_total", "Teamwork API calls by route and status"),
+        &["teamwork_route", "status"],
+    ));
+
+    static ref UPSTREAM_DURATION: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new("teamwork_request_duration_seconds", "Teamwork API latency by route"),
+        &["teamwork_route"],
+    ));
+
+    static ref RATE_LIMITED: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("teamwork_rate_limited_total", "Teamwork API calls rejected as rate limited by route"),
+        &["teamwork_route"],
+    ));
+
+    static ref ERRORS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("errors_total", "Errors returned by the handlers by variant"),
+        &["variant"],
+    ));
+
+    static ref STALE_CACHE: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("stale_cache_lookups_total", "Stale cache lookups while a circuit was open by result"),
+        &["teamwork_route", "result"],
+    ));
+
+    static ref MIRROR_RESPONSES: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("mirror_responses_total", "Responses served from the local mirror while Teamwork was unavailable"),
+        &["teamwork_route"],
+    ));
+
+    static ref CIRCUIT_OPENED: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("teamwork_circuit_opened_total", "Times the circuit to a Teamwork route opened"),
+        &["teamwork_route"],
+    ));
+
+    static ref CIRCUIT_REJECTIONS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("teamwork_circuit_rejections_total", "Requests rejected while a circuit was open"),
+        &["teamwork_route"],
+    ));
+
+    static ref SCHEMA_DRIFT: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new("teamwork_schema_drift", "Schema drift observed since the process started"),
+        &["schema", "field", "kind"],
+    ));
+}
+
+/// The most fields the schema drift is reported for. Field names come from
+/// Teamwork, so drift in further fields is reported as `other` to bound the
+/// label cardinality, the details are available at `/schema/drift`.
+const MAX_DRIFT_FIELDS: usize = 50;
+
+fn register<M>(metric: prometheus::Result<M>) -> M
+where
+    M: prometheus::core::Collector + Clone + 'static,
+{
+    let metric = metric.expect("metric options are valid");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("metrics are only registered once");
+    metric
+}
+
+/// The status label of an upstream call, `error` when no response was received.
+fn status_label(status: Option<u16>) -> String {
+    status.map_or_else(|| "error".to_string(), |s| s.to_string())
+}
+
+pub fn upstream_request(teamwork_route: &str, status: Option<u16>, latency: Duration) {
+    UPSTREAM_REQUESTS
+        .with_label_values(&[teamwork_route, &status_label(status)])
+        .inc();
+    UPSTREAM_DURATION
+        .with_label_values(&[teamwork_route])
+        .observe(latency.as_secs_f64());
+
+    if status == Some(429) {
+        RATE_LIMITED.with_label_values(&[teamwork_route]).inc();
+    }
+}
+
+pub fn error(variant: &str) {
+    ERRORS.with_label_values(&[variant]).inc();
+}
+
+pub fn stale_cache(teamwork_route: &str, status: CacheStatus) {
+    STALE_CACHE
+        .with_label_values(&[teamwork_route, status.as_str()])
+        .inc();
+}
+
+pub fn mirror_response(teamwork_route: &str) {
+    MIRROR_RESPONSES.with_label_values(&[teamwork_route]).inc();
+}
+
+pub fn circuit_opened(teamwork_route: &str) {
+    CIRCUIT_OPENED.with_label_values(&[teamwork_route]).inc();
+}
+
+pub fn circuit_rejection(teamwork_route: &str) {
+    CIRCUIT_REJECTIONS
+        .with_label_values(&[teamwork_route])
+        .inc();
+}
+
+/// The template of the route that served a response, such as `/tasks/:id`.
+#[derive(Debug, Clone)]
+struct RouteTemplate(String);
+
+#[tide::utils::async_trait]
+impl<S: Clone + Send + Sync + 'static> Middleware<S> for RouteTemplate {
+    async fn handle(&self, req: Request<S>, next: Next<'_, S>) -> tide::Result {
+        let mut res = next.run(req).await;
+        res.insert_ext(self.clone());
+        Ok(res)
+    }
+}
+
+/// Adds the route at `path`, labelling the metrics of its requests by the
+/// route's template rather than by the path requested.
+pub fn route<'a, S>(app: &'a mut Server<S>, path: &str) -> Route<'a, S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let mut route = app.at(path);
+    let template = RouteTemplate(format!("/{}", route.path().trim_start_matches('/')));
+    route.with(template);
+    route
+}
+
+/// Middleware recording the count and latency of inbound requests.
+#[derive(Debug, Default)]
+pub struct RequestMetrics;
+
+#[tide::utils::async_trait]
+impl<S: Clone + Send + Sync + 'static> Middleware<S> for RequestMetrics {
+    async fn handle(&self, req: Request<S>, next: Next<'_, S>) -> tide::Result {
+        let start = Instant::now();
+        let method = req.method().to_string();
+
+        let res = next.run(req).await;
+
+        // requests are labelled by route template to bound the label
+        // cardinality, unmatched paths are grouped together
+        let route = res
+            .ext::<RouteTemplate>()
+            .map_or("unmatched", |template| template.0.as_str());
+
+        let status: u16 = res.status().into();
+
+        HTTP_REQUESTS
+            .with_label_values(&[route, &method, &status.to_string()])
+            .inc();
+        HTTP_REQUEST_DURATION
+            .with_label_values(&[route])
+            .observe(start.elapsed().as_secs_f64());
+
+        Ok(res)
+    }
+}
+
+fn schema_drift(summaries: &[DriftSummary]) -> serde_json::Result<()> {
+    SCHEMA_DRIFT.reset();
+
+    for (i, summary) in summaries.iter().enumerate() {
+        let kind = serde_json::to_value(summary.kind)?;
+        let field = if i < MAX_DRIFT_FIELDS {
+            summary.field.as_str()
+        } else {
+            "other"
+        };
+
+        SCHEMA_DRIFT
+            .with_label_values(&[summary.schema, field, kind.as_str().unwrap_or_default()])
+            .add(summary.count as i64);
+    }
+
+    Ok(())
+}
+
+/// Renders the metrics in the Prometheus text format.
+pub async fn handler(_req: Request<State>) -> tide::Result {
+    schema_drift(&teamwork_schema::drift::summaries())?;
+
+    let mut buffer = vec![];
+    let encoder = TextEncoder::new();
+    encoder.encode(&REGISTRY.gather(), &mut buffer)?;
+
+    Ok(Response::builder(200)
+        .body(buffer)
+        .content_type(encoder.format_type())
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::core::Collector;
+    use teamwork_schema::drift::DriftKind;
+
+    use super::*;
+
+    #[test]
+    fn labels_requests_by_route_template() {
+        let mut app = tide::new();
+        app.with(RequestMetrics);
+        route(&mut app, "metrics-test/:id").get(|_| async { Ok("") });
+
+        for path in &["metrics-test/1", "metrics-test/2", "metrics-test"] {
+            let url = tide::http::Url::parse("http://localhost/")
+                .unwrap()
+                .join(path)
+                .unwrap();
+            let req = tide::http::Request::new(tide::http::Method::Get, url);
+            let _: tide::http::Response = async_std::task::block_on(app.respond(req)).unwrap();
+        }
+
+        assert_eq!(
+            HTTP_REQUESTS
+                .with_label_values(&["/metrics-test/:id", "GET", "200"])
+                .get(),
+            2
+        );
+        assert_eq!(
+            HTTP_REQUESTS
+                .with_label_values(&["unmatched", "GET", "404"])
+                .get(),
+            1
+        );
+    }
+
+    #[test]
+    fn counts_rate_limited_teamwork_calls() {
+        upstream_request("rate-limit-test.json", Some(429), Duration::from_millis(1));
+        upstream_request("rate-limit-test.json", Some(200), Duration::from_millis(1));
+
+        assert_eq!(
+            RATE_LIMITED
+                .with_label_values(&["rate-limit-test.json"])
+                .get(),
+            1
+        );
+    }
+
+    #[test]
+    fn bounds_the_schema_drift_fields() {
+        let summaries: Vec<DriftSummary> = (0..MAX_DRIFT_FIELDS + 2)
+            .map(|i| DriftSummary {
+                schema: "Task",
+                field: format!("field-{:03}", i),
+                kind: DriftKind::UnknownField,
+                count: 2,
+                last_found: "string",
+                last_message: None,
+            })
+            .collect();
+
+        schema_drift(&summaries).unwrap();
+
+        let gauge = |field: &str| {
+            SCHEMA_DRIFT
+                .with_label_values(&["Task", field, "unknown_field"])
+                .get()
+        };
+
+        assert_eq!(gauge("field-000"), 2);
+        assert_eq!(gauge("other"), 4);
+        assert_eq!(
+            SCHEMA_DRIFT
+                .collect()
+                .iter()
+                .map(|family| family.get_metric().len())
+                .sum::<usize>(),
+            MAX_DRIFT_FIELDS + 1
+        );
+    }
+}
@@ -0,0 +1,656 @@
+//! A local SQLite mirror of the Teamwork collections. Each collection is a
+//! table with a column per normalized field, nested values stored as JSON,
+//! alongside the whole normalized record in `data`. It answers requests while
+//! Teamwork is unavailable and can be queried directly for analytics, and
+//! keeps the full-text index searched by `/search`.
+//!
+//! Teamwork sends some ids as numbers and others as strings, so ids and the
+//! columns referring to other records are also kept as text in `_` prefixed
+//! key columns, which joins compare.
+
+use std::sync::Mutex;
+
+use chrono::Utc;
+use rusqlite::{params, params_from_iter, types::Value as SqlValue, Connection, OptionalExtension};
+use serde_json::{Map, Value};
+use teamwork_schema::{
+    meta::{FieldKind, SchemaMeta},
+    Task, TaskList, TimeEntry,
+};
+
+use self::search::Searchable;
+use crate::upstream::Resource;
+
+pub mod query;
+pub mod search;
+pub mod sync;
+
+/// A record of another table that a record refers to.
+#[derive(Debug, Clone, Copy)]
+pub struct Relation {
+    /// The name filters reach the related record through, e.g. `task_list` in
+    /// `task_list.status`.
+    pub name: &'static str,
+    pub table: &'static str,
+    /// The column holding the id of the related record.
+    pub column: &'static str,
+}
+
+/// A collection mirrored into a table.
+pub trait Mirrored: Resource {
+    const TABLE: &'static str;
+    /// The normalized field holding when a record last changed, used to only
+    /// fetch the records changed since the last sync. Collections without it
+    /// are always synced in full.
+    const UPDATED_FIELD: Option<&'static str>;
+    /// Params sent with every sync, so the mirror holds every record.
+    const SYNC_PARAMS: &'static [(&'static str, &'static str)];
+    /// Limits the records served in place of Teamwork to those Teamwork
+    /// returns by default.
+    const DEFAULT_FILTER: Option<&'static str>;
+    const RELATIONS: &'static [Relation];
+    const SEARCH: Searchable;
+}
+
+impl Mirrored for Task {
+    const TABLE: &'static str = "tasks";
+    const UPDATED_FIELD: Option<&'static str> = Some("updated_at");
+    const SYNC_PARAMS: &'static [(&'static str, &'static str)] =
+        &[("includeCompletedTasks", "true")];
+    const DEFAULT_FILTER: Option<&'static str> = Some("completed IS NOT 1");
+    const RELATIONS: &'static [Relation] = &[
+        Relation {
+            name: "task_list",
+            table: "task_lists",
+            column: "todo_list_id",
+        },
+        Relation {
+            name: "parent_task",
+            table: "tasks",
+            column: "parent_task_id",
+        },
+    ];
+    const SEARCH: Searchable = Searchable {
+        kind: "task",
+        title: "content",
+        body: Some("description"),
+    };
+}
+
+impl Mirrored for TimeEntry {
+    const TABLE: &'static str = "time_entries";
+    const UPDATED_FIELD: Option<&'static str> = Some("updated_date");
+    const SYNC_PARAMS: &'static [(&'static str, &'static str)] = &[];
+    const DEFAULT_FILTER: Option<&'static str> = None;
+    const RELATIONS: &'static [Relation] = &[
+        Relation {
+            name: "task",
+            table: "tasks",
+            column: "todo_item_id",
+        },
+        Relation {
+            name: "task_list",
+            table: "task_lists",
+            column: "todo_list_id",
+        },
+    ];
+    const SEARCH: Searchable = Searchable {
+        kind: "time_entry",
+        title: "description",
+        body: None,
+    };
+}
+
+impl Mirrored for TaskList {
+    const TABLE: &'static str = "task_lists";
+    const UPDATED_FIELD: Option<&'static str> = None;
+    const SYNC_PARAMS: &'static [(&'static str, &'static str)] = &[];
+    const DEFAULT_FILTER: Option<&'static str> = None;
+    const RELATIONS: &'static [Relation] = &[];
+    const SEARCH: Searchable = Searchable {
+        kind: "task_list",
+        title: "name",
+        body: Some("description"),
+    };
+}
+
+/// What's needed to serve a Teamwork route from its table.
+#[derive(Debug, Clone, Copy)]
+struct Table {
+    route: &'static str,
+    name: &'static str,
+    meta: SchemaMeta,
+    default_filter: Option<&'static str>,
+    relations: &'static [Relation],
+    search: Searchable,
+}
+
+impl Table {
+    /// The columns kept alongside a key column: the id and the columns
+    /// referring to other records.
+    fn keys(&self) -> Vec<&'static str> {
+        let mut keys = vec!["id"];
+
+        for relation in self.relations {
+            if !keys.contains(&relation.column) {
+                keys.push(relation.column);
+            }
+        }
+
+        keys
+    }
+
+    fn of<R: Mirrored>() -> Self {
+        Table {
+            route: R::ROUTE,
+            name: R::TABLE,
+            meta: R::META,
+            default_filter: R::DEFAULT_FILTER,
+            relations: R::RELATIONS,
+            search: R::SEARCH,
+        }
+    }
+}
+
+fn tables() -> [Table; 3] {
+    [
+        Table::of::<Task>(),
+        Table::of::<TimeEntry>(),
+        Table::of::<TaskList>(),
+    ]
+}
+
+/// The table serving a Teamwork route.
+fn table(teamwork_route: &str) -> Option<Table> {
+    tables().iter().copied().find(|t| t.route == teamwork_route)
+}
+
+/// A `WHERE` clause along with the tables it joins and the values bound to
+/// its placeholders.
+#[derive(Debug, Default)]
+struct Condition {
+    joins: String,
+    clause: Option<String>,
+    params: Vec<SqlValue>,
+}
+
+fn column_type(kind: FieldKind) -> &'static str {
+    match kind {
+        FieldKind::Integer | FieldKind::Bool => "INTEGER",
+        FieldKind::Float => "REAL",
+        _ => "TEXT",
+    }
+}
+
+/// The key column of an id column.
+pub(super) fn key_column(column: &str) -> String {
+    format!("_{}", column)
+}
+
+/// An id as text, whether Teamwork sent it as a number or a string.
+fn key(value: Option<&Value>) -> SqlValue {
+    match value {
+        Some(Value::Number(n)) => SqlValue::Text(n.to_string()),
+        Some(Value::String(s)) if !s.trim().is_empty() => SqlValue::Text(s.trim().to_string()),
+        _ => SqlValue::Null,
+    }
+}
+
+/// The value of a field bound to its column, with nested values as JSON.
+fn sql_value(value: Option<&Value>) -> SqlValue {
+    match value {
+        None | Some(Value::Null) => SqlValue::Null,
+        Some(Value::Bool(b)) => SqlValue::Integer(*b as i64),
+        Some(Value::Number(n)) => match n.as_i64() {
+            Some(n) => SqlValue::Integer(n),
+            None => SqlValue::Real(n.as_f64().unwrap_or_default()),
+        },
+        Some(Value::String(s)) => SqlValue::Text(s.clone()),
+        Some(value) => SqlValue::Text(value.to_string()),
+    }
+}
+
+/// The sync state of a table.
+#[derive(Debug, Clone, Default)]
+pub struct SyncState {
+    /// The latest `UPDATED_FIELD` of the mirrored records.
+    pub updated: Option<String>,
+    pub last_sync: Option<String>,
+    pub last_full_sync: Option<String>,
+}
+
+/// A page of a mirrored collection.
+#[derive(Debug)]
+pub struct MirrorPage {
+    pub records: Vec<Value>,
+    pub total_pages: usize,
+    /// When the table was last synced, as RFC 3339.
+    pub synced_at: String,
+}
+
+pub struct Mirror {
+    conn: Mutex<Connection>,
+}
+
+impl Mirror {
+    /// Opens the database at `path`, creating the tables and adding columns
+    /// for fields added to the schemas since it was created.
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let mut conn = Connection::open(path)?;
+
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS sync_state (
+                resource TEXT PRIMARY KEY,
+                updated TEXT,
+                last_sync TEXT,
+                last_full_sync TEXT
+            )",
+        )?;
+
+        for table in tables() {
+            let columns: Vec<String> = table
+                .meta
+                .fields
+                .iter()
+                .map(|f| {
+                    let primary = if f.name == "id" { " PRIMARY KEY" } else { "" };
+                    format!("\"{}\" {}{}", f.name, column_type(f.kind), primary)
+                })
+                .collect();
+
+            conn.execute_batch(&format!(
+                "CREATE TABLE IF NOT EXISTS {} ({}, data TEXT NOT NULL, synced_at TEXT NOT NULL)",
+                table.name,
+                columns.join(", ")
+            ))?;
+
+            let existing: Vec<String> = conn
+                .prepare(&format!(
+                    "SELECT name FROM pragma_table_info('{}')",
+                    table.name
+                ))?
+                .query_map([], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+
+            for field in table.meta.fields {
+                if !existing.iter().any(|c| c == field.name) {
+                    conn.execute_batch(&format!(
+                        "ALTER TABLE {} ADD COLUMN \"{}\" {}",
+                        table.name,
+                        field.name,
+                        column_type(field.kind)
+                    ))?;
+                }
+            }
+
+            // filled in from the records already mirrored when they're added
+            for column in table.keys() {
+                let key = key_column(column);
+
+                if !existing.contains(&key) {
+                    conn.execute_batch(&format!(
+                        "ALTER TABLE {table} ADD COLUMN \"{key}\" TEXT;
+                         UPDATE {table} SET \"{key}\" = CAST(\"{column}\" AS TEXT)",
+                        table = table.name,
+                        key = key,
+                        column = column
+                    ))?;
+                }
+            }
+
+            conn.execute_batch(&format!(
+                "CREATE INDEX IF NOT EXISTS {table}_key ON {table} (\"{key}\")",
+                table = table.name,
+                key = key_column("id")
+            ))?;
+        }
+
+        search::create(&mut conn)?;
+
+        Ok(Mirror {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn with<T, F>(&self, f: F) -> rusqlite::Result<T>
+    where
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T>,
+    {
+        let mut conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        f(&mut conn)
+    }
+
+    pub fn sync_state(&self, table: &str) -> rusqlite::Result<SyncState> {
+        self.with(|conn| {
+            conn.query_row(
+                "SELECT updated, last_sync, last_full_sync FROM sync_state WHERE resource = ?1",
+                [table],
+                |row| {
+                    Ok(SyncState {
+                        updated: row.get(0)?,
+                        last_sync: row.get(1)?,
+                        last_full_sync: row.get(2)?,
+                    })
+                },
+            )
+            .optional()
+            .map(Option::unwrap_or_default)
+        })
+    }
+
+    /// Stores the normalized records of a collection, replacing every record
+    /// when `full` so deleted records are dropped.
+    pub fn store<R: Mirrored>(&self, records: &[Value], full: bool) -> rusqlite::Result<()> {
+        let fields = R::META.fields;
+        let keys = Table::of::<R>().keys();
+        let now = Utc::now().to_rfc3339();
+
+        self.with(|conn| {
+            let tx = conn.transaction()?;
+
+            if full {
+                tx.execute(&format!("DELETE FROM {}", R::TABLE), [])?;
+            }
+
+            {
+                let columns: Vec<String> = fields
+                    .iter()
+                    .map(|f| f.name.to_string())
+                    .chain(keys.iter().map(|k| key_column(k)))
+                    .map(|c| format!("\"{}\"", c))
+                    .collect();
+                let placeholders: Vec<String> =
+                    (1..=columns.len() + 2).map(|i| format!("?{}", i)).collect();
+
+                let mut insert = tx.prepare(&format!(
+                    "INSERT OR REPLACE INTO {} ({}, data, synced_at) VALUES ({})",
+                    R::TABLE,
+                    columns.join(", "),
+                    placeholders.join(", ")
+                ))?;
+
+                for record in records {
+                    let values = fields
+                        .iter()
+                        .map(|f| sql_value(record.get(f.name)))
+                        .chain(keys.iter().map(|k| key(record.get(*k))))
+                        .chain([
+                            SqlValue::Text(record.to_string()),
+                            SqlValue::Text(now.clone()),
+                        ]);
+
+                    insert.execute(params_from_iter(values))?;
+                }
+            }
+
+            search::index(&tx, R::SEARCH, records, full)?;
+
+            let updated: Option<String> = match R::UPDATED_FIELD {
+                Some(field) => tx.query_row(
+                    &format!("SELECT max(\"{}\") FROM {}", field, R::TABLE),
+                    [],
+                    |row| row.get(0),
+                )?,
+                None => None,
+            };
+
+            tx.execute(
+                "INSERT INTO sync_state (resource, updated, last_sync, last_full_sync)
+                 VALUES (?1, ?2, ?3, CASE WHEN ?4 THEN ?3 END)
+                 ON CONFLICT(resource) DO UPDATE SET
+                    updated = excluded.updated,
+                    last_sync = excluded.last_sync,
+                    last_full_sync = coalesce(excluded.last_full_sync, last_full_sync)",
+                params![R::TABLE, updated, now, full],
+            )?;
+
+            tx.commit()
+        })
+    }
+
+    /// A page of the records of `teamwork_route` that Teamwork returns by
+    /// default, `None` when the route isn't mirrored or hasn't been synced
+    /// yet.
+    pub fn page(
+        &self,
+        teamwork_route: &str,
+        page: usize,
+        per_page: usize,
+    ) -> rusqlite::Result<Option<MirrorPage>> {
+        let table = match table(teamwork_route) {
+            Some(table) => table,
+            None => return Ok(None),
+        };
+
+        let condition = Condition {
+            clause: table.default_filter.map(str::to_string),
+            ..Condition::default()
+        };
+
+        self.select(table, condition, page, per_page)
+    }
+
+    /// The records of `teamwork_route` with the given ids, `None` when the
+    /// route isn't mirrored or hasn't been synced yet.
+    pub fn records(
+        &self,
+        teamwork_route: &str,
+        ids: &[String],
+    ) -> rusqlite::Result<Option<Vec<Value>>> {
+        let table = match table(teamwork_route) {
+            Some(table) => table,
+            None => return Ok(None),
+        };
+
+        if ids.is_empty() {
+            return Ok(Some(vec![]));
+        }
+
+        let condition = Condition {
+            clause: Some(format!(
+                "t.\"{}\" IN ({})",
+                key_column("id"),
+                vec!["?"; ids.len()].join(", ")
+            )),
+            params: ids.iter().cloned().map(SqlValue::Text).collect(),
+            ..Condition::default()
+        };
+
+        Ok(self
+            .select(table, condition, 1, ids.len())?
+            .map(|page| page.records))
+    }
+
+    /// A page of the records of `table` matching `condition`, ordered by id.
+    fn select(
+        &self,
+        table: Table,
+        condition: Condition,
+        page: usize,
+        per_page: usize,
+    ) -> rusqlite::Result<Option<MirrorPage>> {
+        let synced_at = match self.sync_state(table.name)?.last_sync {
+            Some(synced_at) => synced_at,
+            None => return Ok(None),
+        };
+
+        let from = format!(
+            "FROM {} t {} {}",
+            table.name,
+            condition.joins,
+            condition
+                .clause
+                .as_ref()
+                .map(|c| format!("WHERE {}", c))
+                .unwrap_or_default()
+        );
+
+        let per_page = per_page.max(1);
+        let offset = (page.max(1) - 1) as i64 * per_page as i64;
+
+        self.with(|conn| {
+            let count: i64 = conn.query_row(
+                &format!("SELECT count(*) {}", from),
+                params_from_iter(&condition.params),
+                |row| row.get(0),
+            )?;
+
+            let params = condition.params.iter().cloned().chain([
+                SqlValue::Integer(per_page as i64),
+                SqlValue::Integer(offset),
+            ]);
+
+            let records = conn
+                .prepare(&format!(
+                    "SELECT t.data {} ORDER BY CAST(t.id AS INTEGER), t.id LIMIT ? OFFSET ?",
+                    from
+                ))?
+                .query_map(params_from_iter(params), |row| row.get::<_, String>(0))?
+                .map(|data| {
+                    data.map(|data| {
+                        serde_json::from_str(&data).unwrap_or(Value::Object(Map::new()))
+                    })
+                })
+                .collect::<rusqlite::Result<Vec<Value>>>()?;
+
+            Ok(Some(MirrorPage {
+                records,
+                total_pages: (count as usize).div_ceil(per_page).max(1),
+                synced_at,
+            }))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn ids(mirror: &Mirror) -> Vec<Value> {
+        mirror
+            .page(Task::ROUTE, 1, 10)
+            .unwrap()
+            .unwrap()
+            .records
+            .iter()
+            .map(|t| t["id"].clone())
+            .collect()
+    }
+
+    #[test]
+    fn incremental_syncs_keep_the_other_records() {
+        let mirror = Mirror::open(":memory:").unwrap();
+        assert!(mirror.page(Task::ROUTE, 1, 10).unwrap().is_none());
+
+        mirror
+            .store::<Task>(
+                &[
+                    json!({"id": 1, "content": "One", "updated_at": "2024-05-01T10:00:00Z"}),
+                    json!({"id": 2, "content": "Two", "updated_at": "2024-05-02T10:00:00Z"}),
+                ],
+                true,
+            )
+            .unwrap();
+        mirror
+            .store::<Task>(
+                &[
+                    json!({"id": 2, "content": "Two again", "updated_at": "2024-05-03T10:00:00Z"}),
+                    json!({"id": 3, "content": "Three", "updated_at": "2024-05-01T12:00:00Z"}),
+                ],
+                false,
+            )
+            .unwrap();
+
+        assert_eq!(ids(&mirror), [json!(1), json!(2), json!(3)]);
+        assert_eq!(
+            mirror.page(Task::ROUTE, 1, 10).unwrap().unwrap().records[1]["content"],
+            "Two again"
+        );
+
+        let state = mirror.sync_state(Task::TABLE).unwrap();
+        assert_eq!(state.updated.as_deref(), Some("2024-05-03T10:00:00Z"));
+        assert!(state.last_sync.is_some());
+        assert!(state.last_full_sync.is_some());
+    }
+
+    #[test]
+    fn full_syncs_drop_deleted_records() {
+        let mirror = Mirror::open(":memory:").unwrap();
+
+        mirror
+            .store::<Task>(&[json!({"id": 1}), json!({"id": 2})], true)
+            .unwrap();
+        mirror.store::<Task>(&[json!({"id": 2})], true).unwrap();
+
+        assert_eq!(ids(&mirror), [json!(2)]);
+    }
+
+    #[test]
+    fn pages_skip_completed_tasks() {
+        let mirror = Mirror::open(":memory:").unwrap();
+
+        mirror
+            .store::<Task>(
+                &[
+                    json!({"id": 1, "completed": true}),
+                    json!({"id": 2, "completed": false}),
+                    json!({"id": 3}),
+                ],
+                true,
+            )
+            .unwrap();
+
+        assert_eq!(ids(&mirror), [json!(2), json!(3)]);
+
+        let page = mirror.page(Task::ROUTE, 2, 1).unwrap().unwrap();
+        assert_eq!(page.records[0]["id"], 3);
+        assert_eq!(page.total_pages, 2);
+    }
+
+    #[test]
+    fn adds_the_columns_missing_from_an_existing_database() {
+        let path = std::env::temp_dir().join(format!("mirror-columns-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        Connection::open(path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE tasks (id INTEGER PRIMARY KEY, data TEXT NOT NULL, synced_at TEXT NOT NULL);
+                 INSERT INTO tasks VALUES (1, '{\"id\": 1}', '2024-05-01T10:00:00Z');",
+            )
+            .unwrap();
+
+        let mirror = Mirror::open(path).unwrap();
+
+        let columns: Vec<String> = mirror
+            .with(|conn| {
+                conn.prepare("SELECT name FROM pragma_table_info('tasks')")?
+                    .query_map([], |row| row.get(0))?
+                    .collect()
+            })
+            .unwrap();
+
+        for field in Table::of::<Task>().meta.fields {
+            assert!(columns.iter().any(|c| c == field.name), "{}", field.name);
+        }
+
+        // and the keys of the records already mirrored are filled in
+        let key: String = mirror
+            .with(|conn| conn.query_row("SELECT _id FROM tasks WHERE id = 1", [], |row| row.get(0)))
+            .unwrap();
+        assert_eq!(key, "1");
+
+        // the records already mirrored are kept
+        mirror
+            .store::<Task>(&[json!({"id": 2, "content": "Two"})], false)
+            .unwrap();
+        assert_eq!(ids(&mirror), [json!(1), json!(2)]);
+
+        drop(mirror);
+        let _ = std::fs::remove_file(path);
+    }
+}
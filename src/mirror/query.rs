@@ -0,0 +1,676 @@
+//! Filters over the mirrored collections, given with `?filter=` on the
+//! collection routes, which Teamwork itself can't do. A filter compares the
+//! normalized fields of a record, the keys of its nested objects or the fields
+//! of the records it refers to, combined with `and`, `or`, `not` and
+//! parentheses, e.g.
+//!
+//! ```text
+//! task_list.status eq "new" and (estimated_minutes gt 60 or priority in ("high", "medium"))
+//! ```
+//!
+//! The comparisons are `eq`, `ne`, `lt`, `le`, `gt`, `ge` (or `=`, `!=`, `<`,
+//! `<=`, `>`, `>=`), `contains`, `in (...)` and `is [not] null`. Fields are
+//! checked against the schemas and values are bound, so filters can't inject
+//! SQL.
+
+use rusqlite::types::Value as SqlValue;
+use teamwork_schema::meta::{self, FieldKind, SchemaMeta};
+
+use super::{key_column, table, tables, Condition, Mirror, MirrorPage, Table};
+use crate::{Error, Result};
+
+/// The query parameter holding the filter.
+pub const FILTER_PARAM: &str = "filter";
+
+/// The longest filter accepted.
+const MAX_LENGTH: usize = 2000;
+
+/// How deeply a filter may nest.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Comma,
+    Word(String),
+    Symbol(&'static str),
+    String(String),
+    Number(f64),
+}
+
+fn tokenize(filter: &str) -> std::result::Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = filter.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' | ',' => {
+                chars.next();
+                tokens.push(match c {
+                    '(' => Token::Open,
+                    ')' => Token::Close,
+                    _ => Token::Comma,
+                });
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut value = String::new();
+
+                loop {
+                    match chars.next() {
+                        Some('\\') => value.extend(chars.next()),
+                        Some(q) if q == c => break,
+                        Some(c) => value.push(c),
+                        None => return Err("unterminated string".into()),
+                    }
+                }
+
+                tokens.push(Token::String(value));
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let next = chars.peek().copied();
+
+                let symbol = match (c, next) {
+                    ('=', Some('='))
+                    | ('!', Some('='))
+                    | ('<', Some('='))
+                    | ('>', Some('='))
+                    | ('<', Some('>')) => {
+                        chars.next();
+                        match (c, next) {
+                            ('=', _) => "eq",
+                            ('<', Some('=')) => "le",
+                            ('>', _) => "ge",
+                            _ => "ne",
+                        }
+                    }
+                    ('=', _) => "eq",
+                    ('<', _) => "lt",
+                    ('>', _) => "gt",
+                    _ => return Err("expected != after !".into()),
+                };
+
+                tokens.push(Token::Symbol(symbol));
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '.' => {
+                let mut number = String::new();
+
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_digit() || c == '-' || c == '.' || c == 'e' || c == 'E' {
+                        number.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+
+                tokens.push(Token::Number(
+                    number
+                        .parse()
+                        .map_err(|_| format!("invalid number {}", number))?,
+                ));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut word = String::new();
+
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+                        word.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+
+                tokens.push(Token::Word(word));
+            }
+            c => return Err(format!("unexpected {}", c)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    String(String),
+    Number(f64),
+    Bool(bool),
+}
+
+impl Literal {
+    fn sql(&self) -> SqlValue {
+        match self {
+            Literal::String(s) => SqlValue::Text(s.clone()),
+            Literal::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => {
+                SqlValue::Integer(*n as i64)
+            }
+            Literal::Number(n) => SqlValue::Real(*n),
+            Literal::Bool(b) => SqlValue::Integer(*b as i64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+impl Op {
+    fn parse(word: &str) -> Option<Self> {
+        Some(match word {
+            "eq" => Op::Eq,
+            "ne" => Op::Ne,
+            "lt" => Op::Lt,
+            "le" => Op::Le,
+            "gt" => Op::Gt,
+            "ge" => Op::Ge,
+            "contains" => Op::Contains,
+            _ => return None,
+        })
+    }
+}
+
+/// A parsed filter.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Compare(String, Op, Literal),
+    In(String, Vec<Literal>),
+    IsNull(String, bool),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    /// Consumes the keyword if it's next.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword) => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn or(&mut self) -> std::result::Result<Filter, String> {
+        let mut filter = self.and()?;
+
+        while self.keyword("or") {
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> std::result::Result<Filter, String> {
+        let mut filter = self.unary()?;
+
+        while self.keyword("and") {
+            filter = Filter::And(Box::new(filter), Box::new(self.unary()?));
+        }
+
+        Ok(filter)
+    }
+
+    fn unary(&mut self) -> std::result::Result<Filter, String> {
+        self.depth += 1;
+
+        if self.depth > MAX_DEPTH {
+            return Err("filter is nested too deeply".into());
+        }
+
+        let filter = if self.keyword("not") {
+            Filter::Not(Box::new(self.unary()?))
+        } else if self.peek() == Some(&Token::Open) {
+            self.next();
+            let filter = self.or()?;
+
+            if self.next() != Some(Token::Close) {
+                return Err("expected )".into());
+            }
+
+            filter
+        } else {
+            self.comparison()?
+        };
+
+        self.depth -= 1;
+        Ok(filter)
+    }
+
+    fn literal(&mut self) -> std::result::Result<Literal, String> {
+        match self.next() {
+            Some(Token::String(s)) => Ok(Literal::String(s)),
+            Some(Token::Number(n)) => Ok(Literal::Number(n)),
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case("true") => Ok(Literal::Bool(true)),
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case("false") => Ok(Literal::Bool(false)),
+            Some(token) => Err(format!("expected a value, found {:?}", token)),
+            None => Err("expected a value".into()),
+        }
+    }
+
+    fn comparison(&mut self) -> std::result::Result<Filter, String> {
+        let field = match self.next() {
+            Some(Token::Word(field)) => field,
+            Some(token) => return Err(format!("expected a field, found {:?}", token)),
+            None => return Err("expected a field".into()),
+        };
+
+        if self.keyword("is") {
+            let negated = self.keyword("not");
+
+            if !self.keyword("null") {
+                return Err(format!("expected null after {} is", field));
+            }
+
+            return Ok(Filter::IsNull(field, negated));
+        }
+
+        if self.keyword("in") {
+            if self.next() != Some(Token::Open) {
+                return Err(format!("expected ( after {} in", field));
+            }
+
+            let mut values = vec![self.literal()?];
+
+            loop {
+                match self.next() {
+                    Some(Token::Comma) => values.push(self.literal()?),
+                    Some(Token::Close) => break,
+                    _ => return Err("expected , or ) in the list".into()),
+                }
+            }
+
+            return Ok(Filter::In(field, values));
+        }
+
+        let op = match self.next() {
+            Some(Token::Symbol(symbol)) => Op::parse(symbol),
+            Some(Token::Word(word)) => Op::parse(&word.to_ascii_lowercase()),
+            _ => None,
+        }
+        .ok_or_else(|| format!("expected a comparison after {}", field))?;
+
+        Ok(Filter::Compare(field, op, self.literal()?))
+    }
+}
+
+impl Filter {
+    pub fn parse(filter: &str) -> std::result::Result<Self, String> {
+        if filter.len() > MAX_LENGTH {
+            return Err(format!("filter is longer than {} characters", MAX_LENGTH));
+        }
+
+        let mut parser = Parser {
+            tokens: tokenize(filter)?,
+            position: 0,
+            depth: 0,
+        };
+
+        let filter = parser.or()?;
+
+        match parser.peek() {
+            None => Ok(filter),
+            Some(token) => Err(format!("unexpected {:?}", token)),
+        }
+    }
+}
+
+/// A field resolved to the SQL selecting it.
+struct Column {
+    sql: String,
+    kind: FieldKind,
+}
+
+/// Compiles filters against a table, collecting the relations they join.
+struct Compiler {
+    table: Table,
+    joins: Vec<&'static str>,
+    params: Vec<SqlValue>,
+}
+
+impl Compiler {
+    fn column(&mut self, path: &str) -> std::result::Result<Column, String> {
+        let segments: Vec<&str> = path.split('.').collect();
+
+        let relation = self
+            .table
+            .relations
+            .iter()
+            .find(|r| segments.len() > 1 && r.name == segments[0]);
+
+        let (alias, schema, rest): (&str, SchemaMeta, &[&str]) = match relation {
+            Some(relation) => {
+                let related = tables()
+                    .iter()
+                    .find(|t| t.name == relation.table)
+                    .map(|t| t.meta)
+                    .ok_or_else(|| format!("unknown relation {}", relation.name))?;
+
+                if !self.joins.contains(&relation.name) {
+                    self.joins.push(relation.name);
+                }
+
+                (relation.name, related, &segments[1..])
+            }
+            None => ("t", self.table.meta, &segments[..]),
+        };
+
+        let field = schema
+            .fields
+            .iter()
+            .find(|f| f.name == rest[0])
+            .ok_or_else(|| format!("unknown field {}", path))?;
+
+        if rest.len() == 1 {
+            return Ok(Column {
+                sql: format!("{}.\"{}\"", alias, field.name),
+                kind: field.kind,
+            });
+        }
+
+        // keys of nested objects, checked against the nested schema when
+        // there is one
+        let mut nested = match field.kind {
+            FieldKind::Object(name) => meta::find(name),
+            _ => return Err(format!("{} isn't an object", rest[0])),
+        };
+
+        let mut kind = FieldKind::Any;
+
+        for key in &rest[1..] {
+            kind = match nested {
+                Some(schema) => {
+                    let field = schema
+                        .fields
+                        .iter()
+                        .find(|f| f.name == *key)
+                        .ok_or_else(|| format!("unknown field {}", path))?;
+
+                    nested = match field.kind {
+                        FieldKind::Object(name) => meta::find(name),
+                        _ => None,
+                    };
+
+                    field.kind
+                }
+                None => FieldKind::Any,
+            };
+        }
+
+        Ok(Column {
+            sql: format!(
+                "json_extract({}.\"{}\", '$.{}')",
+                alias,
+                field.name,
+                rest[1..].join(".")
+            ),
+            kind,
+        })
+    }
+
+    fn bind(&mut self, value: SqlValue) -> &'static str {
+        self.params.push(value);
+        "?"
+    }
+
+    fn compile(&mut self, filter: &Filter) -> std::result::Result<String, String> {
+        Ok(match filter {
+            Filter::And(a, b) => format!("({} AND {})", self.compile(a)?, self.compile(b)?),
+            Filter::Or(a, b) => format!("({} OR {})", self.compile(a)?, self.compile(b)?),
+            Filter::Not(a) => format!("(NOT {})", self.compile(a)?),
+            Filter::IsNull(field, negated) => format!(
+                "{} IS {}NULL",
+                self.column(field)?.sql,
+                if *negated { "NOT " } else { "" }
+            ),
+            Filter::In(field, values) => {
+                let column = self.column(field)?.sql;
+                let placeholders: Vec<&str> = values.iter().map(|v| self.bind(v.sql())).collect();
+
+                format!("{} IN ({})", column, placeholders.join(", "))
+            }
+            Filter::Compare(field, Op::Contains, value) => {
+                let column = self.column(field)?.sql;
+                let pattern = match value {
+                    Literal::String(s) => s.clone(),
+                    Literal::Number(n) => n.to_string(),
+                    Literal::Bool(b) => b.to_string(),
+                };
+                let pattern = pattern
+                    .replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_");
+
+                format!(
+                    "{} LIKE {} ESCAPE '\\'",
+                    column,
+                    self.bind(SqlValue::Text(format!("%{}%", pattern)))
+                )
+            }
+            Filter::Compare(field, op, value) => {
+                let column = self.column(field)?;
+
+                // Teamwork sends many numbers as strings, compare those as
+                // numbers when ranging over them
+                let sql = match (op, value, column.kind) {
+                    (Op::Lt | Op::Le | Op::Gt | Op::Ge, Literal::Number(_), kind)
+                        if kind != FieldKind::Integer && kind != FieldKind::Float =>
+                    {
+                        format!("CAST({} AS REAL)", column.sql)
+                    }
+                    _ => column.sql,
+                };
+
+                let op = match op {
+                    Op::Eq => "=",
+                    Op::Ne => "IS NOT",
+                    Op::Lt => "<",
+                    Op::Le => "<=",
+                    Op::Gt => ">",
+                    Op::Ge => ">=",
+                    Op::Contains => unreachable!("contains is compiled above"),
+                };
+
+                format!("{} {} {}", sql, op, self.bind(value.sql()))
+            }
+        })
+    }
+}
+
+impl Mirror {
+    /// A page of the records of `teamwork_route` matching the filter, across
+    /// every mirrored record including completed tasks. `None` when the route
+    /// isn't mirrored or hasn't been synced yet.
+    pub fn filter(
+        &self,
+        teamwork_route: &str,
+        filter: &Filter,
+        page: usize,
+        per_page: usize,
+    ) -> Result<Option<MirrorPage>> {
+        let table = match table(teamwork_route) {
+            Some(table) => table,
+            None => return Ok(None),
+        };
+
+        let mut compiler = Compiler {
+            table,
+            joins: vec![],
+            params: vec![],
+        };
+
+        let clause = compiler.compile(filter).map_err(Error::QueryError)?;
+
+        let joins = compiler
+            .joins
+            .iter()
+            .filter_map(|name| table.relations.iter().find(|r| r.name == *name))
+            .map(|r| {
+                format!(
+                    "LEFT JOIN {table} {alias} ON {alias}.\"{id}\" = t.\"{column}\"",
+                    table = r.table,
+                    alias = r.name,
+                    id = key_column("id"),
+                    column = key_column(r.column)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let condition = Condition {
+            joins,
+            clause: Some(clause),
+            params: compiler.params,
+        };
+
+        Ok(self.select(table, condition, page, per_page)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use teamwork_schema::{Task, TaskList, TimeEntry};
+
+    use super::*;
+
+    #[test]
+    fn parses_precedence_and_lists() {
+        assert_eq!(
+            Filter::parse("a = 1 or not b in ('x', \"y\") and c is not null").unwrap(),
+            Filter::Or(
+                Box::new(Filter::Compare("a".into(), Op::Eq, Literal::Number(1.0))),
+                Box::new(Filter::And(
+                    Box::new(Filter::Not(Box::new(Filter::In(
+                        "b".into(),
+                        vec![Literal::String("x".into()), Literal::String("y".into())]
+                    )))),
+                    Box::new(Filter::IsNull("c".into(), true)),
+                )),
+            )
+        );
+
+        assert!(Filter::parse("a eq").is_err());
+        assert!(Filter::parse("(a eq 1").is_err());
+        assert!(Filter::parse(&"(".repeat(100)).is_err());
+    }
+
+    #[test]
+    fn filters_through_relations() {
+        let mirror = Mirror::open(":memory:").unwrap();
+
+        mirror
+            .store::<TaskList>(
+                &[
+                    json!({"id": "1", "name": "Design", "status": "new"}),
+                    json!({"id": "2", "name": "Build", "status": "completed"}),
+                ],
+                true,
+            )
+            .unwrap();
+        mirror
+            .store::<Task>(
+                &[
+                    json!({"id": 1, "content": "a", "todo_list_id": 1, "estimated_minutes": 90}),
+                    json!({"id": 2, "content": "b", "todo_list_id": 1, "estimated_minutes": 30}),
+                    json!({"id": 3, "content": "c", "todo_list_id": 2, "estimated_minutes": 90}),
+                ],
+                true,
+            )
+            .unwrap();
+
+        let ids = |filter: &str| -> Vec<i64> {
+            let filter = Filter::parse(filter).unwrap();
+            mirror
+                .filter("tasks.json", &filter, 1, 10)
+                .unwrap()
+                .unwrap()
+                .records
+                .iter()
+                .map(|r| r["id"].as_i64().unwrap())
+                .collect()
+        };
+
+        assert_eq!(ids("task_list.status eq 'new'"), [1, 2]);
+        assert_eq!(
+            ids("task_list.name contains 'des' and estimated_minutes > 60"),
+            [1]
+        );
+        assert_eq!(ids("not task_list.status = 'new' or content = 'b'"), [2, 3]);
+
+        let unknown = Filter::parse("task_list.nope eq 1").unwrap();
+        assert!(mirror.filter("tasks.json", &unknown, 1, 10).is_err());
+    }
+
+    #[test]
+    fn joins_ids_sent_as_numbers_and_strings_by_index() {
+        let mirror = Mirror::open(":memory:").unwrap();
+
+        mirror
+            .store::<Task>(&[json!({"id": 7, "content": "Design"})], true)
+            .unwrap();
+        mirror
+            .store::<TimeEntry>(
+                &[
+                    json!({"id": "1", "todo_item_id": "7"}),
+                    json!({"id": "2", "todo_item_id": "8"}),
+                ],
+                true,
+            )
+            .unwrap();
+
+        let filter = Filter::parse("task.content eq 'Design'").unwrap();
+        let page = mirror
+            .filter("time_entries.json", &filter, 1, 10)
+            .unwrap()
+            .unwrap();
+        assert_eq!(page.records.len(), 1);
+        assert_eq!(page.records[0]["id"], "1");
+
+        let plan: Vec<String> = mirror
+            .with(|conn| {
+                conn.prepare(
+                    "EXPLAIN QUERY PLAN SELECT t.data FROM time_entries t
+                     LEFT JOIN tasks task ON task._id = t._todo_item_id",
+                )?
+                .query_map([], |row| row.get(3))?
+                .collect()
+            })
+            .unwrap();
+        assert!(
+            plan.iter().any(|step| step.contains("INDEX tasks_key")),
+            "{:?}",
+            plan
+        );
+    }
+}
@@ -0,0 +1,351 @@
+//! A full-text index of the mirrored records, kept in an FTS5 table alongside
+//! them and updated as they're stored. Each record is indexed with a title and
+//! an optional body, e.g. a task's content and description.
+
+use std::collections::BTreeMap;
+
+use rusqlite::{params, params_from_iter, types::Value as SqlValue, Connection, OptionalExtension};
+use serde::Serialize;
+use serde_json::Value;
+
+use super::{tables, Mirror};
+use crate::reports::text;
+
+/// Marks the matches in the highlighted titles and snippets, replaced with
+/// `<mark>` once the text is escaped. Private use characters, so they aren't
+/// found in the records.
+const HIGHLIGHT: (&str, &str) = ("\u{e000}", "\u{e001}");
+
+/// How many tokens snippets hold.
+const SNIPPET_TOKENS: i64 = 16;
+
+/// The fields of a collection's records that are searched.
+#[derive(Debug, Clone, Copy)]
+pub struct Searchable {
+    /// The type of the records in search results, e.g. `task`.
+    pub kind: &'static str,
+    pub title: &'static str,
+    pub body: Option<&'static str>,
+}
+
+/// Creates the index, indexing the records already mirrored when it's added
+/// to an existing mirror.
+pub(super) fn create(conn: &mut Connection) -> rusqlite::Result<()> {
+    let exists = conn
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'search'",
+            [],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some();
+
+    if exists {
+        return Ok(());
+    }
+
+    let tx = conn.transaction()?;
+
+    tx.execute_batch(
+        "CREATE VIRTUAL TABLE search USING fts5(
+            kind UNINDEXED,
+            id UNINDEXED,
+            project_id UNINDEXED,
+            title,
+            body,
+            tokenize = 'unicode61 remove_diacritics 2'
+        )",
+    )?;
+
+    for table in tables() {
+        let records = tx
+            .prepare(&format!("SELECT data FROM {}", table.name))?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .filter_map(|data| data.map(|d| serde_json::from_str(&d).ok()).transpose())
+            .collect::<rusqlite::Result<Vec<Value>>>()?;
+
+        index(&tx, table.search, &records, true)?;
+    }
+
+    tx.commit()
+}
+
+/// Indexes the records of a collection, replacing every record of the
+/// collection when `full`.
+pub(super) fn index(
+    conn: &Connection,
+    searchable: Searchable,
+    records: &[Value],
+    full: bool,
+) -> rusqlite::Result<()> {
+    if full {
+        conn.execute("DELETE FROM search WHERE kind = ?1", [searchable.kind])?;
+    }
+
+    let mut delete = conn.prepare("DELETE FROM search WHERE kind = ?1 AND id = ?2")?;
+    let mut insert = conn.prepare(
+        "INSERT INTO search (kind, id, project_id, title, body) VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+
+    // the same record can turn up on several pages, keep the last
+    let records: BTreeMap<String, &Value> = records
+        .iter()
+        .filter_map(|record| Some((text(record, "id")?, record)))
+        .collect();
+
+    for (id, record) in records {
+        if !full {
+            delete.execute(params![searchable.kind, id])?;
+        }
+
+        insert.execute(params![
+            searchable.kind,
+            id,
+            text(record, "project_id"),
+            text(record, searchable.title),
+            searchable.body.and_then(|body| text(record, body)),
+        ])?;
+    }
+
+    Ok(())
+}
+
+/// Turns what was typed into an FTS5 query matching records with every term,
+/// so the query syntax can't be misused. Double quotes group a phrase and a
+/// trailing `*` matches a prefix.
+pub fn match_expression(query: &str) -> Option<String> {
+    let mut terms = vec![];
+    let mut rest = query.trim();
+
+    while !rest.is_empty() {
+        let (term, prefix, remaining) = match rest.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                (&quoted[..end], false, quoted.get(end + 1..).unwrap_or(""))
+            }
+            None => {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                let word = &rest[..end];
+
+                match word.strip_suffix('*') {
+                    Some(word) => (word, true, &rest[end..]),
+                    None => (word, false, &rest[end..]),
+                }
+            }
+        };
+
+        let term = term.trim();
+
+        if !term.is_empty() {
+            terms.push(format!(
+                "\"{}\"{}",
+                term.replace('"', "\"\""),
+                if prefix { "*" } else { "" }
+            ));
+        }
+
+        rest = remaining.trim_start();
+    }
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+/// Escapes highlighted text as HTML, wrapping the matches in `<mark>`.
+fn highlight(text: String) -> String {
+    let (start, end) = HIGHLIGHT;
+
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace(start, "<mark>")
+        .replace(end, "</mark>")
+}
+
+/// A record matching a search.
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub id: String,
+    pub project_id: Option<String>,
+    /// The title as HTML, with the matches highlighted.
+    pub title: Option<String>,
+    /// The part of the body around the matches as HTML, highlighted.
+    pub snippet: Option<String>,
+    /// How well the record matches, higher is better.
+    pub score: f64,
+}
+
+/// A page of search results.
+#[derive(Debug)]
+pub struct SearchPage {
+    pub hits: Vec<SearchHit>,
+    pub total: usize,
+    pub total_pages: usize,
+}
+
+impl Mirror {
+    /// A page of the records matching `expression`, from `match_expression`,
+    /// best first. Limited to a project and to the given kinds when set.
+    /// `None` when nothing has been synced yet.
+    pub fn search(
+        &self,
+        expression: &str,
+        project: Option<&str>,
+        kinds: &[&str],
+        page: usize,
+        per_page: usize,
+    ) -> rusqlite::Result<Option<SearchPage>> {
+        let mut synced = false;
+
+        for table in tables() {
+            synced |= self.sync_state(table.name)?.last_sync.is_some();
+        }
+
+        if !synced {
+            return Ok(None);
+        }
+
+        let mut clause = "search MATCH ?".to_string();
+        let mut params = vec![SqlValue::Text(expression.to_string())];
+
+        if let Some(project) = project {
+            clause.push_str(" AND project_id = ?");
+            params.push(SqlValue::Text(project.to_string()));
+        }
+
+        if !kinds.is_empty() {
+            let placeholders = vec!["?"; kinds.len()].join(", ");
+            clause.push_str(&format!(" AND kind IN ({})", placeholders));
+            params.extend(kinds.iter().map(|k| SqlValue::Text(k.to_string())));
+        }
+
+        let per_page = per_page.max(1);
+        let offset = (page.max(1) - 1) as i64 * per_page as i64;
+
+        self.with(|conn| {
+            let total: i64 = conn.query_row(
+                &format!("SELECT count(*) FROM search WHERE {}", clause),
+                params_from_iter(&params),
+                |row| row.get(0),
+            )?;
+
+            let (start, end) = HIGHLIGHT;
+            let mut select_params = vec![
+                SqlValue::Text(start.into()),
+                SqlValue::Text(end.into()),
+                SqlValue::Text(start.into()),
+                SqlValue::Text(end.into()),
+                SqlValue::Integer(SNIPPET_TOKENS),
+            ];
+            select_params.extend(params.iter().cloned());
+            select_params.push(SqlValue::Integer(per_page as i64));
+            select_params.push(SqlValue::Integer(offset));
+
+            let hits = conn
+                .prepare(&format!(
+                    "SELECT kind, id, project_id, highlight(search, 3, ?, ?),
+                        nullif(snippet(search, 4, ?, ?, '…', ?), ''), bm25(search) AS rank
+                     FROM search WHERE {} ORDER BY rank LIMIT ? OFFSET ?",
+                    clause
+                ))?
+                .query_map(params_from_iter(select_params), |row| {
+                    Ok(SearchHit {
+                        kind: row.get(0)?,
+                        id: row.get(1)?,
+                        project_id: row.get(2)?,
+                        title: row.get::<_, Option<String>>(3)?.map(highlight),
+                        snippet: row.get::<_, Option<String>>(4)?.map(highlight),
+                        // bm25 is lower for better matches
+                        score: -row.get::<_, f64>(5)?,
+                    })
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+
+            Ok(Some(SearchPage {
+                hits,
+                total: total as usize,
+                total_pages: (total as usize).div_ceil(per_page).max(1),
+            }))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use teamwork_schema::{Task, TaskList};
+
+    use super::*;
+
+    #[test]
+    fn searches_across_collections_with_snippets() {
+        let mirror = Mirror::open(":memory:").unwrap();
+
+        mirror
+            .store::<Task>(
+                &[
+                    json!({"id": 1, "content": "Write the launch post", "description": "Draft the post announcing the launch of the new dashboard", "project_id": 1}),
+                    json!({"id": 2, "content": "Fix login", "project_id": 2}),
+                    json!({"id": 3, "content": "Fix <b> & signup", "description": "a < b & c", "project_id": 3}),
+                ],
+                true,
+            )
+            .unwrap();
+        mirror
+            .store::<TaskList>(
+                &[json!({"id": "1", "name": "Launch", "project_id": "2"})],
+                true,
+            )
+            .unwrap();
+        // updated records replace what was indexed
+        mirror
+            .store::<Task>(
+                &[json!({"id": 2, "content": "Fix launch login", "project_id": 2})],
+                false,
+            )
+            .unwrap();
+
+        let search = |q: &str, project: Option<&str>, kinds: &[&str]| {
+            let expression = match_expression(q).unwrap();
+            mirror
+                .search(&expression, project, kinds, 1, 10)
+                .unwrap()
+                .unwrap()
+                .hits
+        };
+
+        assert_eq!(search("launch", None, &[]).len(), 3);
+        assert_eq!(search("launch", Some("2"), &["task"]).len(), 1);
+
+        let hits = search("dash*", None, &[]);
+        assert_eq!(hits[0].id, "1");
+        assert!(hits[0]
+            .snippet
+            .as_deref()
+            .unwrap()
+            .contains("<mark>dashboard</mark>"));
+
+        // highlights are HTML with the records' text escaped
+        let hits = search("signup b", None, &[]);
+        assert_eq!(
+            hits[0].title.as_deref(),
+            Some("Fix &lt;<mark>b</mark>&gt; &amp; <mark>signup</mark>")
+        );
+        assert_eq!(
+            hits[0].snippet.as_deref(),
+            Some("a &lt; <mark>b</mark> &amp; c")
+        );
+
+        assert_eq!(
+            match_expression(r#"fix "new da"sh* OR"#).unwrap(),
+            r#""fix" "new da" "sh"* "OR""#
+        );
+        assert_eq!(match_expression("  "), None);
+    }
+}
@@ -0,0 +1,213 @@
+//! Keeps the mirror up to date with the configured API key, fetching the
+//! records changed since the last sync every `mirror_sync_interval_secs` and
+//! every record every `mirror_full_sync_interval_secs` so deleted records are
+//! dropped.
+
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use chrono::{DateTime, Duration, Utc};
+use serde_json::Value;
+use teamwork_schema::{Task, TaskList, TimeEntry};
+
+use super::{Mirror, Mirrored, SyncState};
+use crate::{reports::to_values, upstream::Upstream, Error, Result, State};
+
+/// Records changed within this long of the last sync are fetched again, so
+/// records changed in the same second as the last one aren't missed.
+const OVERLAP: Duration = Duration::seconds(1);
+
+/// The format of Teamwork's `updatedAfterDate` param.
+const UPDATED_AFTER_FORMAT: &str = "%Y%m%d%H%M%S";
+
+fn older_than(timestamp: &Option<String>, age: std::time::Duration) -> bool {
+    let age = Duration::from_std(age).unwrap_or(Duration::MAX);
+
+    timestamp
+        .as_deref()
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        .is_none_or(|t| Utc::now().signed_duration_since(t) >= age)
+}
+
+/// The `updatedAfterDate` of the records to fetch, `None` when the collection
+/// is synced in full.
+fn updated_after<R: Mirrored>(
+    state: &SyncState,
+    full_every: std::time::Duration,
+) -> Option<String> {
+    if R::UPDATED_FIELD.is_none() || older_than(&state.last_full_sync, full_every) {
+        return None;
+    }
+
+    state
+        .updated
+        .as_deref()
+        .and_then(|u| DateTime::parse_from_rfc3339(u).ok())
+        .map(|u| {
+            (u.with_timezone(&Utc) - OVERLAP)
+                .format(UPDATED_AFTER_FORMAT)
+                .to_string()
+        })
+}
+
+/// Syncs a collection, returning the number of records fetched.
+async fn sync<R: Mirrored>(
+    upstream: &Upstream<'_>,
+    mirror: &Arc<Mirror>,
+    full_every: std::time::Duration,
+) -> Result<usize> {
+    // SQLite blocks, keep the reads and writes off the executor's threads
+    let state = {
+        let mirror = mirror.clone();
+        async_std::task::spawn_blocking(move || mirror.sync_state(R::TABLE)).await?
+    };
+
+    let updated_after = updated_after::<R>(&state, full_every);
+    let full = updated_after.is_none();
+
+    let mut params: HashMap<String, Value> = R::SYNC_PARAMS
+        .iter()
+        .map(|(k, v)| (k.to_string(), Value::from(*v)))
+        .collect();
+
+    if let Some(updated_after) = updated_after {
+        params.insert("updatedAfterDate".into(), updated_after.into());
+    }
+
+    let records = to_values(&upstream.get_all::<R>(&params).await?)
+        .map_err(|e| Error::SchemaError(e.to_string()))?;
+    let count = records.len();
+
+    let mirror = mirror.clone();
+    async_std::task::spawn_blocking(move || mirror.store::<R>(&records, full)).await?;
+
+    tide::log::info!("Synced mirror", {
+        table: R::TABLE,
+        full: full,
+        records: count,
+    });
+
+    Ok(count)
+}
+
+/// Syncs every mirrored collection, carrying on past failures so one
+/// collection doesn't hold back the others.
+pub async fn sync_all(state: &State) -> Result<()> {
+    let mirror = match &state.mirror {
+        Some(mirror) => mirror,
+        None => return Ok(()),
+    };
+
+    let upstream = Upstream::with_api_key(state)?;
+    let full_every = state.config.mirror_full_sync_interval();
+
+    let results = [
+        (
+            Task::TABLE,
+            sync::<Task>(&upstream, mirror, full_every).await,
+        ),
+        (
+            TaskList::TABLE,
+            sync::<TaskList>(&upstream, mirror, full_every).await,
+        ),
+        (
+            TimeEntry::TABLE,
+            sync::<TimeEntry>(&upstream, mirror, full_every).await,
+        ),
+    ];
+
+    let mut failed = None;
+
+    for (table, result) in results {
+        if let Err(e) = result {
+            tide::log::error!("Failed to sync mirror", {
+                table: table,
+                error: e.to_string(),
+            });
+            failed = Some(e);
+        }
+    }
+
+    failed.map_or(Ok(()), Err)
+}
+
+/// Syncs the mirror every `mirror_sync_interval_secs` until the process exits.
+pub async fn run(state: State) {
+    let interval = state.config.mirror_sync_interval();
+
+    loop {
+        let start = Instant::now();
+
+        // failures are logged, the next sync retries them
+        let _ = sync_all(&state).await;
+
+        async_std::task::sleep(interval.saturating_sub(start.elapsed())).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: std::time::Duration = std::time::Duration::from_secs(86400);
+
+    fn state(updated: Option<&str>, last_full_sync: Option<String>) -> SyncState {
+        SyncState {
+            updated: updated.map(str::to_string),
+            last_sync: last_full_sync.clone(),
+            last_full_sync,
+        }
+    }
+
+    #[test]
+    fn fetches_changes_since_the_latest_update() {
+        let recent = Some(Utc::now().to_rfc3339());
+        let state = state(Some("2024-05-10T12:30:00+02:00"), recent);
+
+        // in UTC, a second before the latest update
+        assert_eq!(
+            updated_after::<Task>(&state, DAY).as_deref(),
+            Some("20240510102959")
+        );
+        assert_eq!(
+            updated_after::<TimeEntry>(&state, DAY).as_deref(),
+            Some("20240510102959")
+        );
+    }
+
+    #[test]
+    fn syncs_in_full_when_due() {
+        let recent = Some(Utc::now().to_rfc3339());
+        let stale = Some((Utc::now() - Duration::days(2)).to_rfc3339());
+        let updated = Some("2024-05-10T10:30:00Z");
+
+        // never synced
+        assert_eq!(updated_after::<Task>(&state(None, None), DAY), None);
+        // the last full sync is older than full_every
+        assert_eq!(updated_after::<Task>(&state(updated, stale), DAY), None);
+        // nothing records when task lists change
+        assert_eq!(
+            updated_after::<TaskList>(&state(updated, recent.clone()), DAY),
+            None
+        );
+        // nothing was mirrored yet
+        assert_eq!(
+            updated_after::<Task>(&state(None, recent.clone()), DAY),
+            None
+        );
+        assert!(updated_after::<Task>(&state(updated, recent), DAY).is_some());
+    }
+
+    #[test]
+    fn full_syncs_follow_the_interval() {
+        let last = Some((Utc::now() - Duration::days(400)).to_rfc3339());
+        let state = state(Some("2024-05-10T10:30:00Z"), last);
+
+        assert!(updated_after::<Task>(&state, 500 * DAY).is_some());
+        assert_eq!(updated_after::<Task>(&state, 300 * DAY), None);
+        // a zero interval syncs in full every time
+        assert_eq!(
+            updated_after::<Task>(&state, std::time::Duration::ZERO),
+            None
+        );
+    }
+}
@@ -0,0 +1,362 @@
+//! Estimated against logged time for every task, rolled up into task lists
+//! and projects, flagging tasks that overran their estimate.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use teamwork_schema::{Task, TimeEntry};
+use tide::{Body, Request, Response};
+
+use super::{entry_date, minutes, nonblank, round, DateRange, DATE_FORMAT};
+use crate::{upstream::Upstream, Error, State};
+
+#[derive(Debug, Deserialize)]
+struct Query {
+    /// Only report on tasks of these projects, comma separated.
+    project: Option<String>,
+    /// Only count the time logged from this date.
+    #[serde(default, deserialize_with = "super::date")]
+    from: Option<NaiveDate>,
+    /// Only count the time logged up to this date.
+    #[serde(default, deserialize_with = "super::date")]
+    to: Option<NaiveDate>,
+    /// Overrides the configured `estimate_variance_threshold`.
+    threshold: Option<f64>,
+}
+
+impl Query {
+    /// The project ids asked for, `None` for every project.
+    fn projects(&self) -> Option<Vec<&str>> {
+        self.project.as_deref().map(|projects| {
+            projects
+                .split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .collect()
+        })
+    }
+
+    /// The range the logged time is limited to, `None` for all time.
+    fn range(&self) -> Result<Option<DateRange>, String> {
+        match (self.from, self.to) {
+            (None, None) => Ok(None),
+            (from, to) => DateRange::new(from, to).map(Some),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Minutes {
+    estimated: f64,
+    logged: f64,
+    /// Time logged on tasks without an estimate, which isn't part of the
+    /// variance.
+    unestimated: f64,
+    tasks: usize,
+    over_threshold: usize,
+}
+
+impl Minutes {
+    fn add(&mut self, task: &Minutes) {
+        self.estimated += task.estimated;
+        self.logged += task.logged;
+        self.unestimated += task.unestimated;
+        self.tasks += task.tasks;
+        self.over_threshold += task.over_threshold;
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Variance {
+    estimated_hours: f64,
+    logged_hours: f64,
+    /// Logged less estimated hours, positive when over the estimate.
+    variance_hours: f64,
+    /// The variance as a share of the estimate, `None` without an estimate.
+    variance: Option<f64>,
+    unestimated_hours: f64,
+    tasks: usize,
+    over_threshold: usize,
+}
+
+impl From<&Minutes> for Variance {
+    fn from(minutes: &Minutes) -> Self {
+        let variance = minutes.logged - minutes.estimated;
+
+        Variance {
+            estimated_hours: round(minutes.estimated / 60.0),
+            logged_hours: round(minutes.logged / 60.0),
+            variance_hours: round(variance / 60.0),
+            variance: if minutes.estimated > 0.0 {
+                Some((variance / minutes.estimated * 1000.0).round() / 1000.0)
+            } else {
+                None
+            },
+            unestimated_hours: round(minutes.unestimated / 60.0),
+            tasks: minutes.tasks,
+            over_threshold: minutes.over_threshold,
+        }
+    }
+}
+
+/// A task's estimate and logged time, `None` when it has neither.
+fn task_minutes(task: &Task, logged: f64, threshold: f64) -> Option<Minutes> {
+    let estimated = task.estimated_minutes.unwrap_or_default() as f64;
+
+    if estimated <= 0.0 && logged <= 0.0 {
+        return None;
+    }
+
+    Some(if estimated > 0.0 {
+        Minutes {
+            estimated,
+            logged,
+            unestimated: 0.0,
+            tasks: 1,
+            over_threshold: (logged > estimated * (1.0 + threshold)) as usize,
+        }
+    } else {
+        Minutes {
+            unestimated: logged,
+            tasks: 1,
+            ..Minutes::default()
+        }
+    })
+}
+
+struct Report {
+    tasks: Vec<Value>,
+    task_lists: Vec<Value>,
+    projects: Vec<Value>,
+    totals: Variance,
+}
+
+/// A group's id and name fields followed by its variance.
+fn group(id: (&str, &Option<String>), name: (&str, &Option<String>), minutes: &Minutes) -> Value {
+    let mut group = json!({ id.0: id.1, name.0: name.1 });
+
+    if let (Value::Object(group), Value::Object(variance)) =
+        (&mut group, json!(Variance::from(minutes)))
+    {
+        group.extend(variance);
+    }
+
+    group
+}
+
+fn id(id: Option<i64>) -> Option<String> {
+    id.map(|id| id.to_string())
+}
+
+fn aggregate(tasks: &[Task], entries: &[TimeEntry], threshold: f64) -> Report {
+    let mut logged: HashMap<&str, f64> = HashMap::new();
+
+    for entry in entries {
+        if let Some(task_id) = nonblank(&entry.todo_item_id) {
+            *logged.entry(task_id).or_default() += minutes(entry);
+        }
+    }
+
+    type Groups = BTreeMap<Option<String>, (Option<String>, Minutes)>;
+
+    let mut task_lists = Groups::new();
+    let mut projects = Groups::new();
+    let mut totals = Minutes::default();
+    let mut rows = vec![];
+
+    for task in tasks {
+        let task_id = id(task.id);
+        let task_logged = task_id
+            .as_deref()
+            .and_then(|id| logged.get(id))
+            .copied()
+            .unwrap_or_default();
+
+        let minutes = match task_minutes(task, task_logged, threshold) {
+            Some(minutes) => minutes,
+            None => continue,
+        };
+
+        for (groups, group_id, name) in [
+            (&mut task_lists, task.todo_list_id, &task.todo_list_name),
+            (&mut projects, task.project_id, &task.project_name),
+        ] {
+            let (group_name, group) = groups.entry(id(group_id)).or_default();
+            *group_name = group_name
+                .take()
+                .or_else(|| nonblank(name).map(str::to_string));
+            group.add(&minutes);
+        }
+
+        totals.add(&minutes);
+
+        let content = nonblank(&task.content).map(str::to_string);
+
+        let mut row = group(("task_id", &task_id), ("task", &content), &minutes);
+        row["task_list_id"] = json!(id(task.todo_list_id));
+        row["project_id"] = json!(id(task.project_id));
+        row["completed"] = task.completed.unwrap_or_default().into();
+        row["over_threshold"] = (minutes.over_threshold > 0).into();
+        if let Value::Object(row) = &mut row {
+            row.remove("tasks");
+        }
+        rows.push(row);
+    }
+
+    // the largest overruns first
+    rows.sort_by(|a, b| {
+        let variance = |row: &Value| row["variance_hours"].as_f64().unwrap_or_default();
+        variance(b).total_cmp(&variance(a))
+    });
+
+    let groups = |groups: Groups, id, name| {
+        groups
+            .iter()
+            .map(|(group_id, (group_name, minutes))| {
+                group((id, group_id), (name, group_name), minutes)
+            })
+            .collect()
+    };
+
+    Report {
+        tasks: rows,
+        task_lists: groups(task_lists, "task_list_id", "task_list"),
+        projects: groups(projects, "project_id", "project"),
+        totals: Variance::from(&totals),
+    }
+}
+
+/// Reports the variance between estimated and logged time per task, task list
+/// and project, across every task including completed ones. `project` and
+/// `from`/`to` limit the report to projects and the time logged in a range.
+pub async fn handler(req: Request<State>) -> tide::Result {
+    let query: Query = req.query().map_err(|e| Error::QueryError(e.to_string()))?;
+
+    let threshold = query
+        .threshold
+        .unwrap_or_else(|| req.state().config.estimate_variance_threshold());
+
+    if !threshold.is_finite() || threshold < 0.0 {
+        Err(Error::QueryError("threshold must not be negative".into()))?;
+    }
+
+    let range = query.range().map_err(Error::QueryError)?;
+    let projects = query.projects();
+
+    let mut task_params = HashMap::new();
+    task_params.insert("includeCompletedTasks".to_string(), json!(true));
+
+    let mut entry_params: HashMap<String, Value> =
+        range.iter().flat_map(DateRange::teamwork_params).collect();
+
+    if let Some(projects) = &projects {
+        task_params.insert("projectIds".to_string(), json!(projects.join(",")));
+        entry_params.insert("projectIds".to_string(), json!(projects.join(",")));
+    }
+
+    let upstream = Upstream::new(&req)?;
+    let (tasks, entries) = futures::future::try_join(
+        upstream.get_all::<Task>(&task_params),
+        upstream.get_all::<TimeEntry>(&entry_params),
+    )
+    .await?;
+
+    // checked again locally so that the report doesn't depend on Teamwork
+    // honouring the params
+    let tasks: Vec<Task> = tasks
+        .into_iter()
+        .filter(|task| match (&projects, id(task.project_id)) {
+            (Some(projects), Some(project)) => projects.contains(&project.as_str()),
+            (Some(_), None) => false,
+            (None, _) => true,
+        })
+        .collect();
+
+    let entries: Vec<TimeEntry> = entries
+        .into_iter()
+        .filter(|entry| match (range, entry_date(entry)) {
+            (Some(range), Some(date)) => range.contains(date),
+            (Some(_), None) => false,
+            (None, _) => true,
+        })
+        .collect();
+
+    let report = aggregate(&tasks, &entries, threshold);
+
+    Ok(Response::builder(200)
+        .body(Body::from_json(&json!({
+            "data": {
+                "tasks": report.tasks,
+                "task_lists": report.task_lists,
+                "projects": report.projects,
+            },
+            "meta": {
+                "project": projects,
+                "from": range.map(|r| r.from.format(DATE_FORMAT).to_string()),
+                "to": range.map(|r| r.to.format(DATE_FORMAT).to_string()),
+                "threshold": threshold,
+                "totals": report.totals,
+            },
+        }))?)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_tasks_over_the_threshold() {
+        let tasks: Vec<Task> = serde_json::from_value(json!([
+            {"id": 1, "estimated-minutes": 60, "todo-list-id": 1, "project-id": 1},
+            {"id": 2, "estimated-minutes": 60, "todo-list-id": 1, "project-id": 1},
+            {"id": 3, "estimated-minutes": 0, "todo-list-id": 2, "project-id": 1},
+        ]))
+        .unwrap();
+        let entries: Vec<TimeEntry> = serde_json::from_value(json!([
+            {"todo-item-id": "1", "hours": "1", "minutes": "30"},
+            {"todo-item-id": "2", "hours": "1", "minutes": "5"},
+            {"todo-item-id": "3", "hours": "2", "minutes": "0"},
+        ]))
+        .unwrap();
+
+        let report = aggregate(&tasks, &entries, 0.2);
+
+        let over: Vec<_> = report
+            .tasks
+            .iter()
+            .filter(|t| t["over_threshold"] == true)
+            .map(|t| t["task_id"].clone())
+            .collect();
+        assert_eq!(over, vec![json!("1")]);
+
+        assert_eq!(report.totals.estimated_hours, 2.0);
+        assert_eq!(report.totals.variance_hours, 0.58);
+        assert_eq!(report.totals.unestimated_hours, 2.0);
+        assert_eq!(report.projects.len(), 1);
+        assert_eq!(report.task_lists.len(), 2);
+    }
+
+    #[test]
+    fn parses_the_project_and_date_filters() {
+        let query = |query: &str| -> Query {
+            let url = tide::http::Url::parse(&format!("http://localhost/?{}", query)).unwrap();
+            tide::http::Request::new(tide::http::Method::Get, url)
+                .query()
+                .unwrap()
+        };
+
+        assert_eq!(query("").projects(), None);
+        assert_eq!(query("project=1, 2,").projects(), Some(vec!["1", "2"]));
+
+        assert!(query("").range().unwrap().is_none());
+        assert!(query("from=2024-01-01&to=2024-01-31")
+            .range()
+            .unwrap()
+            .is_some());
+        assert!(query("from=2024-01-01").range().is_err());
+        assert!(query("from=2024-02-01&to=2024-01-31").range().is_err());
+    }
+}
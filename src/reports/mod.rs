@@ -0,0 +1,164 @@
+//! Reports aggregating Teamwork records across every page of a collection,
+//! computed server side so consumers don't each reimplement them.
+
+use chrono::{DateTime, Duration, NaiveDate};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use teamwork_schema::{Task, TimeEntry};
+
+pub mod estimates;
+pub mod overdue;
+pub mod time;
+pub mod utilization;
+
+/// The format of dates in report queries and responses.
+pub const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// The format of dates in Teamwork's query params.
+const TEAMWORK_DATE_FORMAT: &str = "%Y%m%d";
+
+/// Deserializes an optional `YYYY-MM-DD` date.
+pub fn date<'de, D>(d: D) -> Result<Option<NaiveDate>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(d)?
+        .filter(|s| !s.is_empty())
+        .map(|s| NaiveDate::parse_from_str(&s, DATE_FORMAT).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// An inclusive range of dates.
+#[derive(Debug, Clone, Copy)]
+pub struct DateRange {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+impl DateRange {
+    pub fn new(from: Option<NaiveDate>, to: Option<NaiveDate>) -> Result<Self, String> {
+        match (from, to) {
+            (Some(from), Some(to)) if from <= to => Ok(DateRange { from, to }),
+            (Some(_), Some(_)) => Err("from must not be after to".into()),
+            _ => Err("from and to are required, formatted as YYYY-MM-DD".into()),
+        }
+    }
+
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.from <= date && date <= self.to
+    }
+
+    /// The params limiting Teamwork's time entries to the range. Teamwork
+    /// filters by the UTC date, while entries are reported on the day they
+    /// were logged for, so the params span a day either side of the range and
+    /// the entries have to be filtered with `contains` as well.
+    pub fn teamwork_params(&self) -> Vec<(String, Value)> {
+        let from = self.from - Duration::days(1);
+        let to = self.to + Duration::days(1);
+
+        vec![
+            (
+                "fromdate".into(),
+                from.format(TEAMWORK_DATE_FORMAT).to_string().into(),
+            ),
+            (
+                "todate".into(),
+                to.format(TEAMWORK_DATE_FORMAT).to_string().into(),
+            ),
+        ]
+    }
+}
+
+/// A string field of a normalized record, `None` when it's missing or blank.
+pub fn text(record: &Value, field: &str) -> Option<String> {
+    match record.get(field) {
+        Some(Value::String(s)) if !s.trim().is_empty() => Some(s.trim().to_string()),
+        Some(Value::Number(n)) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// A string field that isn't blank.
+pub fn nonblank(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|s| !s.is_empty())
+}
+
+/// A numeric field, which Teamwork sends as a string.
+pub fn number(value: &Option<String>) -> f64 {
+    nonblank(value)
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_default()
+}
+
+/// A flag field, which Teamwork sends as `"1"` or `"0"`.
+pub fn flag(value: &Option<String>) -> bool {
+    nonblank(value).is_some_and(|s| s == "1" || s.eq_ignore_ascii_case("true"))
+}
+
+/// The time logged by a time entry in minutes, combining its hours and
+/// minutes.
+pub fn minutes(entry: &TimeEntry) -> f64 {
+    number(&entry.hours) * 60.0 + number(&entry.minutes)
+}
+
+/// The date a time entry was logged for, as seen by the person who logged it.
+/// `date` is in UTC, so entries logged late or early in the day would land on
+/// the wrong day in other timezones.
+pub fn entry_date(entry: &TimeEntry) -> Option<NaiveDate> {
+    let date = nonblank(&entry.date_user_perspective).or_else(|| nonblank(&entry.date))?;
+
+    // the local time is sent with a `Z` suffix, so it's read as written rather
+    // than converted
+    DateTime::parse_from_rfc3339(date)
+        .map(|d| d.naive_local().date())
+        .or_else(|_| NaiveDate::parse_from_str(date, DATE_FORMAT))
+        .ok()
+}
+
+/// A task date, which Teamwork formats as `YYYYMMDD`.
+pub fn task_date(date: &Option<String>) -> Option<NaiveDate> {
+    nonblank(date).and_then(|d| NaiveDate::parse_from_str(d, TEAMWORK_DATE_FORMAT).ok())
+}
+
+/// The ids and names of the people a task is assigned to, or a single
+/// unassigned `None`.
+pub fn assignees(task: &Task) -> Vec<(Option<String>, Option<String>)> {
+    let ids = nonblank(&task.responsible_party_ids).unwrap_or_default();
+    let names = nonblank(&task.responsible_party_names).unwrap_or_default();
+
+    let mut names = names.split('|').map(|n| n.trim().to_string());
+
+    let assignees: Vec<_> = ids
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| (Some(id.to_string()), names.next().filter(|n| !n.is_empty())))
+        .collect();
+
+    if assignees.is_empty() {
+        vec![(None, None)]
+    } else {
+        assignees
+    }
+}
+
+/// The full name of the person who logged a time entry.
+pub fn person(entry: &TimeEntry) -> String {
+    format!(
+        "{} {}",
+        nonblank(&entry.person_first_name).unwrap_or_default(),
+        nonblank(&entry.person_last_name).unwrap_or_default()
+    )
+    .trim()
+    .to_string()
+}
+
+/// Rounds hours to two decimal places for the responses.
+pub fn round(hours: f64) -> f64 {
+    (hours * 100.0).round() / 100.0
+}
+
+/// Serializes records so they can be aggregated by their normalized fields.
+pub fn to_values<T: serde::Serialize>(records: &[T]) -> serde_json::Result<Vec<Value>> {
+    records.iter().map(serde_json::to_value).collect()
+}
@@ -0,0 +1,369 @@
+//! Incomplete tasks needing attention, grouped by assignee and project: those
+//! past due, those due soon without progress and those blocked by
+//! incomplete predecessors.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use chrono::{NaiveDate, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use teamwork_schema::Task;
+use tide::{Body, Request, Response};
+
+use super::{assignees, nonblank, task_date, DATE_FORMAT};
+use crate::{include::MAX_IDS, upstream::Upstream, Error, State};
+
+/// How many days ahead a task without progress is at risk by default.
+const DEFAULT_DAYS: i64 = 7;
+
+#[derive(Debug, Deserialize)]
+struct Query {
+    /// Tasks due within this many days without progress are at risk.
+    days: Option<i64>,
+    /// Only report on tasks of these comma separated projects.
+    project: Option<String>,
+}
+
+impl Query {
+    /// The project ids asked for, `None` for every project.
+    fn projects(&self) -> Option<Vec<&str>> {
+        self.project.as_deref().map(|projects| {
+            projects
+                .split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .collect()
+        })
+    }
+}
+
+/// Why a task needs attention.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Reason {
+    Overdue,
+    AtRisk,
+    Blocked,
+}
+
+impl Reason {
+    fn key(self) -> &'static str {
+        match self {
+            Reason::Overdue => "overdue",
+            Reason::AtRisk => "at_risk",
+            Reason::Blocked => "blocked",
+        }
+    }
+}
+
+/// The ids of the incomplete predecessors of a task.
+fn blocked_by(task: &Task, incomplete: &HashSet<i64>) -> Vec<String> {
+    let has_predecessors = task.has_predecessors.unwrap_or_default() > 0
+        || task.has_dependencies.unwrap_or_default() > 0;
+
+    if !has_predecessors {
+        return vec![];
+    }
+
+    task.predecessors
+        .iter()
+        .flatten()
+        .filter_map(|p| p.id)
+        .filter(|id| incomplete.contains(id))
+        .map(|id| id.to_string())
+        .collect()
+}
+
+fn id(id: Option<i64>) -> Option<String> {
+    id.map(|id| id.to_string())
+}
+
+#[derive(Debug, Default)]
+struct Group {
+    assignee: Option<String>,
+    project: Option<String>,
+    tasks: Vec<(Reason, Value)>,
+}
+
+fn report(tasks: &[Task], outside: &[Task], today: NaiveDate, days: i64) -> Vec<Value> {
+    let tasks: Vec<&Task> = tasks
+        .iter()
+        .filter(|t| !t.completed.unwrap_or_default())
+        .collect();
+    let incomplete: HashSet<i64> = tasks
+        .iter()
+        .copied()
+        .chain(outside.iter().filter(|t| !t.completed.unwrap_or_default()))
+        .filter_map(|t| t.id)
+        .collect();
+
+    let mut groups: BTreeMap<(Option<String>, Option<String>), Group> = BTreeMap::new();
+
+    for task in tasks {
+        let due = task_date(&task.due_date);
+        let progress = task.progress.unwrap_or_default();
+        let blocked_by = blocked_by(task, &incomplete);
+
+        let mut reasons = vec![];
+
+        match due {
+            Some(due) if due < today => reasons.push(Reason::Overdue),
+            Some(due) if (due - today).num_days() <= days && progress <= 0 => {
+                reasons.push(Reason::AtRisk)
+            }
+            _ => {}
+        }
+
+        if !blocked_by.is_empty() {
+            reasons.push(Reason::Blocked);
+        }
+
+        if reasons.is_empty() {
+            continue;
+        }
+
+        let summary = json!({
+            "task_id": id(task.id),
+            "task": nonblank(&task.content),
+            "task_list_id": id(task.todo_list_id),
+            "task_list": nonblank(&task.todo_list_name),
+            "due_date": due.map(|d| d.format(DATE_FORMAT).to_string()),
+            "days_overdue": due.filter(|d| *d < today).map(|d| (today - d).num_days()),
+            "progress": progress,
+            "priority": nonblank(&task.priority),
+            "blocked_by": blocked_by,
+        });
+
+        for (assignee_id, assignee) in assignees(task) {
+            let group = groups
+                .entry((assignee_id, id(task.project_id)))
+                .or_default();
+
+            group.assignee = group.assignee.take().or_else(|| assignee.clone());
+            group.project = group
+                .project
+                .take()
+                .or_else(|| nonblank(&task.project_name).map(str::to_string));
+
+            for reason in &reasons {
+                group.tasks.push((*reason, summary.clone()));
+            }
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|((assignee_id, project_id), group)| {
+            let list = |reason: Reason| {
+                group
+                    .tasks
+                    .iter()
+                    .filter(|(r, _)| *r == reason)
+                    .map(|(_, task)| task.clone())
+                    .collect::<Vec<_>>()
+            };
+
+            let mut value = json!({
+                "assignee_id": assignee_id,
+                "assignee": group.assignee,
+                "project_id": project_id,
+                "project": group.project,
+            });
+
+            for reason in [Reason::Overdue, Reason::AtRisk, Reason::Blocked] {
+                value[reason.key()] = list(reason).into();
+            }
+
+            value
+        })
+        .collect()
+}
+
+/// The predecessors of `tasks` that aren't among them, which for a single
+/// project's tasks may belong to another project.
+async fn outside_predecessors(upstream: &Upstream<'_>, tasks: &[Task]) -> crate::Result<Vec<Task>> {
+    let known: HashSet<i64> = tasks.iter().filter_map(|t| t.id).collect();
+    let mut missing: Vec<String> = tasks
+        .iter()
+        .flat_map(|t| t.predecessors.iter().flatten())
+        .filter_map(|p| p.id)
+        .filter(|id| !known.contains(id))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .map(|id| id.to_string())
+        .collect();
+    missing.sort();
+
+    let mut outside = vec![];
+
+    for chunk in missing.chunks(MAX_IDS) {
+        let mut params = HashMap::new();
+        params.insert("taskIds".to_string(), json!(chunk.join(",")));
+        params.insert("includeCompletedTasks".to_string(), json!(true));
+
+        outside.extend(upstream.get_all::<Task>(&params).await?);
+    }
+
+    Ok(outside)
+}
+
+/// Reports the incomplete tasks that are overdue, at risk or blocked, grouped
+/// by assignee and project.
+pub async fn handler(req: Request<State>) -> tide::Result {
+    let query: Query = req.query().map_err(|e| Error::QueryError(e.to_string()))?;
+
+    let days = query.days.unwrap_or(DEFAULT_DAYS);
+
+    if days < 0 {
+        Err(Error::QueryError("days must not be negative".into()))?;
+    }
+
+    let projects = query.projects();
+
+    let mut params = HashMap::new();
+
+    if let Some(projects) = &projects {
+        params.insert("projectIds".to_string(), json!(projects.join(",")));
+    }
+
+    let upstream = Upstream::new(&req)?;
+    let tasks = upstream.get_all::<Task>(&params).await?;
+    let outside = outside_predecessors(&upstream, &tasks).await?;
+
+    let today = Utc::now().date_naive();
+
+    let mut groups = report(&tasks, &outside, today, days);
+
+    if let Some(projects) = &projects {
+        groups.retain(|group| {
+            group["project_id"]
+                .as_str()
+                .is_some_and(|project| projects.contains(&project))
+        });
+    }
+
+    let count = |reason: Reason| {
+        groups
+            .iter()
+            .filter_map(|g| g[reason.key()].as_array())
+            .flatten()
+            .filter_map(|t| t["task_id"].as_str())
+            .collect::<HashSet<_>>()
+            .len()
+    };
+
+    let totals = json!({
+        "overdue": count(Reason::Overdue),
+        "at_risk": count(Reason::AtRisk),
+        "blocked": count(Reason::Blocked),
+    });
+
+    Ok(Response::builder(200)
+        .body(Body::from_json(&json!({
+            "data": groups,
+            "meta": {
+                "as_of": today.format(DATE_FORMAT).to_string(),
+                "days": days,
+                "totals": totals,
+            },
+        }))?)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tasks() -> Vec<Task> {
+        serde_json::from_value(json!([
+            {"id": 1, "content": "Late", "due-date": "20240301", "progress": 50, "project-id": 10, "project-name": "Website", "responsible-party-ids": "7", "responsible-party-names": "Holly M."},
+            {"id": 2, "content": "Soon", "due-date": "20240312", "progress": 0, "project-id": 10, "project-name": "Website", "responsible-party-ids": "7", "responsible-party-names": "Holly M."},
+            {"id": 3, "content": "Started", "due-date": "20240312", "progress": 10, "project-id": 10, "responsible-party-ids": "7"},
+            {"id": 4, "content": "Later", "due-date": "20240401", "progress": 0, "project-id": 10, "responsible-party-ids": "7"},
+            {"id": 5, "content": "Waiting", "project-id": 11, "has-predecessors": 1, "predecessors": [{"id": 1}, {"id": 6}]},
+            {"id": 6, "content": "Done", "due-date": "20240101", "completed": true, "project-id": 11},
+        ]))
+        .unwrap()
+    }
+
+    fn ids(group: &Value, reason: &str) -> Vec<Value> {
+        group[reason]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["task_id"].clone())
+            .collect()
+    }
+
+    #[test]
+    fn predecessors_in_other_projects_still_block() {
+        let today = NaiveDate::from_ymd_opt(2024, 3, 8).unwrap();
+        let all = tasks();
+        let (project, others): (Vec<Task>, Vec<Task>) =
+            all.into_iter().partition(|t| t.project_id == Some(11));
+
+        let groups = report(&project, &others, today, 7);
+
+        assert_eq!(ids(&groups[0], "blocked"), [json!("5")]);
+        assert_eq!(groups[0]["blocked"][0]["blocked_by"], json!(["1"]));
+    }
+
+    #[test]
+    fn groups_overdue_at_risk_and_blocked_tasks() {
+        let today = NaiveDate::from_ymd_opt(2024, 3, 8).unwrap();
+        let groups = report(&tasks(), &[], today, 7);
+
+        assert_eq!(groups.len(), 2);
+
+        let unassigned = &groups[0];
+        assert_eq!(unassigned["assignee_id"], Value::Null);
+        assert_eq!(unassigned["project_id"], "11");
+        assert_eq!(ids(unassigned, "blocked"), [json!("5")]);
+        assert_eq!(unassigned["blocked"][0]["blocked_by"], json!(["1"]));
+
+        let holly = &groups[1];
+        assert_eq!(holly["assignee"], "Holly M.");
+        assert_eq!(holly["project"], "Website");
+        assert_eq!(ids(holly, "overdue"), [json!("1")]);
+        assert_eq!(holly["overdue"][0]["days_overdue"], 7);
+        assert_eq!(ids(holly, "at_risk"), [json!("2")]);
+        assert_eq!(ids(holly, "blocked"), Vec::<Value>::new());
+    }
+
+    #[test]
+    fn the_at_risk_window_is_inclusive() {
+        let today = NaiveDate::from_ymd_opt(2024, 3, 8).unwrap();
+
+        assert_eq!(
+            ids(&report(&tasks(), &[], today, 4)[1], "at_risk"),
+            [json!("2")]
+        );
+        assert!(report(&tasks(), &[], today, 3)[1]["at_risk"]
+            .as_array()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn only_incomplete_predecessors_block() {
+        let tasks = tasks();
+        let incomplete: HashSet<i64> = vec![1, 5].into_iter().collect();
+
+        assert_eq!(blocked_by(&tasks[4], &incomplete), ["1"]);
+        assert!(blocked_by(&tasks[4], &HashSet::new()).is_empty());
+        // predecessors aren't looked at unless the task says it has some
+        assert!(blocked_by(&tasks[0], &incomplete).is_empty());
+    }
+
+    #[test]
+    fn parses_the_project_filter() {
+        let query = |query: &str| -> Query {
+            let url = tide::http::Url::parse(&format!("http://localhost/?{}", query)).unwrap();
+            tide::http::Request::new(tide::http::Method::Get, url)
+                .query()
+                .unwrap()
+        };
+
+        assert_eq!(query("").projects(), None);
+        assert_eq!(query("project= 1, 2,").projects(), Some(vec!["1", "2"]));
+    }
+}
@@ -0,0 +1,300 @@
+//! Logged time summed across every time entry in a date range, grouped by any
+//! combination of who, what and when.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use teamwork_schema::TimeEntry;
+use tide::{Body, Request, Response};
+
+use super::{entry_date, flag, minutes, nonblank, person, round, DateRange, DATE_FORMAT};
+use crate::{upstream::Upstream, Error, State};
+
+/// A dimension the time can be grouped by.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dimension {
+    Person,
+    Project,
+    TaskList,
+    Task,
+    Day,
+    Week,
+    Month,
+    Billable,
+}
+
+impl Dimension {
+    fn parse(value: &str) -> Result<Self, String> {
+        Ok(match value.trim() {
+            "person" => Dimension::Person,
+            "project" => Dimension::Project,
+            "task_list" => Dimension::TaskList,
+            "task" => Dimension::Task,
+            "day" => Dimension::Day,
+            "week" => Dimension::Week,
+            "month" => Dimension::Month,
+            "billable" => Dimension::Billable,
+            other => return Err(format!("unknown group_by {}", other)),
+        })
+    }
+
+    /// The fields identifying the group of an entry along this dimension.
+    pub(super) fn fields(self, entry: &TimeEntry, date: NaiveDate) -> Vec<(&'static str, Value)> {
+        let id_and_name = |id: &'static str, name: &'static str, id_value, name_value| {
+            vec![
+                (id, json!(nonblank(id_value))),
+                (name, json!(nonblank(name_value))),
+            ]
+        };
+
+        match self {
+            Dimension::Person => vec![
+                ("person_id", json!(nonblank(&entry.person_id))),
+                ("person", person(entry).into()),
+            ],
+            Dimension::Project => id_and_name(
+                "project_id",
+                "project",
+                &entry.project_id,
+                &entry.project_name,
+            ),
+            Dimension::TaskList => id_and_name(
+                "task_list_id",
+                "task_list",
+                &entry.todo_list_id,
+                &entry.todo_list_name,
+            ),
+            Dimension::Task => id_and_name(
+                "task_id",
+                "task",
+                &entry.todo_item_id,
+                &entry.todo_item_name,
+            ),
+            Dimension::Day => vec![("day", date.format(DATE_FORMAT).to_string().into())],
+            Dimension::Week => {
+                let week = date.iso_week();
+                vec![(
+                    "week",
+                    format!("{}-W{:02}", week.year(), week.week()).into(),
+                )]
+            }
+            Dimension::Month => vec![("month", date.format("%Y-%m").to_string().into())],
+            Dimension::Billable => vec![("billable", flag(&entry.isbillable).into())],
+        }
+    }
+}
+
+/// Parses a comma separated list of dimensions.
+pub fn dimensions(group_by: &str) -> Result<Vec<Dimension>, String> {
+    let dimensions = group_by
+        .split(',')
+        .filter(|d| !d.trim().is_empty())
+        .map(Dimension::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if dimensions.is_empty() {
+        return Err("group_by must name at least one dimension".into());
+    }
+
+    Ok(dimensions)
+}
+
+#[derive(Debug, Deserialize)]
+struct Query {
+    #[serde(default, deserialize_with = "super::date")]
+    from: Option<NaiveDate>,
+    #[serde(default, deserialize_with = "super::date")]
+    to: Option<NaiveDate>,
+    group_by: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Totals {
+    entries: usize,
+    minutes: u64,
+    hours: f64,
+}
+
+impl Totals {
+    fn add(&mut self, minutes: f64) {
+        self.entries += 1;
+        self.minutes += minutes.round().max(0.0) as u64;
+        self.hours = round(self.minutes as f64 / 60.0);
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Group {
+    #[serde(flatten)]
+    key: Map<String, Value>,
+    #[serde(flatten)]
+    totals: Totals,
+}
+
+/// Sums the time of the entries in the range by the dimensions.
+fn aggregate(
+    entries: &[TimeEntry],
+    range: DateRange,
+    dimensions: &[Dimension],
+) -> (Vec<Group>, Totals) {
+    let mut groups: BTreeMap<Vec<String>, Group> = BTreeMap::new();
+    let mut totals = Totals::default();
+
+    for entry in entries {
+        let date = match entry_date(entry) {
+            Some(date) if range.contains(date) => date,
+            _ => continue,
+        };
+
+        let fields: Vec<(&'static str, Value)> = dimensions
+            .iter()
+            .flat_map(|d| d.fields(entry, date))
+            .collect();
+
+        let sort_key = fields.iter().map(|(_, v)| v.to_string()).collect();
+
+        let group = groups.entry(sort_key).or_insert_with(|| Group {
+            key: fields
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+            totals: Totals::default(),
+        });
+
+        let minutes = minutes(entry);
+        group.totals.add(minutes);
+        totals.add(minutes);
+    }
+
+    (groups.into_values().collect(), totals)
+}
+
+/// Reports the time logged in a date range, grouped by `group_by`.
+pub async fn handler(req: Request<State>) -> tide::Result {
+    let query: Query = req.query().map_err(|e| Error::QueryError(e.to_string()))?;
+
+    let range = DateRange::new(query.from, query.to).map_err(Error::QueryError)?;
+    let dimensions =
+        dimensions(query.group_by.as_deref().unwrap_or("person")).map_err(Error::QueryError)?;
+
+    let upstream = Upstream::new(&req)?;
+    let params: HashMap<_, _> = range.teamwork_params().into_iter().collect();
+    let entries = upstream.get_all::<TimeEntry>(&params).await?;
+
+    let (groups, totals) = aggregate(&entries, range, &dimensions);
+
+    Ok(Response::builder(200)
+        .body(Body::from_json(&json!({
+            "data": groups,
+            "meta": {
+                "from": range.from.format(DATE_FORMAT).to_string(),
+                "to": range.to.format(DATE_FORMAT).to_string(),
+                "group_by": dimensions,
+                "totals": totals,
+            },
+        }))?)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<TimeEntry> {
+        serde_json::from_value(json!([
+            {
+                "person-id": "1", "person-first-name": "Holly", "person-last-name": "McGill",
+                "project-id": "10", "project-name": "Website", "isbillable": "1",
+                "date": "2024-03-01T23:30:00Z", "dateUserPerspective": "2024-03-02T00:30:00Z",
+                "hours": "1", "minutes": "30",
+            },
+            {
+                "person-id": "1", "person-first-name": "Holly", "person-last-name": "McGill",
+                "project-id": "11", "project-name": "App", "isbillable": "0",
+                "dateUserPerspective": "2024-03-02T09:00:00Z",
+                "hours": "0", "minutes": "45",
+            },
+            {
+                "person-id": "2", "person-first-name": "Kyle", "person-last-name": "Bracken",
+                "project-id": "10", "project-name": "Website", "isbillable": "1",
+                "dateUserPerspective": "2024-03-03T09:00:00Z",
+                "hours": "2", "minutes": "",
+            },
+        ]))
+        .unwrap()
+    }
+
+    fn range(from: u32, to: u32) -> DateRange {
+        DateRange::new(
+            NaiveDate::from_ymd_opt(2024, 3, from),
+            NaiveDate::from_ymd_opt(2024, 3, to),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn sums_time_by_each_dimension() {
+        let (groups, totals) = aggregate(
+            &entries(),
+            range(1, 31),
+            &[Dimension::Person, Dimension::Billable],
+        );
+
+        let groups = serde_json::to_value(&groups).unwrap();
+
+        assert_eq!(
+            groups,
+            json!([
+                { "person_id": "1", "person": "Holly McGill", "billable": false, "entries": 1, "minutes": 45, "hours": 0.75 },
+                { "person_id": "1", "person": "Holly McGill", "billable": true, "entries": 1, "minutes": 90, "hours": 1.5 },
+                { "person_id": "2", "person": "Kyle Bracken", "billable": true, "entries": 1, "minutes": 120, "hours": 2.0 },
+            ])
+        );
+        assert_eq!((totals.entries, totals.minutes), (3, 255));
+    }
+
+    #[test]
+    fn entries_fall_on_the_day_they_were_logged_for() {
+        // the first entry was logged on the 2nd for its author, although it's
+        // the 1st in UTC
+        let (groups, totals) = aggregate(&entries(), range(1, 2), &[Dimension::Day]);
+
+        let groups = serde_json::to_value(&groups).unwrap();
+
+        assert_eq!(
+            groups,
+            json!([{ "day": "2024-03-02", "entries": 2, "minutes": 135, "hours": 2.25 }])
+        );
+        assert_eq!(totals.entries, 2);
+    }
+
+    #[test]
+    fn fetches_the_days_around_the_range() {
+        let params = range(1, 2).teamwork_params();
+
+        assert_eq!(
+            params,
+            [
+                ("fromdate".to_string(), json!("20240229")),
+                ("todate".to_string(), json!("20240303")),
+            ]
+        );
+
+        // the entries of the days around the range are fetched but left out
+        let (_, totals) = aggregate(&entries(), range(2, 2), &[Dimension::Day]);
+        assert_eq!((totals.entries, totals.minutes), (2, 135));
+    }
+
+    #[test]
+    fn parses_dimensions() {
+        assert_eq!(
+            dimensions("project, week").unwrap(),
+            [Dimension::Project, Dimension::Week]
+        );
+        assert!(dimensions("").is_err());
+        assert!(dimensions("person,year").is_err());
+    }
+}
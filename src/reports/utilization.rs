@@ -0,0 +1,344 @@
+//! Billable utilization per person and period, along with the billable time
+//! not yet billed by company.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use teamwork_schema::TimeEntry;
+use tide::{Body, Request, Response};
+
+use super::{
+    entry_date, flag, minutes, nonblank, person, round, time::Dimension, DateRange, DATE_FORMAT,
+};
+use crate::{upstream::Upstream, Error, State};
+
+#[derive(Debug, Deserialize)]
+struct Query {
+    #[serde(default, deserialize_with = "super::date")]
+    from: Option<NaiveDate>,
+    #[serde(default, deserialize_with = "super::date")]
+    to: Option<NaiveDate>,
+    period: Option<String>,
+}
+
+/// Whether a time entry has been billed, either flagged as billed or included
+/// on an invoice.
+fn billed(entry: &TimeEntry) -> bool {
+    flag(&entry.isbilled) || nonblank(&entry.invoice_no).is_some()
+}
+
+#[derive(Debug, Default)]
+struct Minutes {
+    total: f64,
+    billable: f64,
+    billed: f64,
+}
+
+impl Minutes {
+    fn add(&mut self, entry: &TimeEntry) {
+        let minutes = minutes(entry);
+
+        self.total += minutes;
+
+        if flag(&entry.isbillable) {
+            self.billable += minutes;
+
+            if billed(entry) {
+                self.billed += minutes;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Utilization {
+    total_hours: f64,
+    billable_hours: f64,
+    non_billable_hours: f64,
+    /// The share of the logged time that's billable, between 0 and 1.
+    billable_share: Option<f64>,
+    billed_hours: f64,
+    unbilled_hours: f64,
+}
+
+impl From<&Minutes> for Utilization {
+    fn from(minutes: &Minutes) -> Self {
+        Utilization {
+            total_hours: round(minutes.total / 60.0),
+            billable_hours: round(minutes.billable / 60.0),
+            non_billable_hours: round((minutes.total - minutes.billable) / 60.0),
+            billable_share: if minutes.total > 0.0 {
+                Some((minutes.billable / minutes.total * 1000.0).round() / 1000.0)
+            } else {
+                None
+            },
+            billed_hours: round(minutes.billed / 60.0),
+            unbilled_hours: round((minutes.billable - minutes.billed) / 60.0),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Backlog {
+    company: Option<String>,
+    minutes: f64,
+    entries: usize,
+    oldest: Option<NaiveDate>,
+}
+
+/// The key fields of a person's period and the minutes logged in it.
+type PersonPeriod = (Map<String, Value>, Minutes);
+
+struct Report {
+    people: Vec<Value>,
+    totals: Utilization,
+}
+
+fn aggregate(entries: &[TimeEntry], range: DateRange, period: Dimension) -> Report {
+    let mut people: BTreeMap<(String, String, String), PersonPeriod> = BTreeMap::new();
+    let mut totals = Minutes::default();
+
+    for entry in entries {
+        let date = match entry_date(entry) {
+            Some(date) if range.contains(date) => date,
+            _ => continue,
+        };
+
+        let person_id = nonblank(&entry.person_id).unwrap_or_default().to_string();
+        let period_fields = period.fields(entry, date);
+        let period_key = period_fields
+            .iter()
+            .map(|(_, v)| v.to_string())
+            .collect::<String>();
+
+        let (_, minutes) = people
+            .entry((period_key, person(entry), person_id.clone()))
+            .or_insert_with(|| {
+                let mut key = Map::new();
+                key.insert("person_id".into(), json!(person_id));
+                key.insert("person".into(), json!(person(entry)));
+                key.extend(period_fields.into_iter().map(|(k, v)| (k.to_string(), v)));
+                (key, Minutes::default())
+            });
+
+        minutes.add(entry);
+        totals.add(entry);
+    }
+
+    let people = people
+        .into_values()
+        .map(|(mut key, minutes)| {
+            if let Value::Object(utilization) = json!(Utilization::from(&minutes)) {
+                key.extend(utilization);
+            }
+            Value::Object(key)
+        })
+        .collect();
+
+    Report {
+        people,
+        totals: Utilization::from(&totals),
+    }
+}
+
+/// The billable time not yet billed by company, largest first. Unlike the
+/// utilization it isn't limited to a date range, since time logged before the
+/// range is still owed.
+fn backlog(entries: &[TimeEntry]) -> Vec<Value> {
+    let mut backlog: BTreeMap<String, Backlog> = BTreeMap::new();
+
+    for entry in entries {
+        if !flag(&entry.isbillable) || billed(entry) {
+            continue;
+        }
+
+        let company_id = nonblank(&entry.company_id).unwrap_or_default();
+        let backlog = backlog.entry(company_id.to_string()).or_default();
+
+        backlog.company = backlog
+            .company
+            .take()
+            .or_else(|| nonblank(&entry.company_name).map(str::to_string));
+        backlog.minutes += minutes(entry);
+        backlog.entries += 1;
+
+        if let Some(date) = entry_date(entry) {
+            backlog.oldest = Some(backlog.oldest.map_or(date, |oldest| oldest.min(date)));
+        }
+    }
+
+    let mut backlog: Vec<(String, Backlog)> = backlog.into_iter().collect();
+    backlog.sort_by(|(_, a), (_, b)| b.minutes.total_cmp(&a.minutes));
+
+    backlog
+        .into_iter()
+        .map(|(company_id, backlog)| {
+            json!({
+                "company_id": company_id,
+                "company": backlog.company,
+                "unbilled_hours": round(backlog.minutes / 60.0),
+                "entries": backlog.entries,
+                "oldest_unbilled": backlog.oldest.map(|d| d.format(DATE_FORMAT).to_string()),
+            })
+        })
+        .collect()
+}
+
+/// The params limiting Teamwork's time entries to the billable ones that
+/// haven't been invoiced, across every date.
+fn unbilled_params() -> HashMap<String, Value> {
+    let mut params = HashMap::new();
+    params.insert("billableType".to_string(), json!("billable"));
+    params.insert("invoicedType".to_string(), json!("noninvoiced"));
+    params
+}
+
+/// Reports the billable utilization of each person per period in a date
+/// range, along with the unbilled backlog by company.
+pub async fn handler(req: Request<State>) -> tide::Result {
+    let query: Query = req.query().map_err(|e| Error::QueryError(e.to_string()))?;
+
+    let range = DateRange::new(query.from, query.to).map_err(Error::QueryError)?;
+    let period = match query.period.as_deref().unwrap_or("week") {
+        "day" => Dimension::Day,
+        "week" => Dimension::Week,
+        "month" => Dimension::Month,
+        other => Err(Error::QueryError(format!(
+            "unknown period {}, expected day, week or month",
+            other
+        )))?,
+    };
+
+    let upstream = Upstream::new(&req)?;
+    // the params cover a day either side of the range, the entries logged for
+    // days outside of it are left out by `aggregate`
+    let params: HashMap<_, _> = range.teamwork_params().into_iter().collect();
+    let unbilled_params = unbilled_params();
+    let (entries, unbilled) = futures::future::try_join(
+        upstream.get_all::<TimeEntry>(&params),
+        upstream.get_all::<TimeEntry>(&unbilled_params),
+    )
+    .await?;
+
+    let report = aggregate(&entries, range, period);
+
+    Ok(Response::builder(200)
+        .body(Body::from_json(&json!({
+            "data": {
+                "people": report.people,
+                "unbilled_backlog": backlog(&unbilled),
+            },
+            "meta": {
+                "from": range.from.format(DATE_FORMAT).to_string(),
+                "to": range.to.format(DATE_FORMAT).to_string(),
+                "period": period,
+                "totals": report.totals,
+            },
+        }))?)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_billable_share_and_unbilled_backlog() {
+        // the backlog includes unbilled time from before the range
+        let entries: Vec<TimeEntry> = serde_json::from_value(json!([
+            {
+                "person-id": "1", "person-first-name": "Holly", "isbillable": "1", "isbilled": "1",
+                "company-id": "5", "company-name": "Acme",
+                "dateUserPerspective": "2024-03-04T09:00:00Z", "hours": "2", "minutes": "0",
+            },
+            {
+                "person-id": "1", "person-first-name": "Holly", "isbillable": "1", "isbilled": "0",
+                "company-id": "5", "company-name": "Acme",
+                "dateUserPerspective": "2024-03-05T09:00:00Z", "hours": "1", "minutes": "0",
+            },
+            {
+                "person-id": "1", "person-first-name": "Holly", "isbillable": "1", "invoiceNo": "INV-1",
+                "company-id": "6", "company-name": "Globex",
+                "dateUserPerspective": "2024-03-05T10:00:00Z", "hours": "0", "minutes": "30",
+            },
+            {
+                "person-id": "1", "person-first-name": "Holly", "isbillable": "0",
+                "dateUserPerspective": "2024-03-06T09:00:00Z", "hours": "0", "minutes": "30",
+            },
+            {
+                "person-id": "1", "person-first-name": "Holly", "isbillable": "1",
+                "company-id": "5", "company-name": "Acme",
+                "dateUserPerspective": "2023-12-20T09:00:00Z", "hours": "1", "minutes": "0",
+            },
+        ]))
+        .unwrap();
+
+        let range = DateRange::new(
+            NaiveDate::from_ymd_opt(2024, 3, 1),
+            NaiveDate::from_ymd_opt(2024, 3, 31),
+        )
+        .unwrap();
+
+        let report = aggregate(&entries, range, Dimension::Week);
+        let backlog = backlog(&entries);
+
+        assert_eq!(
+            report.people,
+            vec![json!({
+                "person_id": "1",
+                "person": "Holly",
+                "week": "2024-W10",
+                "total_hours": 4.0,
+                "billable_hours": 3.5,
+                "non_billable_hours": 0.5,
+                "billable_share": 0.875,
+                "billed_hours": 2.5,
+                "unbilled_hours": 1.0,
+            })]
+        );
+        assert_eq!(
+            backlog,
+            vec![json!({
+                "company_id": "5",
+                "company": "Acme",
+                "unbilled_hours": 2.0,
+                "entries": 2,
+                "oldest_unbilled": "2023-12-20",
+            })]
+        );
+        assert_eq!(report.totals.billable_share, Some(0.875));
+    }
+
+    #[test]
+    fn counts_entries_on_the_day_they_were_logged_for() {
+        // both entries are fetched with the range widened by a day, but only
+        // the first was logged for a day in the range
+        let entries: Vec<TimeEntry> = serde_json::from_value(json!([
+            {
+                "person-id": "1", "person-first-name": "Holly", "isbillable": "1",
+                "date": "2024-02-29T23:30:00Z", "dateUserPerspective": "2024-03-01T00:30:00Z",
+                "hours": "1", "minutes": "0",
+            },
+            {
+                "person-id": "1", "person-first-name": "Holly", "isbillable": "1",
+                "date": "2024-03-31T23:30:00Z", "dateUserPerspective": "2024-04-01T00:30:00Z",
+                "hours": "2", "minutes": "0",
+            },
+        ]))
+        .unwrap();
+
+        let range = DateRange::new(
+            NaiveDate::from_ymd_opt(2024, 3, 1),
+            NaiveDate::from_ymd_opt(2024, 3, 31),
+        )
+        .unwrap();
+
+        let report = aggregate(&entries, range, Dimension::Month);
+
+        assert_eq!(report.people.len(), 1);
+        assert_eq!(report.people[0]["month"], "2024-03");
+        assert_eq!(report.people[0]["total_hours"], 1.0);
+    }
+}
@@ -0,0 +1,126 @@
+//! Full-text search across the mirrored tasks, task lists and time entries,
+//! which Teamwork's own search can't do in one request. Answered from the
+//! mirror's index, so it's only available with the configured API key.
+//!
+//! `/search?q=` matches the records holding every term of `q`, where double
+//! quotes group a phrase and a trailing `*` matches a prefix. Results can be
+//! limited to a `project` and to a comma separated list of `type`s.
+
+use serde::Deserialize;
+use tide::{Body, Request, Response};
+
+use crate::{
+    mirror::search::{match_expression, SearchHit},
+    upstream::{self, Upstream},
+    uses_api_key, ApiResponse, Error, Links, Meta, State,
+};
+
+/// The types of records that can be searched.
+const TYPES: &[&str] = &["task", "task_list", "time_entry"];
+
+#[derive(Debug, Deserialize)]
+struct Query {
+    q: Option<String>,
+    project: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    #[serde(default = "Query::default_page")]
+    page: usize,
+    #[serde(rename = "pageSize")]
+    page_size: Option<usize>,
+}
+
+impl Query {
+    fn default_page() -> usize {
+        1
+    }
+}
+
+pub async fn handler(req: Request<State>) -> tide::Result {
+    let query: Query = req.query().map_err(|e| Error::QueryError(e.to_string()))?;
+
+    let upstream = Upstream::new(&req)?;
+
+    let mirror = req.state().mirror.clone().ok_or_else(|| {
+        Error::MirrorUnavailable("search needs the local mirror, which isn't configured".into())
+    })?;
+
+    if !uses_api_key(&req, &upstream) {
+        Err(Error::Forbidden(
+            "search is only available with the configured API key",
+        ))?;
+    }
+
+    let expression = query
+        .q
+        .as_deref()
+        .and_then(match_expression)
+        .ok_or_else(|| Error::QueryError("q is required".into()))?;
+
+    let kinds = query
+        .kind
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+        .map(|kind| {
+            TYPES.iter().copied().find(|t| *t == kind).ok_or_else(|| {
+                Error::QueryError(format!(
+                    "unknown type {}, expected one of {}",
+                    kind,
+                    TYPES.join(", ")
+                ))
+            })
+        })
+        .collect::<Result<Vec<&str>, Error>>()?;
+
+    let per_page = query
+        .page_size
+        .unwrap_or(upstream::MAX_PAGE_SIZE)
+        .clamp(1, upstream::MAX_PAGE_SIZE);
+
+    let project = query
+        .project
+        .as_deref()
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(str::to_string);
+    let page_number = query.page;
+
+    // SQLite blocks, keep the reads off the executor's threads
+    let page = async_std::task::spawn_blocking(move || {
+        mirror.search(
+            &expression,
+            project.as_deref(),
+            &kinds,
+            page_number,
+            per_page,
+        )
+    })
+    .await?
+    .ok_or_else(|| Error::MirrorUnavailable("the mirror hasn't been synced yet".into()))?;
+
+    let meta = Meta {
+        page: query.page,
+        total_pages: page.total_pages,
+    };
+
+    let links = Links::new(req.url(), &meta);
+    let link_header = links.header();
+
+    let mut res = Response::builder(200)
+        .body(Body::from_json(&ApiResponse::<SearchHit> {
+            data: page.hits,
+            meta,
+            links,
+            included: None,
+            included_errors: None,
+        })?)
+        .build();
+
+    res.insert_header("Link", link_header);
+    res.insert_header("X-Total-Count", page.total.to_string());
+
+    Ok(res)
+}
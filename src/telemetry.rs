@@ -0,0 +1,651 @@
+//! Request tracing. Every inbound request creates a server span, with child
+//! spans for the work done while handling it, exported as OTLP/HTTP JSON to the
+//! collector configured by `OTEL_EXPORTER_OTLP_ENDPOINT`.
+//!
+//! The W3C `traceparent` and `tracestate` headers are honoured on inbound
+//! requests and propagated to Teamwork so that the spans join the caller's
+//! trace.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use serde_json::json;
+use tide::{Middleware, Next, Request};
+
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+pub const TRACESTATE_HEADER: &str = "tracestate";
+
+/// The longest `tracestate` propagated, the limit W3C allows vendors to
+/// truncate to.
+const MAX_TRACESTATE_LEN: usize = 512;
+
+/// Spans buffered beyond this are dropped rather than growing without bound
+/// when the collector is unavailable.
+const MAX_PENDING: usize = 2048;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex<const N: usize>(s: &str) -> Option<[u8; N]> {
+    if s.len() != N * 2 {
+        return None;
+    }
+
+    let mut bytes = [0; N];
+
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(s.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+
+    Some(bytes)
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    bytes.copy_from_slice(&uuid::Uuid::new_v4().as_bytes()[..N]);
+    bytes
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Identifies a span within a trace, as carried by the `traceparent` header,
+/// along with the vendor specific `tracestate` that travels with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpanContext {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    sampled: bool,
+    trace_state: Option<String>,
+}
+
+impl SpanContext {
+    /// Parses a version 00 `traceparent` header, returning `None` when it's
+    /// malformed or contains the invalid all zero ids.
+    pub fn from_traceparent(header: &str) -> Option<Self> {
+        let mut parts = header.trim().split('-');
+
+        let version = parts.next()?;
+        let trace_id = from_hex::<16>(parts.next()?)?;
+        let span_id = from_hex::<8>(parts.next()?)?;
+        let flags = from_hex::<1>(parts.next()?)?;
+
+        if version != "00" || parts.next().is_some() {
+            return None;
+        }
+
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+
+        Some(SpanContext {
+            trace_id,
+            span_id,
+            sampled: flags[0] & 1 == 1,
+            trace_state: None,
+        })
+    }
+
+    /// Carries the `tracestate` header along with the context, unless it's
+    /// empty, too long or not printable ASCII, in which case it's dropped as
+    /// the W3C spec allows.
+    pub fn with_tracestate(mut self, header: &str) -> Self {
+        let header = header.trim();
+
+        let valid = !header.is_empty()
+            && header.len() <= MAX_TRACESTATE_LEN
+            && header.bytes().all(|b| (0x20..=0x7e).contains(&b));
+
+        self.trace_state = if valid {
+            Some(header.to_string())
+        } else {
+            None
+        };
+        self
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            hex(&self.trace_id),
+            hex(&self.span_id),
+            self.sampled as u8
+        )
+    }
+
+    pub fn tracestate(&self) -> Option<&str> {
+        self.trace_state.as_deref()
+    }
+
+    pub fn trace_id(&self) -> String {
+        hex(&self.trace_id)
+    }
+}
+
+/// The kind of span, numbered as in the OTLP protocol.
+#[derive(Debug, Clone, Copy)]
+pub enum SpanKind {
+    Internal = 1,
+    Server = 2,
+    Client = 3,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AttributeValue {
+    StringValue(String),
+    /// OTLP JSON encodes 64 bit integers as strings.
+    IntValue(String),
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> Self {
+        AttributeValue::StringValue(value.to_string())
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(value: String) -> Self {
+        AttributeValue::StringValue(value)
+    }
+}
+
+impl From<u16> for AttributeValue {
+    fn from(value: u16) -> Self {
+        AttributeValue::IntValue(value.to_string())
+    }
+}
+
+impl From<usize> for AttributeValue {
+    fn from(value: usize) -> Self {
+        AttributeValue::IntValue(value.to_string())
+    }
+}
+
+fn attributes(attributes: &[(&'static str, AttributeValue)]) -> Vec<serde_json::Value> {
+    attributes
+        .iter()
+        .map(|(key, value)| json!({ "key": key, "value": value }))
+        .collect()
+}
+
+/// Buffers finished spans and periodically posts them to the collector.
+#[derive(Debug)]
+struct Exporter {
+    client: surf::Client,
+    url: String,
+    service_name: String,
+    pending: Mutex<Vec<serde_json::Value>>,
+}
+
+impl Exporter {
+    fn push(&self, span: serde_json::Value) {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+
+        if pending.len() < MAX_PENDING {
+            pending.push(span);
+        }
+    }
+
+    fn take(&self) -> Vec<serde_json::Value> {
+        std::mem::take(&mut *self.pending.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    async fn export(&self) {
+        let spans = self.take();
+
+        if spans.is_empty() {
+            return;
+        }
+
+        let count = spans.len();
+
+        let body = json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": attributes(&[("service.name", self.service_name.as_str().into())]),
+                },
+                "scopeSpans": [{
+                    "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                    "spans": spans,
+                }],
+            }],
+        });
+
+        let request = match surf::Body::from_json(&body) {
+            Ok(body) => surf::post(&self.url).body(body),
+            Err(e) => {
+                tide::log::error!("Failed to encode spans", { error: e.to_string() });
+                return;
+            }
+        };
+
+        match self.client.send(request).await {
+            Ok(response) if response.status().is_success() => {}
+            Ok(response) => tide::log::warn!("Collector rejected spans", {
+                status: u16::from(response.status()),
+                spans: count,
+            }),
+            Err(e) => tide::log::warn!("Failed to export spans", {
+                error: e.to_string(),
+                spans: count,
+            }),
+        }
+    }
+}
+
+/// Creates spans and hands them to the exporter when they end. Without a
+/// collector configured spans are still created, so that the `traceparent`
+/// is propagated, but they're discarded.
+#[derive(Debug, Clone, Default)]
+pub struct Tracer {
+    exporter: Option<Arc<Exporter>>,
+}
+
+impl Tracer {
+    /// A tracer exporting to the OTLP/HTTP `endpoint`, flushing the buffered
+    /// spans every `interval` and giving up on an export after `timeout`.
+    pub fn new(
+        endpoint: &str,
+        service_name: &str,
+        interval: Duration,
+        timeout: Duration,
+    ) -> Result<Self, isahc::Error> {
+        use isahc::config::Configurable;
+
+        let client = isahc::HttpClient::builder()
+            .connect_timeout(timeout)
+            .timeout(timeout)
+            .build()?;
+
+        let exporter = Arc::new(Exporter {
+            client: surf::Client::with_http_client(http_client::isahc::IsahcClient::from_client(
+                client,
+            )),
+            url: format!("{}/v1/traces", endpoint.trim_end_matches('/')),
+            service_name: service_name.to_string(),
+            pending: Mutex::new(vec![]),
+        });
+
+        let background = exporter.clone();
+
+        async_std::task::spawn(async move {
+            loop {
+                async_std::task::sleep(interval).await;
+                background.export().await;
+            }
+        });
+
+        Ok(Tracer {
+            exporter: Some(exporter),
+        })
+    }
+
+    /// Starts a span, continuing the trace of `parent` or starting a new one.
+    pub fn start(
+        &self,
+        name: impl Into<String>,
+        kind: SpanKind,
+        parent: Option<SpanContext>,
+    ) -> Span {
+        let context = SpanContext {
+            trace_id: parent.as_ref().map_or_else(random_bytes, |p| p.trace_id),
+            span_id: random_bytes(),
+            sampled: parent.as_ref().is_none_or(|p| p.sampled),
+            trace_state: parent.as_ref().and_then(|p| p.trace_state.clone()),
+        };
+
+        Span {
+            tracer: self.clone(),
+            context,
+            parent: parent.map(|p| p.span_id),
+            name: name.into(),
+            kind,
+            start: SystemTime::now(),
+            attributes: vec![],
+            error: None,
+        }
+    }
+}
+
+/// A unit of work within a trace, exported when it's dropped.
+#[derive(Debug)]
+pub struct Span {
+    tracer: Tracer,
+    context: SpanContext,
+    parent: Option<[u8; 8]>,
+    name: String,
+    kind: SpanKind,
+    start: SystemTime,
+    attributes: Vec<(&'static str, AttributeValue)>,
+    error: Option<String>,
+}
+
+impl Span {
+    pub fn context(&self) -> &SpanContext {
+        &self.context
+    }
+
+    pub fn set_attribute(&mut self, key: &'static str, value: impl Into<AttributeValue>) {
+        self.attributes.push((key, value.into()));
+    }
+
+    /// Marks the span as failed.
+    pub fn set_error(&mut self, message: impl Into<String>) {
+        self.error = Some(message.into());
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let exporter = match &self.tracer.exporter {
+            Some(exporter) if self.context.sampled => exporter,
+            _ => return,
+        };
+
+        let status = match &self.error {
+            Some(message) => json!({ "code": 2, "message": message }),
+            None => json!({ "code": 0 }),
+        };
+
+        exporter.push(json!({
+            "traceId": hex(&self.context.trace_id),
+            "spanId": hex(&self.context.span_id),
+            "parentSpanId": self.parent.as_ref().map(|id| hex(id)).unwrap_or_default(),
+            "name": self.name,
+            "kind": self.kind as u8,
+            "startTimeUnixNano": unix_nanos(self.start),
+            "endTimeUnixNano": unix_nanos(SystemTime::now()),
+            "attributes": attributes(&self.attributes),
+            "status": status,
+        }));
+    }
+}
+
+/// The trace a request belongs to, inserted into the request's extensions by
+/// the `Tracing` middleware so handlers can create child spans.
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    tracer: Tracer,
+    context: Option<SpanContext>,
+}
+
+impl Trace {
+    pub fn span(&self, name: impl Into<String>, kind: SpanKind) -> Span {
+        self.tracer.start(name, kind, self.context.clone())
+    }
+
+    pub fn trace_id(&self) -> Option<String> {
+        self.context.as_ref().map(|c| c.trace_id())
+    }
+}
+
+/// Middleware creating a server span for every request.
+#[derive(Debug, Clone, Default)]
+pub struct Tracing {
+    tracer: Tracer,
+}
+
+impl Tracing {
+    pub fn new(tracer: Tracer) -> Self {
+        Tracing { tracer }
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for Tracing {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let parent = req
+            .header(TRACEPARENT_HEADER)
+            .and_then(|h| SpanContext::from_traceparent(h.as_str()))
+            .map(|context| match req.header(TRACESTATE_HEADER) {
+                // Multiple `tracestate` headers are combined into one list.
+                Some(values) => context.with_tracestate(
+                    &values
+                        .iter()
+                        .map(|v| v.as_str())
+                        .collect::<Vec<_>>()
+                        .join(","),
+                ),
+                None => context,
+            });
+
+        let method = req.method().to_string();
+        let route = req.url().path().to_string();
+
+        let mut span = self
+            .tracer
+            .start(format!("{} {}", method, route), SpanKind::Server, parent);
+
+        span.set_attribute("http.method", method);
+        span.set_attribute("http.target", route);
+
+        req.set_ext(Trace {
+            tracer: self.tracer.clone(),
+            context: Some(span.context().clone()),
+        });
+
+        let res = next.run(req).await;
+
+        let status: u16 = res.status().into();
+        span.set_attribute("http.status_code", status);
+
+        if res.status().is_server_error() {
+            span.set_error(
+                res.error()
+                    .map_or_else(|| res.status().to_string(), |e| e.to_string()),
+            );
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::{
+        io::{
+            prelude::{BufReadExt, ReadExt, WriteExt},
+            BufReader,
+        },
+        net::TcpListener,
+        task,
+    };
+    use tide::http::{Method, Url};
+
+    use super::*;
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    /// A stand-in collector accepting a single export, replying only when
+    /// `respond` is set, and returning the body it received.
+    async fn collector(respond: bool) -> (String, task::JoinHandle<serde_json::Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+
+        let received = task::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut reader = BufReader::new(stream.clone());
+            let mut length = 0;
+
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+
+                if line.trim().is_empty() {
+                    break;
+                }
+
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).await.unwrap();
+
+            if respond {
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                    .await
+                    .unwrap();
+            } else {
+                task::sleep(Duration::from_secs(60)).await;
+            }
+
+            serde_json::from_slice(&body).unwrap()
+        });
+
+        (endpoint, received)
+    }
+
+    #[test]
+    fn traceparent_round_trips() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let context = SpanContext::from_traceparent(header).unwrap();
+
+        assert!(context.sampled);
+        assert_eq!(context.traceparent(), header);
+    }
+
+    #[test]
+    fn invalid_traceparent_is_ignored() {
+        for header in &[
+            "",
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+        ] {
+            assert_eq!(SpanContext::from_traceparent(header), None);
+        }
+    }
+
+    #[test]
+    fn tracestate_is_carried_when_valid() {
+        let context = SpanContext::from_traceparent(TRACEPARENT).unwrap();
+
+        assert_eq!(
+            context
+                .clone()
+                .with_tracestate(" congo=t61rcWkgMzE ")
+                .tracestate(),
+            Some("congo=t61rcWkgMzE")
+        );
+        assert_eq!(context.clone().with_tracestate("").tracestate(), None);
+        assert_eq!(
+            context.clone().with_tracestate("a=\u{e9}").tracestate(),
+            None
+        );
+        assert_eq!(context.with_tracestate(&"a".repeat(513)).tracestate(), None);
+    }
+
+    #[test]
+    fn inbound_trace_context_is_continued() {
+        let mut app = tide::new();
+        app.with(Tracing::default());
+        app.at("/").get(|req: Request<()>| async move {
+            let span = req
+                .ext::<Trace>()
+                .unwrap()
+                .span("child", SpanKind::Internal);
+
+            Ok(format!(
+                "{} {}",
+                span.context().trace_id(),
+                span.context().tracestate().unwrap_or_default()
+            ))
+        });
+
+        let mut req =
+            tide::http::Request::new(Method::Get, Url::parse("http://localhost/").unwrap());
+        req.insert_header(TRACEPARENT_HEADER, TRACEPARENT);
+        req.append_header(TRACESTATE_HEADER, "rojo=00f067aa0ba902b7");
+        req.append_header(TRACESTATE_HEADER, "congo=t61rcWkgMzE");
+
+        let mut res: tide::http::Response = task::block_on(app.respond(req)).unwrap();
+
+        assert_eq!(
+            task::block_on(res.body_string()).unwrap(),
+            "4bf92f3577b34da6a3ce929d0e0e4736 rojo=00f067aa0ba902b7,congo=t61rcWkgMzE"
+        );
+    }
+
+    #[test]
+    fn spans_are_exported_to_the_collector() {
+        task::block_on(async {
+            let (endpoint, received) = collector(true).await;
+            let tracer = Tracer::new(
+                &endpoint,
+                "teamwork_api",
+                Duration::from_secs(3600),
+                Duration::from_secs(5),
+            )
+            .unwrap();
+
+            let parent = SpanContext::from_traceparent(TRACEPARENT).unwrap();
+
+            {
+                let mut span = tracer.start("GET /tasks", SpanKind::Server, Some(parent));
+                span.set_attribute("http.status_code", 502u16);
+                span.set_error("Bad Gateway");
+            }
+
+            tracer.exporter.as_ref().unwrap().export().await;
+
+            let body = received.await;
+            let resource = &body["resourceSpans"][0];
+
+            assert_eq!(
+                resource["resource"]["attributes"][0],
+                json!({ "key": "service.name", "value": { "stringValue": "teamwork_api" } })
+            );
+
+            let span = &resource["scopeSpans"][0]["spans"][0];
+
+            assert_eq!(span["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+            assert_eq!(span["parentSpanId"], "00f067aa0ba902b7");
+            assert_eq!(span["name"], "GET /tasks");
+            assert_eq!(span["kind"], 2);
+            assert_eq!(
+                span["attributes"][0],
+                json!({ "key": "http.status_code", "value": { "intValue": "502" } })
+            );
+            assert_eq!(
+                span["status"],
+                json!({ "code": 2, "message": "Bad Gateway" })
+            );
+        });
+    }
+
+    #[test]
+    fn exports_give_up_after_the_timeout() {
+        task::block_on(async {
+            let (endpoint, _received) = collector(false).await;
+            let tracer = Tracer::new(
+                &endpoint,
+                "teamwork_api",
+                Duration::from_secs(3600),
+                Duration::from_millis(200),
+            )
+            .unwrap();
+
+            drop(tracer.start("GET /tasks", SpanKind::Server, None));
+
+            let started = std::time::Instant::now();
+            tracer.exporter.as_ref().unwrap().export().await;
+
+            assert!(started.elapsed() < Duration::from_secs(5));
+        });
+    }
+}
@@ -0,0 +1,355 @@
+//! Calls to the Teamwork API made on behalf of a request. Every call goes
+//! through the circuit breaker and is recorded in the request's log, metrics
+//! and trace.
+
+use std::{borrow::Cow, collections::HashMap, str::FromStr, time::Instant};
+
+use serde::{de::DeserializeOwned, Serialize};
+use teamwork_schema::{meta::Schema, Project, Task, TaskList, TimeEntry};
+use tide::Request;
+
+use crate::{
+    logging::{self, RequestId, RequestLog},
+    metrics,
+    telemetry::{self, SpanKind, Trace},
+    Error, Meta, Query, Result, State,
+};
+
+/// The page size used when fetching every page of a collection.
+pub const MAX_PAGE_SIZE: usize = 250;
+
+/// A collection proxied from Teamwork.
+pub trait Resource: DeserializeOwned + Serialize + Schema {
+    /// The Teamwork route listing the collection.
+    const ROUTE: &'static str;
+    /// The key holding the records in Teamwork's response.
+    const RESPONSE_KEY: &'static str;
+}
+
+impl Resource for Task {
+    const ROUTE: &'static str = "tasks.json";
+    const RESPONSE_KEY: &'static str = "todo-items";
+}
+
+impl Resource for TimeEntry {
+    const ROUTE: &'static str = "time_entries.json";
+    const RESPONSE_KEY: &'static str = "time-entries";
+}
+
+impl Resource for TaskList {
+    const ROUTE: &'static str = "tasklists.json";
+    const RESPONSE_KEY: &'static str = "tasklists";
+}
+
+impl Resource for Project {
+    const ROUTE: &'static str = "projects.json";
+    const RESPONSE_KEY: &'static str = "projects";
+}
+
+/// A route with its ids replaced by `{id}`, so the routes of every record
+/// share one circuit and one set of metrics.
+fn route_template(teamwork_route: &str) -> String {
+    teamwork_route
+        .split('/')
+        .map(|segment| {
+            if !segment.is_empty() && segment.bytes().all(|b| b.is_ascii_digit()) {
+                "{id}"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// A page of a collection as returned by Teamwork.
+pub struct Page {
+    pub body: Vec<u8>,
+    pub meta: Meta,
+}
+
+pub struct Upstream<'a> {
+    state: Cow<'a, State>,
+    auth: Cow<'a, str>,
+    log: RequestLog,
+    trace: Trace,
+    request_id: Option<Cow<'a, str>>,
+}
+
+impl<'a> Upstream<'a> {
+    /// Calls Teamwork with the request's authorization header, falling back to
+    /// the configured API key.
+    pub fn new(req: &'a Request<State>) -> Result<Self> {
+        let auth: Cow<'_, str> = if let Some(header) = req.header("authorization") {
+            Cow::Borrowed(header.as_str())
+        } else {
+            req.state()
+                .config
+                .api_key_auth()
+                .ok_or(Error::AuthError)
+                .map(Cow::Owned)?
+        };
+
+        let log = req.ext::<RequestLog>().cloned().unwrap_or_default();
+        log.identity(&auth);
+
+        Ok(Upstream {
+            state: Cow::Borrowed(req.state()),
+            auth,
+            log,
+            trace: req.ext::<Trace>().cloned().unwrap_or_default(),
+            request_id: req
+                .ext::<RequestId>()
+                .map(|RequestId(id)| Cow::Borrowed(id.as_str())),
+        })
+    }
+
+    /// Calls Teamwork with the configured API key outside of a request, such
+    /// as from a subcommand.
+    pub fn with_api_key(state: &'a State) -> Result<Self> {
+        let auth = state.config.api_key_auth().ok_or(Error::AuthError)?;
+
+        Ok(Upstream {
+            state: Cow::Borrowed(state),
+            auth: Cow::Owned(auth),
+            log: RequestLog::default(),
+            trace: Trace::default(),
+            request_id: None,
+        })
+    }
+
+    /// Detaches the calls from the borrowed request, so they can be made from
+    /// tasks outliving the borrow, such as GraphQL's batch loaders. They're
+    /// still recorded in the request's log and trace.
+    pub fn into_owned(self) -> Upstream<'static> {
+        Upstream {
+            state: Cow::Owned(self.state.into_owned()),
+            auth: Cow::Owned(self.auth.into_owned()),
+            log: self.log,
+            trace: self.trace,
+            request_id: self.request_id.map(|id| Cow::Owned(id.into_owned())),
+        }
+    }
+
+    pub fn log(&self) -> &RequestLog {
+        &self.log
+    }
+
+    pub fn trace(&self) -> &Trace {
+        &self.trace
+    }
+
+    /// A hash identifying who the calls are made on behalf of.
+    pub fn identity(&self) -> String {
+        logging::identity_hash(&self.auth)
+    }
+
+    fn request<Q: Serialize>(
+        &self,
+        teamwork_route: &str,
+        query: &Q,
+        span: &telemetry::Span,
+    ) -> Result<surf::Request> {
+        let mut request = self
+            .state
+            .client
+            .get(format!(
+                "{}/{}",
+                self.state.config.endpoint(),
+                teamwork_route
+            ))
+            .query(query)
+            .map_err(|e| Error::QueryError(e.to_string()))?
+            .header("Authorization", self.auth.as_ref())
+            .header(telemetry::TRACEPARENT_HEADER, span.context().traceparent());
+
+        if let Some(trace_state) = span.context().tracestate() {
+            request = request.header(telemetry::TRACESTATE_HEADER, trace_state);
+        }
+
+        if let Some(request_id) = &self.request_id {
+            request = request.header(logging::REQUEST_ID_HEADER, request_id.as_ref());
+        }
+
+        Ok(request.build())
+    }
+
+    /// Fetches a page of `teamwork_route`, failing fast while the circuit to
+    /// the route is open.
+    pub async fn get<Q: Serialize>(&self, teamwork_route: &str, query: &Q) -> Result<Page> {
+        let route = route_template(teamwork_route);
+        let breaker = &self.state.breaker;
+
+        if let Err(retry_after) = breaker.check(&route) {
+            metrics::circuit_rejection(&route);
+            return Err(Error::CircuitOpen(retry_after));
+        }
+
+        let mut span = self.trace.span(format!("GET {}", route), SpanKind::Client);
+
+        let request = self.request(teamwork_route, query, &span)?;
+        let url = request.url().to_string();
+
+        span.set_attribute("http.method", "GET");
+        span.set_attribute("http.url", url.as_str());
+
+        let start = Instant::now();
+        let response = self.state.client.send(request).await;
+        let status = response.as_ref().ok().map(|r| r.status().into());
+
+        self.log.upstream(&url, status, start.elapsed());
+        metrics::upstream_request(&route, status, start.elapsed());
+
+        match (&response, status) {
+            (Err(e), _) => span.set_error(e.to_string()),
+            (_, Some(status)) if status >= 400 => {
+                span.set_attribute("http.status_code", status);
+                span.set_error(format!("Teamwork returned {}", status));
+            }
+            (_, Some(status)) => span.set_attribute("http.status_code", status),
+            (_, None) => {}
+        }
+
+        drop(span);
+
+        // only an unavailable Teamwork counts towards opening the circuit
+        if response.is_err() || status.is_some_and(|s| s >= 500) {
+            if breaker.failure(&route) {
+                tide::log::warn!("Circuit to Teamwork opened", { route: route });
+                metrics::circuit_opened(&route);
+            }
+        } else {
+            breaker.success(&route);
+        }
+
+        let mut response = response?;
+
+        if !response.status().is_success() {
+            let body = response
+                .body_string()
+                .await
+                .map(|b| {
+                    serde_json::from_str::<serde_json::Value>(&b)
+                        .unwrap_or(serde_json::Value::String(b))
+                })
+                .ok();
+            let status: u16 = response.status().into();
+            let message = response.status().canonical_reason();
+            return Err(Error::TeamworkError(status, message, body));
+        }
+
+        let meta = Meta {
+            page: response
+                .header("X-Page")
+                .and_then(|page| usize::from_str(page.as_str()).ok())
+                .unwrap_or(1),
+            total_pages: usize::from_str(
+                response
+                    .header("X-Pages")
+                    .ok_or(Error::MissingHeader("X-Pages"))?
+                    .as_str(),
+            )
+            .map_err(|e| Error::InvalidHeader("X-Pages", e.to_string()))?,
+        };
+
+        Ok(Page {
+            body: response.body_bytes().await?,
+            meta,
+        })
+    }
+
+    /// Deserializes a Teamwork response, logging any schema drift found by
+    /// the lenient schemas.
+    pub fn deserialize<T: DeserializeOwned>(&self, teamwork_route: &str, body: &[u8]) -> Result<T> {
+        self.decode(teamwork_route, || serde_json::from_slice(body))
+    }
+
+    fn decode<T, F>(&self, teamwork_route: &str, decode: F) -> Result<T>
+    where
+        F: FnOnce() -> serde_json::Result<T>,
+    {
+        let mut span = self.trace.span("deserialize", SpanKind::Internal);
+
+        let (result, drift) = teamwork_schema::drift::collect(decode);
+
+        let result = result.map_err(|e| {
+            span.set_error(e.to_string());
+            Error::SchemaError(e.to_string())
+        });
+
+        // lenient schemas record drift rather than failing to deserialize, log
+        // it once per field so that changes to the Teamwork API are noticed
+        for summary in drift {
+            tide::log::warn!("Teamwork schema drift", {
+                route: teamwork_route,
+                schema: summary.schema,
+                field: summary.field,
+                kind: format!("{:?}", summary.kind),
+                found: summary.last_found,
+                records: summary.count,
+                message: summary.last_message.unwrap_or_default(),
+            });
+        }
+
+        result
+    }
+
+    /// Fetches every page of a collection, filtered by `params`.
+    pub async fn get_all<R: Resource>(
+        &self,
+        params: &HashMap<String, serde_json::Value>,
+    ) -> Result<Vec<R>> {
+        self.get_all_at(R::ROUTE, params).await
+    }
+
+    /// Fetches every page of a collection listed at another route, such as a
+    /// task's time entries at `tasks/{id}/time_entries.json`.
+    pub async fn get_all_at<R: Resource>(
+        &self,
+        teamwork_route: &str,
+        params: &HashMap<String, serde_json::Value>,
+    ) -> Result<Vec<R>> {
+        let mut records = vec![];
+        let mut page = 1;
+
+        loop {
+            let query = Query {
+                page,
+                per_page: Some(MAX_PAGE_SIZE),
+                other: params.clone(),
+            };
+
+            let (page_records, meta) = self.get_page_at::<R>(teamwork_route, &query).await?;
+            records.extend(page_records);
+
+            if page >= meta.total_pages {
+                return Ok(records);
+            }
+
+            page += 1;
+        }
+    }
+
+    /// Fetches a page of a collection.
+    pub async fn get_page<R: Resource>(&self, query: &Query) -> Result<(Vec<R>, Meta)> {
+        self.get_page_at(R::ROUTE, query).await
+    }
+
+    async fn get_page_at<R: Resource>(
+        &self,
+        teamwork_route: &str,
+        query: &Query,
+    ) -> Result<(Vec<R>, Meta)> {
+        let response = self.get(teamwork_route, query).await?;
+
+        let mut body: serde_json::Map<String, serde_json::Value> =
+            self.deserialize(teamwork_route, &response.body)?;
+
+        let records = match body.remove(R::RESPONSE_KEY) {
+            Some(data) => self.decode(teamwork_route, || serde_json::from_value::<Vec<R>>(data))?,
+            None => vec![],
+        };
+
+        Ok((records, response.meta))
+    }
+}
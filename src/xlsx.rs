@@ -0,0 +1,326 @@
+//! Excel workbooks of time entries for billing reviews, with a sheet per
+//! project or person and a totals row on each sheet.
+
+use std::collections::BTreeMap;
+
+use chrono::Datelike;
+use rust_xlsxwriter::{ExcelDateTime, Format, Formula, Workbook, Worksheet, XlsxError};
+use teamwork_schema::TimeEntry;
+
+use crate::reports::{entry_date, flag, minutes, nonblank, person};
+
+pub const MIME: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+
+/// The query parameter choosing how entries are split into sheets.
+pub const SHEETS_PARAM: &str = "sheets";
+
+/// How the entries are split into sheets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sheets {
+    Project,
+    Person,
+}
+
+impl Sheets {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "project" => Some(Sheets::Project),
+            "person" => Some(Sheets::Person),
+            _ => None,
+        }
+    }
+
+    fn name(self, entry: &TimeEntry) -> String {
+        match self {
+            Sheets::Project => text(&entry.project_name).to_string(),
+            Sheets::Person => person(entry),
+        }
+    }
+}
+
+const HEADERS: &[(&str, f64)] = &[
+    ("Date", 12.0),
+    ("Person", 20.0),
+    ("Project", 24.0),
+    ("Task list", 24.0),
+    ("Task", 30.0),
+    ("Description", 40.0),
+    ("Billable", 9.0),
+    ("Billed", 9.0),
+    ("Invoice", 12.0),
+    ("Hours", 9.0),
+];
+
+const HOURS_COLUMN: u16 = 9;
+
+fn text(value: &Option<String>) -> &str {
+    nonblank(value).unwrap_or_default()
+}
+
+/// A valid, unique sheet name. Excel limits names to 31 characters and
+/// disallows some punctuation.
+fn sheet_name(name: &str, used: &mut Vec<String>) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            '[' | ']' | ':' | '*' | '?' | '/' | '\\' => ' ',
+            c => c,
+        })
+        .collect();
+
+    let cleaned = cleaned.trim().trim_matches('\'');
+    let base = if cleaned.is_empty() {
+        "(none)"
+    } else {
+        cleaned
+    };
+
+    let mut name: String = base.chars().take(31).collect();
+    let mut n = 2;
+
+    while used.iter().any(|u| u.eq_ignore_ascii_case(&name)) {
+        let suffix = format!(" ({})", n);
+        name = base.chars().take(31 - suffix.len()).collect::<String>() + &suffix;
+        n += 1;
+    }
+
+    used.push(name.clone());
+    name
+}
+
+fn write_sheet(sheet: &mut Worksheet, entries: &[&TimeEntry]) -> Result<(), XlsxError> {
+    let bold = Format::new().set_bold();
+    let date = Format::new().set_num_format("yyyy-mm-dd");
+    let decimal = Format::new().set_num_format("0.00");
+
+    for (col, (header, width)) in HEADERS.iter().enumerate() {
+        sheet.write_string_with_format(0, col as u16, *header, &bold)?;
+        sheet.set_column_width(col as u16, *width)?;
+    }
+
+    sheet.set_freeze_panes(1, 0)?;
+
+    let mut total = 0.0;
+
+    for (i, entry) in entries.iter().enumerate() {
+        let row = i as u32 + 1;
+
+        // the day the entry was logged for by the person who logged it
+        let day = entry_date(entry).and_then(|day| {
+            ExcelDateTime::from_ymd(day.year() as u16, day.month() as u8, day.day() as u8).ok()
+        });
+
+        if let Some(day) = day {
+            sheet.write_datetime_with_format(row, 0, day, &date)?;
+        }
+
+        sheet.write_string(row, 1, person(entry))?;
+        sheet.write_string(row, 2, text(&entry.project_name))?;
+        sheet.write_string(row, 3, text(&entry.todo_list_name))?;
+        sheet.write_string(row, 4, text(&entry.todo_item_name))?;
+        sheet.write_string(row, 5, text(&entry.description))?;
+        sheet.write_boolean(row, 6, flag(&entry.isbillable))?;
+        sheet.write_boolean(row, 7, flag(&entry.isbilled))?;
+        sheet.write_string(row, 8, text(&entry.invoice_no))?;
+
+        let hours = minutes(entry) / 60.0;
+        total += hours;
+        sheet.write_number_with_format(row, HOURS_COLUMN, hours, &decimal)?;
+    }
+
+    let totals = entries.len() as u32 + 1;
+    let formula = if entries.is_empty() {
+        Formula::new("=0")
+    } else {
+        Formula::new(format!("=SUM(J2:J{})", totals))
+    };
+
+    sheet.write_string_with_format(totals, 0, "Total", &bold)?;
+    sheet.write_formula_with_format(
+        totals,
+        HOURS_COLUMN,
+        formula.set_result(format!("{}", total)),
+        &bold.set_num_format("0.00"),
+    )?;
+
+    Ok(())
+}
+
+/// The entries of each sheet, by the name of its project or person.
+fn groups(entries: &[TimeEntry], sheets: Sheets) -> BTreeMap<String, Vec<&TimeEntry>> {
+    let mut groups: BTreeMap<String, Vec<&TimeEntry>> = BTreeMap::new();
+
+    for entry in entries {
+        groups.entry(sheets.name(entry)).or_default().push(entry);
+    }
+
+    groups
+}
+
+/// Builds a workbook of the time entries with a sheet per group, ordered by
+/// name.
+pub fn time_entries(entries: &[TimeEntry], sheets: Sheets) -> Result<Vec<u8>, XlsxError> {
+    let groups = groups(entries, sheets);
+
+    let mut workbook = Workbook::new();
+    let mut used = vec![];
+
+    if groups.is_empty() {
+        write_sheet(workbook.add_worksheet().set_name("Time entries")?, &[])?;
+    }
+
+    for (name, entries) in &groups {
+        let sheet = workbook
+            .add_worksheet()
+            .set_name(sheet_name(name, &mut used))?;
+        write_sheet(sheet, entries)?;
+    }
+
+    workbook.save_to_buffer()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn entry(project: &str, first_name: &str, hours: &str) -> TimeEntry {
+        serde_json::from_value(json!({
+            "project-name": project,
+            "person-first-name": first_name,
+            "person-last-name": "Bracken",
+            "date": "2024-03-01T23:30:00Z",
+            "dateUserPerspective": "2024-03-02T00:30:00Z",
+            "hours": hours,
+            "minutes": "30",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn groups_entries_by_project_or_person() {
+        let entries = [
+            entry("Website", "Holly", "1"),
+            entry("App", "Kyle", "2"),
+            entry("Website", "Kyle", "3"),
+        ];
+
+        let by_project = groups(&entries, Sheets::Project);
+        assert_eq!(by_project.keys().collect::<Vec<_>>(), ["App", "Website"]);
+        assert_eq!(by_project["Website"].len(), 2);
+
+        let by_person = groups(&entries, Sheets::Person);
+        assert_eq!(
+            by_person.keys().collect::<Vec<_>>(),
+            ["Holly Bracken", "Kyle Bracken"]
+        );
+        assert_eq!(by_person["Kyle Bracken"].len(), 2);
+    }
+
+    #[test]
+    fn sheet_names_are_valid_and_unique() {
+        let mut used = vec![];
+
+        assert_eq!(
+            sheet_name("Q1: Billing/Review", &mut used),
+            "Q1  Billing Review"
+        );
+        assert_eq!(sheet_name("", &mut used), "(none)");
+        assert_eq!(
+            sheet_name("An unusually long project name here", &mut used),
+            "An unusually long project name "
+        );
+        assert_eq!(
+            sheet_name("An unusually long project name there", &mut used),
+            "An unusually long project n (2)"
+        );
+        assert_eq!(sheet_name("(NONE)", &mut used), "(NONE) (2)");
+    }
+
+    /// The XML of a part of the workbook.
+    fn part(workbook: &[u8], name: &str) -> String {
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(workbook)).unwrap();
+        let mut xml = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name(name).unwrap(), &mut xml).unwrap();
+        xml
+    }
+
+    /// The contents of the elements with the tag in the XML.
+    fn elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+        let (open, close) = (format!("<{}", tag), format!("</{}>", tag));
+
+        xml.match_indices(&open)
+            .filter_map(|(i, _)| {
+                let start = i + xml[i..].find('>')? + 1;
+                let end = start + xml[start..].find(&close)?;
+                Some(&xml[start..end])
+            })
+            .collect()
+    }
+
+    fn sheet_names(workbook: &[u8]) -> Vec<String> {
+        let xml = part(workbook, "xl/workbook.xml");
+
+        xml.match_indices("<sheet name=\"")
+            .map(|(i, m)| {
+                let name = &xml[i + m.len()..];
+                name[..name.find('"').unwrap()].to_string()
+            })
+            .collect()
+    }
+
+    /// The value of a cell of the nth sheet, with shared strings resolved and
+    /// the cached result of formulas prefixed by the formula.
+    fn cell(workbook: &[u8], sheet: usize, reference: &str) -> Option<String> {
+        let xml = part(workbook, &format!("xl/worksheets/sheet{}.xml", sheet));
+        let start = xml.find(&format!("<c r=\"{}\"", reference))?;
+        let cell = &xml[start..start + xml[start..].find("</c>")?];
+        let value = elements(cell, "v").first()?.to_string();
+
+        if cell.contains(" t=\"s\"") {
+            let strings = part(workbook, "xl/sharedStrings.xml");
+            let index: usize = value.parse().unwrap();
+            return Some(elements(&strings, "t")[index].to_string());
+        }
+
+        match elements(cell, "f").first() {
+            Some(formula) => Some(format!("={} {}", formula, value)),
+            None => Some(value),
+        }
+    }
+
+    #[test]
+    fn builds_a_workbook() {
+        let entries = [
+            entry("Website", "Holly", "1"),
+            entry("App", "Kyle", "2"),
+            entry("Website", "Kyle", "3"),
+        ];
+        let workbook = time_entries(&entries, Sheets::Project).unwrap();
+
+        assert_eq!(sheet_names(&workbook), ["App", "Website"]);
+
+        let website = |reference| cell(&workbook, 2, reference);
+        assert_eq!(website("A1").as_deref(), Some("Date"));
+        assert_eq!(website("J1").as_deref(), Some("Hours"));
+
+        // the day the entry was logged for, 2024-03-02, as an Excel serial
+        // date rather than the UTC date
+        assert_eq!(website("A2").as_deref(), Some("45353"));
+        assert_eq!(website("B2").as_deref(), Some("Holly Bracken"));
+        assert_eq!(website("C2").as_deref(), Some("Website"));
+        assert_eq!(website("J2").as_deref(), Some("1.5"));
+        assert_eq!(website("B3").as_deref(), Some("Kyle Bracken"));
+        assert_eq!(website("J3").as_deref(), Some("3.5"));
+
+        assert_eq!(website("A4").as_deref(), Some("Total"));
+        assert_eq!(website("J4").as_deref(), Some("=SUM(J2:J3) 5"));
+        assert_eq!(website("A5"), None);
+
+        let empty = time_entries(&[], Sheets::Person).unwrap();
+        assert_eq!(sheet_names(&empty), ["Time entries"]);
+        assert_eq!(cell(&empty, 1, "A2").as_deref(), Some("Total"));
+        assert_eq!(cell(&empty, 1, "J2").as_deref(), Some("=0 0"));
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+
+/// The type inferred from one or more JSON samples.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Inferred {
+    /// Only `null` (or nothing at all) has been seen, so the type is unknown.
+    Null,
+    Bool,
+    Integer,
+    Float,
+    String,
+    /// The value has been seen as both a string and a number.
+    StringOrNumber,
+    Object(BTreeMap<String, Inferred>),
+    /// An array of the inferred element type, `Inferred::Null` when every
+    /// sample was empty.
+    Array(Box<Inferred>),
+}
+
+impl Inferred {
+    /// Infers the type of a single sample value, `path` describing where the
+    /// value is when the elements of an array are irreconcilable.
+    pub(crate) fn from_value(value: &serde_json::Value, path: &str) -> Result<Self, String> {
+        Ok(match value {
+            serde_json::Value::Null => Inferred::Null,
+            serde_json::Value::Bool(_) => Inferred::Bool,
+            serde_json::Value::Number(n) if n.is_f64() => Inferred::Float,
+            serde_json::Value::Number(_) => Inferred::Integer,
+            serde_json::Value::String(_) => Inferred::String,
+            serde_json::Value::Object(obj) => Inferred::from_object(obj, path)?,
+            serde_json::Value::Array(arr) => {
+                let path = format!("{}[]", path);
+                let inner = arr.iter().try_fold(Inferred::Null, |acc, item| {
+                    acc.unify(&Inferred::from_value(item, &path)?, &path)
+                })?;
+
+                Inferred::Array(Box::new(inner))
+            }
+        })
+    }
+
+    pub(crate) fn from_object(
+        obj: &serde_json::Map<String, serde_json::Value>,
+        path: &str,
+    ) -> Result<Self, String> {
+        Ok(Inferred::Object(
+            obj.iter()
+                .map(|(key, value)| {
+                    Ok((
+                        key.clone(),
+                        Inferred::from_value(value, &join_path(path, key))?,
+                    ))
+                })
+                .collect::<Result<_, String>>()?,
+        ))
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Inferred::Null => "null",
+            Inferred::Bool => "bool",
+            Inferred::Integer => "integer",
+            Inferred::Float => "float",
+            Inferred::String => "string",
+            Inferred::StringOrNumber => "string or number",
+            Inferred::Object(_) => "object",
+            Inferred::Array(_) => "array",
+        }
+    }
+
+    /// Unifies two inferred types into one capable of representing both. The
+    /// `path` is used to describe where the conflict occurred when the types
+    /// are irreconcilable.
+    pub(crate) fn unify(&self, other: &Inferred, path: &str) -> Result<Inferred, String> {
+        use Inferred::*;
+
+        match (self, other) {
+            (Null, other) | (other, Null) => Ok(other.clone()),
+            (Integer, Float) | (Float, Integer) => Ok(Float),
+            (String, Integer)
+            | (Integer, String)
+            | (String, Float)
+            | (Float, String)
+            | (StringOrNumber, String)
+            | (StringOrNumber, Integer)
+            | (StringOrNumber, Float)
+            | (String, StringOrNumber)
+            | (Integer, StringOrNumber)
+            | (Float, StringOrNumber) => Ok(StringOrNumber),
+            (Object(a), Object(b)) => {
+                let mut fields = a.clone();
+
+                for (key, ty) in b {
+                    let unified = match fields.get(key) {
+                        Some(existing) => existing.unify(ty, &join_path(path, key))?,
+                        None => ty.clone(),
+                    };
+                    fields.insert(key.clone(), unified);
+                }
+
+                Ok(Object(fields))
+            }
+            (Array(a), Array(b)) => Ok(Array(Box::new(a.unify(b, &format!("{}[]", path))?))),
+            (a, b) if a == b => Ok(a.clone()),
+            (a, b) => Err(format!(
+                "irreconcilable types for field `{}`: {} in previous samples, {} in this sample",
+                path,
+                a.describe(),
+                b.describe()
+            )),
+        }
+    }
+}
+
+pub(crate) fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn unify_samples(samples: &[serde_json::Value]) -> Result<Inferred, String> {
+        samples.iter().try_fold(Inferred::Null, |acc, sample| {
+            acc.unify(&Inferred::from_value(sample, "Sample")?, "Sample")
+        })
+    }
+
+    #[test]
+    fn unifies_numbers_and_strings() {
+        let unified = unify_samples(&[json!({"a": 1, "b": 1}), json!({"a": 1.5, "b": "1"})]);
+
+        let mut fields = BTreeMap::new();
+        fields.insert("a".to_string(), Inferred::Float);
+        fields.insert("b".to_string(), Inferred::StringOrNumber);
+
+        assert_eq!(unified, Ok(Inferred::Object(fields)));
+    }
+
+    #[test]
+    fn unifies_empty_arrays_with_object_arrays() {
+        let unified = unify_samples(&[json!({"tags": []}), json!({"tags": [{"id": 1}]})]);
+
+        let mut tag = BTreeMap::new();
+        tag.insert("id".to_string(), Inferred::Integer);
+
+        let mut fields = BTreeMap::new();
+        fields.insert(
+            "tags".to_string(),
+            Inferred::Array(Box::new(Inferred::Object(tag))),
+        );
+
+        assert_eq!(unified, Ok(Inferred::Object(fields)));
+    }
+
+    #[test]
+    fn reports_conflicting_types() {
+        let unified = unify_samples(&[json!({"a": {"b": true}}), json!({"a": {"b": {}}})]);
+
+        assert_eq!(
+            unified,
+            Err(
+                "irreconcilable types for field `Sample.a.b`: bool in previous samples, object in \
+                 this sample"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn reports_conflicting_array_elements() {
+        let unified = unify_samples(&[json!({"a": [true, 1, "x"]})]);
+
+        assert_eq!(
+            unified,
+            Err(
+                "irreconcilable types for field `Sample.a[]`: bool in previous samples, integer \
+                 in this sample"
+                    .to_string()
+            )
+        );
+
+        // numbers and strings still unify within an array
+        let unified = unify_samples(&[json!({"a": [1, "x"]})]);
+        let mut fields = BTreeMap::new();
+        fields.insert(
+            "a".to_string(),
+            Inferred::Array(Box::new(Inferred::StringOrNumber)),
+        );
+        assert_eq!(unified, Ok(Inferred::Object(fields)));
+    }
+}
@@ -1,103 +1,306 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use inflector::Inflector;
 use proc_macro::TokenStream;
 use proc_macro2::Span;
-use quote::quote;
+use quote::{format_ident, quote};
 use syn::{
     parse::{Parse, ParseStream},
-    parse_macro_input, token, Ident, LitStr, Result, Token, Type,
+    parse_macro_input, token, Ident, LitStr, NestedMeta, Result, Token, Type,
 };
 
-lazy_static::lazy_static! {
-    static ref RENAMED: HashMap<&'static str, &'static str> = {
-        let mut m = HashMap::new();
-        m.insert(
-        "created_on", "created_at");
-        m.insert(
-        "last_changed_on", "updated_at");
-        m
-    };
+use crate::{
+    infer::Inferred,
+    overrides::{OverrideKind, Overrides},
+};
+
+mod infer;
+mod overrides;
+
+/// Creates the identifier for a field, falling back to a raw identifier when
+/// the name is a reserved keyword, e.g. `type`.
+fn field_ident(name: &str) -> Ident {
+    syn::parse_str::<Ident>(name)
+        .or_else(|_| syn::parse_str::<Ident>(&format!("r#{}", name)))
+        .unwrap_or_else(|_| Ident::new(name, Span::call_site()))
+}
+
+/// The JSON type of a generated field, used to describe the field in the
+/// generated JSON Schema and metadata.
+#[derive(Debug, Clone)]
+enum FieldType {
+    String,
+    StringOrNumber,
+    Integer,
+    Float,
+    Bool,
+    Object(Ident),
+    Array(Box<FieldType>),
+    Any,
+}
+
+impl FieldType {
+    /// Expands into an expression that evaluates to the field's JSON Schema. All
+    /// of the generated fields are wrapped in an `Option`, so `null` is always
+    /// permitted.
+    fn json_schema(&self) -> proc_macro2::TokenStream {
+        match self {
+            FieldType::String | FieldType::StringOrNumber => {
+                quote! { serde_json::json!({ "type": ["string", "null"] }) }
+            }
+            FieldType::Integer => quote! { serde_json::json!({ "type": ["integer", "null"] }) },
+            FieldType::Float => quote! { serde_json::json!({ "type": ["number", "null"] }) },
+            FieldType::Bool => quote! { serde_json::json!({ "type": ["boolean", "null"] }) },
+            FieldType::Object(ident) => quote! {
+                serde_json::json!({ "anyOf": [#ident::json_schema(), { "type": "null" }] })
+            },
+            FieldType::Array(inner) => {
+                let items = inner.item_schema();
+                quote! {
+                    serde_json::json!({
+                        "type": ["array", "null"],
+                        "items": #items
+                    })
+                }
+            }
+            FieldType::Any => quote! { serde_json::json!({}) },
+        }
+    }
+
+    /// Expands into the schema of an array element, which unlike fields are
+    /// never `null`.
+    fn item_schema(&self) -> proc_macro2::TokenStream {
+        match self {
+            FieldType::String | FieldType::StringOrNumber => {
+                quote! { serde_json::json!({ "type": "string" }) }
+            }
+            FieldType::Integer => quote! { serde_json::json!({ "type": "integer" }) },
+            FieldType::Float => quote! { serde_json::json!({ "type": "number" }) },
+            FieldType::Bool => quote! { serde_json::json!({ "type": "boolean" }) },
+            FieldType::Object(ident) => quote! { #ident::json_schema() },
+            FieldType::Array(inner) => {
+                let items = inner.item_schema();
+                quote! { serde_json::json!({ "type": "array", "items": #items }) }
+            }
+            FieldType::Any => quote! { serde_json::json!({}) },
+        }
+    }
 
+    /// Expands into the `crate::meta::FieldKind` describing the type.
+    fn meta(&self) -> proc_macro2::TokenStream {
+        match self {
+            FieldType::String => quote! { crate::meta::FieldKind::String },
+            FieldType::StringOrNumber => quote! { crate::meta::FieldKind::StringOrNumber },
+            FieldType::Integer => quote! { crate::meta::FieldKind::Integer },
+            FieldType::Float => quote! { crate::meta::FieldKind::Float },
+            FieldType::Bool => quote! { crate::meta::FieldKind::Bool },
+            FieldType::Object(ident) => {
+                let name = ident.to_string();
+                quote! { crate::meta::FieldKind::Object(#name) }
+            }
+            FieldType::Array(inner) => {
+                let inner = inner.meta();
+                quote! { crate::meta::FieldKind::Array(&#inner) }
+            }
+            FieldType::Any => quote! { crate::meta::FieldKind::Any },
+        }
+    }
 }
 
 #[derive(Debug, Default)]
 struct Builder {
-    structs: HashMap<String, Object>,
+    structs: BTreeMap<String, Object>,
 }
 
 impl Builder {
-    fn create_object_from_map(
+    /// Returns the identifier of the struct generated for `obj_name`, creating
+    /// it from the inferred fields when it doesn't exist yet.
+    fn object_ident(&mut self, obj_name: &str, fields: &BTreeMap<String, Inferred>) -> Ident {
+        if !self.structs.contains_key(obj_name) {
+            self.create_object(obj_name, fields, &Overrides::default());
+        }
+
+        self.structs.get(obj_name).unwrap().name_ident.clone()
+    }
+
+    /// Maps an inferred array element type to the element's Rust type, `None`
+    /// when the element type is unknown.
+    fn item_type(
+        &mut self,
+        old_name: &str,
+        inferred: &Inferred,
+    ) -> Option<(proc_macro2::TokenStream, FieldType)> {
+        match inferred {
+            Inferred::Bool => Some((quote! { bool }, FieldType::Bool)),
+            Inferred::Integer => Some((quote! { i64 }, FieldType::Integer)),
+            Inferred::Float => Some((quote! { f64 }, FieldType::Float)),
+            Inferred::String => Some((quote! { String }, FieldType::String)),
+            Inferred::Object(fields) => {
+                let obj_ident = self.object_ident(&old_name.to_singular().to_pascal_case(), fields);
+                Some((quote! { #obj_ident }, FieldType::Object(obj_ident)))
+            }
+            Inferred::Array(inner) => self.item_type(old_name, inner).map(|(ty, field_type)| {
+                (quote! { Vec<#ty> }, FieldType::Array(Box::new(field_type)))
+            }),
+            Inferred::Null | Inferred::StringOrNumber => None,
+        }
+    }
+
+    fn create_object(
         &mut self,
         name: &str,
-        input_fields: &serde_json::Map<String, serde_json::Value>,
+        input_fields: &BTreeMap<String, Inferred>,
+        overrides: &Overrides,
     ) {
+        let name = name.to_pascal_case();
+
+        // the deserializers used by lenient structs are generated into a hidden
+        // module alongside the struct, since `deserialize_with` only accepts a
+        // path
+        let support_module = format_ident!("__{}_support", name.to_snake_case());
+        let mut support_fns: Vec<proc_macro2::TokenStream> = vec![];
+        let mut skipped: Vec<&String> = vec![];
+
         let fields: Vec<Field> = input_fields
             .iter()
-            .map(|(old_name, value)| {
-                let mut new_name = old_name.to_snake_case();
+            .filter(|(old_name, _)| {
+                let skip = overrides
+                    .for_field(old_name)
+                    .any(|o| matches!(o, OverrideKind::Skip));
 
-                if let Some(target_name) = RENAMED.get(new_name.as_str()) {
-                    new_name = target_name.to_string();
+                if skip {
+                    skipped.push(old_name);
                 }
-                let new_name_ident = Ident::new(&new_name, Span::call_site());
 
-                let mut attributes: Vec<proc_macro2::TokenStream> =
-                    vec![quote! { rename(deserialize = #old_name) }];
+                !skip
+            })
+            .map(|(old_name, inferred)| {
+                let mut new_name = old_name.to_snake_case();
 
-                let ty = match value {
-                    serde_json::Value::String(_) => {
-                        attributes.push(quote!(default));
-                        attributes.push(quote! { deserialize_with =
-                        "serde_with::rust::string_empty_as_none::deserialize" });
-                        quote! {Option<String>}
-                    }
-                    serde_json::Value::Number(_) => {
-                        if value.is_f64() {
-                            quote! { Option<f64> }
-                        } else {
-                            quote! { Option<i64> }
-                        }
+                let mut required = false;
+                let mut type_override = None;
+                let mut serde_overrides = vec![];
+
+                for o in overrides.for_field(old_name) {
+                    match o {
+                        OverrideKind::Rename(target_name) => new_name = target_name.value(),
+                        OverrideKind::Required => required = true,
+                        OverrideKind::Type(ty) => type_override = Some(ty.clone()),
+                        OverrideKind::Serde(attrs) => serde_overrides.extend(attrs.iter()),
+                        OverrideKind::Skip => {}
                     }
-                    serde_json::Value::Object(inner_obj) => {
-                        let obj_name = old_name.to_pascal_case();
+                }
 
-                        if !self.structs.contains_key(&obj_name) {
-                            self.create_object_from_map(&obj_name, inner_obj);
-                        }
+                let new_name_ident = field_ident(&new_name);
 
-                        let obj = self.structs.get(&obj_name).unwrap();
+                let mut attributes: Vec<proc_macro2::TokenStream> =
+                    vec![quote! { rename(deserialize = #old_name) }];
 
-                        let obj_ident = &obj.name_ident;
-                        quote! { Option<#obj_ident> }
+                // the deserializers used for the field when it's optional and
+                // when it's required respectively
+                let (ty, field_type, deserialize_with) = match inferred {
+                    Inferred::String => (
+                        quote! { String },
+                        FieldType::String,
+                        (
+                            Some("serde_with::rust::string_empty_as_none::deserialize"),
+                            None,
+                        ),
+                    ),
+                    Inferred::StringOrNumber => (
+                        quote! { String },
+                        FieldType::StringOrNumber,
+                        (
+                            Some("crate::de::string_or_number"),
+                            Some("crate::de::required_string_or_number"),
+                        ),
+                    ),
+                    Inferred::Float => (quote! { f64 }, FieldType::Float, (None, None)),
+                    Inferred::Integer => (quote! { i64 }, FieldType::Integer, (None, None)),
+                    Inferred::Object(inner_fields) => {
+                        let obj_ident = self.object_ident(&old_name.to_pascal_case(), inner_fields);
+
+                        (
+                            quote! { #obj_ident },
+                            FieldType::Object(obj_ident),
+                            (None, None),
+                        )
                     }
-                    serde_json::Value::Bool(_) => {
-                        quote! { Option<bool> }
-                    }
-                    serde_json::Value::Array(arr) => {
-                        if arr.len() > 0 && arr[0].is_object() {
-                            let inner_obj = &arr[0]
-                                .as_object()
-                                .expect("is_object returned true, should unwrap to object");
-                            let obj_name = old_name.to_singular().to_pascal_case();
-
-                            if !self.structs.contains_key(&obj_name) {
-                                self.create_object_from_map(&obj_name, inner_obj);
-                            }
-
-                            let obj = self.structs.get(&obj_name).unwrap();
+                    Inferred::Bool => (quote! { bool }, FieldType::Bool, (None, None)),
+                    Inferred::Array(inner) => match self.item_type(old_name, inner) {
+                        Some((item_ty, item_field_type)) => (
+                            quote! { Vec<#item_ty> },
+                            FieldType::Array(Box::new(item_field_type)),
+                            (None, None),
+                        ),
+                        None => (quote! { serde_json::Value }, FieldType::Any, (None, None)),
+                    },
+                    Inferred::Null => (quote! { serde_json::Value }, FieldType::Any, (None, None)),
+                };
 
-                            let obj_ident = &obj.name_ident;
+                let (ty, field_type, deserialize_with) = match type_override {
+                    Some(ty) => (quote! { #ty }, FieldType::Any, None),
+                    None if required => (ty, field_type, deserialize_with.1),
+                    None => (quote! { Option<#ty> }, field_type, deserialize_with.0),
+                };
 
-                            quote! { Option<Vec<#obj_ident>> }
-                        } else {
-                            quote! { Option<serde_json::Value> }
-                        }
+                let custom_deserializer = serde_overrides.iter().any(|meta| match meta {
+                    NestedMeta::Meta(meta) => {
+                        meta.path().is_ident("with") || meta.path().is_ident("deserialize_with")
                     }
-                    _ => {
-                        quote! { Option<serde_json::Value> }
+                    _ => false,
+                });
+
+                let deserialize_with = deserialize_with.filter(|_| !custom_deserializer);
+
+                // fields serde can leave out of the output can't be required
+                // by the JSON Schema
+                let skip_serializing = serde_overrides.iter().any(|meta| match meta {
+                    NestedMeta::Meta(meta) => ["skip", "skip_serializing", "skip_serializing_if"]
+                        .iter()
+                        .any(|ident| meta.path().is_ident(ident)),
+                    _ => false,
+                });
+
+                let lenient = overrides.lenient
+                    && !required
+                    && !custom_deserializer
+                    && !matches!(field_type, FieldType::Any);
+
+                if lenient {
+                    let fn_ident = format_ident!("field_{}", new_name);
+
+                    let inner = match deserialize_with {
+                        Some(path) => {
+                            let path: syn::Path =
+                                syn::parse_str(path).expect("deserialize_with paths are valid");
+                            quote! { #path(value) }
+                        }
+                        None => quote! { <#ty as serde::Deserialize>::deserialize(value) },
+                    };
+
+                    support_fns.push(quote! {
+                        pub(super) fn #fn_ident<'de, D>(
+                            deserializer: D,
+                        ) -> std::result::Result<#ty, D::Error>
+                        where
+                            D: serde::Deserializer<'de>,
+                        {
+                            crate::de::lenient(deserializer, #name, #new_name, |value| #inner)
+                        }
+                    });
+
+                    let path = format!("{}::{}", support_module, fn_ident);
+                    attributes.push(quote!(default));
+                    attributes.push(quote! { deserialize_with = #path });
+                } else if let Some(deserialize_with) = deserialize_with {
+                    if !required {
+                        attributes.push(quote!(default));
                     }
-                };
+                    attributes.push(quote! { deserialize_with = #deserialize_with });
+                }
+
+                attributes.extend(serde_overrides.iter().map(|meta| quote! { #meta }));
 
                 let attributes = quote! { #[serde(#(#attributes ,)*)] };
 
@@ -111,20 +314,52 @@ impl Builder {
                 Field {
                     old_name: old_name.clone(),
                     new_name,
-                    new_name_ident,
-                    ty,
+                    field_type,
+                    required,
+                    skip_serializing,
                     field,
                 }
             })
             .collect();
 
-        let name = name.to_pascal_case();
         let name_ident = syn::Ident::new(&name, Span::call_site());
 
+        if overrides.extra {
+            support_fns.push(quote! {
+                pub(super) fn extra<'de, D>(
+                    deserializer: D,
+                ) -> std::result::Result<
+                    std::collections::BTreeMap<String, serde_json::Value>,
+                    D::Error,
+                >
+                where
+                    D: serde::Deserializer<'de>,
+                {
+                    crate::de::extra(deserializer, #name, &[#(#skipped),*])
+                }
+            });
+        }
+
+        let support = if support_fns.is_empty() {
+            None
+        } else {
+            Some(quote! {
+                #[doc(hidden)]
+                mod #support_module {
+                    use super::*;
+
+                    #(#support_fns)*
+                }
+            })
+        };
+
         let obj = Object {
             name,
             name_ident,
             fields,
+            skipped: skipped.into_iter().cloned().collect(),
+            extra: overrides.extra.then_some(support_module),
+            support,
         };
 
         self.structs.insert(obj.name.clone(), obj);
@@ -138,18 +373,49 @@ impl Builder {
                 let name = &s.name_ident;
                 let fields: Vec<&proc_macro2::TokenStream> =
                     s.fields.iter().map(|f| f.expand()).collect();
+                let json_schema = s.expand_json_schema();
+                let meta = s.expand_meta();
+                let support = &s.support;
+
+                let extra = s.extra.as_ref().map(|support_module| {
+                    let deserialize_with = format!("{}::extra", support_module);
+
+                    quote! {
+                        /// Fields returned by Teamwork that aren't part of the schema.
+                        #[serde(
+                            flatten,
+                            deserialize_with = #deserialize_with,
+                            serialize_with = "crate::de::serialize_extra",
+                            skip_serializing_if = "std::collections::BTreeMap::is_empty"
+                        )]
+                        pub extra: std::collections::BTreeMap<String, serde_json::Value>,
+                    }
+                });
 
                 quote! {
                     #[derive(Debug, Serialize, Deserialize)]
                     pub struct #name {
                         #(#fields)*
+                        #extra
                     }
+
+                    #json_schema
+
+                    #meta
+
+                    #support
                 }
             })
             .collect();
 
+        let names = self.structs.values().map(|s| &s.name_ident);
+
         quote! {
             #(#expanded)*
+
+            /// Metadata of every struct generated from the samples.
+            pub const SCHEMAS: &[crate::meta::SchemaMeta] =
+                &[#(<#names as crate::meta::Schema>::META),*];
         }
     }
 }
@@ -158,8 +424,12 @@ impl Builder {
 struct Field {
     old_name: String,
     new_name: String,
-    new_name_ident: Ident,
-    ty: Type,
+    field_type: FieldType,
+    /// Whether the field is required, in which case it isn't wrapped in an
+    /// `Option`.
+    required: bool,
+    /// Whether serde may leave the field out of the serialized output.
+    skip_serializing: bool,
     field: proc_macro2::TokenStream,
 }
 
@@ -174,72 +444,293 @@ struct Object {
     name: String,
     name_ident: Ident,
     fields: Vec<Field>,
+    /// The fields in the samples that were deliberately omitted.
+    skipped: Vec<String>,
+    /// The support module containing the deserializer of the `extra` field,
+    /// when unknown fields are retained.
+    extra: Option<Ident>,
+    /// Items generated alongside the struct, e.g. lenient deserializers.
+    support: Option<proc_macro2::TokenStream>,
+}
+
+impl Object {
+    /// Expands into the implementation of `crate::meta::Schema`, describing the
+    /// struct's fields and where they came from.
+    fn expand_meta(&self) -> proc_macro2::TokenStream {
+        let name = &self.name_ident;
+        let title = &self.name;
+        let skipped = &self.skipped;
+        let extra = self.extra.is_some();
+
+        let fields = self.fields.iter().map(|f| {
+            let field_name = &f.new_name;
+            let source = &f.old_name;
+            let kind = f.field_type.meta();
+            let required = f.required;
+
+            quote! {
+                crate::meta::FieldMeta {
+                    name: #field_name,
+                    source: #source,
+                    kind: #kind,
+                    required: #required,
+                }
+            }
+        });
+
+        quote! {
+            impl crate::meta::Schema for #name {
+                const META: crate::meta::SchemaMeta = crate::meta::SchemaMeta {
+                    name: #title,
+                    fields: &[#(#fields),*],
+                    skipped: &[#(#skipped),*],
+                    extra: #extra,
+                };
+            }
+        }
+    }
+
+    /// Expands into a `json_schema` associated function describing the
+    /// serialized form of the struct, allowing consumers of the proxy to
+    /// validate payloads and detect drift.
+    fn expand_json_schema(&self) -> proc_macro2::TokenStream {
+        let name = &self.name_ident;
+        let title = &self.name;
+        let doc = format!(
+            " Returns the JSON Schema (draft 7) describing the serialized form of `{}`.",
+            title
+        );
+
+        let properties: Vec<proc_macro2::TokenStream> = self
+            .fields
+            .iter()
+            .map(|f| {
+                let key = &f.new_name;
+                let schema = if f.required {
+                    f.field_type.item_schema()
+                } else {
+                    f.field_type.json_schema()
+                };
+                quote! { properties.insert(#key.to_string(), #schema); }
+            })
+            .collect();
+
+        let extra = self.extra.as_ref().map(|_| {
+            quote! {
+                properties.insert(
+                    "extra".to_string(),
+                    serde_json::json!({ "type": "object" }),
+                );
+            }
+        });
+
+        let required: Vec<&String> = self
+            .fields
+            .iter()
+            .filter(|f| !f.skip_serializing)
+            .map(|f| &f.new_name)
+            .collect();
+
+        quote! {
+            impl #name {
+                #[doc = #doc]
+                pub fn json_schema() -> serde_json::Value {
+                    let mut properties = serde_json::Map::new();
+                    #(#properties)*
+                    #extra
+
+                    serde_json::json!({
+                        "title": #title,
+                        "type": "object",
+                        "properties": properties,
+                        "required": [#(#required),*],
+                        "additionalProperties": false
+                    })
+                }
+            }
+        }
+    }
 }
 
+/// Generates structs from sample Teamwork responses.
+///
+/// Samples are either inline JSON, `(Task, r#"{ ... }"#)`, or paths to JSON
+/// files relative to the crate's manifest, `Task => ["schemas/task.json"]`.
+/// Each schema can be followed by a block of field overrides and flags:
+///
+/// ```ignore
+/// Task => "schemas/task.json" {
+///     lenient,
+///     extra,
+///     rename "content" => "title",
+///     required "id",
+///     skip "DLM",
+///     type "estimated-minutes" => Option<u32>,
+///     serde "tags" => (skip_serializing_if = "Option::is_none"),
+/// }
+/// ```
 #[proc_macro]
 pub fn generate_schema(input: TokenStream) -> TokenStream {
-    fn parse_litstr_to_json_object(
-        s: &LitStr,
-    ) -> Result<serde_json::Map<String, serde_json::Value>> {
-        let span = s.span();
-        let inner = s.value();
-
-        serde_json::from_str::<serde_json::Value>(&inner)
-            .map_err(|e| syn::Error::new(span, e.to_string()))
-            .and_then(|val| {
-                val.as_object()
-                    .ok_or_else(|| {
-                        syn::Error::new(span, "expected value to deserialize to json object")
-                    })
-                    .map(|v| v.clone())
-            })
+    fn parse_str_to_json_objects(
+        inner: &str,
+        span: Span,
+    ) -> Result<Vec<serde_json::Map<String, serde_json::Value>>> {
+        let val = serde_json::from_str::<serde_json::Value>(inner)
+            .map_err(|e| syn::Error::new(span, e.to_string()))?;
+
+        let expected_object =
+            || syn::Error::new(span, "expected value to deserialize to json object");
+
+        match val {
+            serde_json::Value::Object(obj) => Ok(vec![obj]),
+            serde_json::Value::Array(arr) if !arr.is_empty() => arr
+                .into_iter()
+                .map(|v| match v {
+                    serde_json::Value::Object(obj) => Ok(obj),
+                    _ => Err(expected_object()),
+                })
+                .collect(),
+            _ => Err(expected_object()),
+        }
+    }
+
+    /// Reads the sample file at `path`, resolved relative to the manifest
+    /// directory of the crate invoking the macro.
+    fn read_sample_file(
+        path: &LitStr,
+    ) -> Result<(String, Vec<serde_json::Map<String, serde_json::Value>>)> {
+        let span = path.span();
+        let manifest_dir = std::env::var("CARGO_MANIFEST_DIR")
+            .map_err(|_| syn::Error::new(span, "CARGO_MANIFEST_DIR is not set"))?;
+
+        let full_path = std::path::Path::new(&manifest_dir).join(path.value());
+
+        let inner = std::fs::read_to_string(&full_path).map_err(|e| {
+            syn::Error::new(
+                span,
+                format!("failed to read {}: {}", full_path.display(), e),
+            )
+        })?;
+
+        let samples = parse_str_to_json_objects(&inner, span)?;
+
+        Ok((full_path.to_string_lossy().into_owned(), samples))
     }
 
     #[derive(Debug)]
     struct Schema {
         name: Ident,
-        inner: LitStr,
-        json_obj: serde_json::Map<String, serde_json::Value>,
+        /// The fields unified across every sample of the schema.
+        fields: BTreeMap<String, Inferred>,
+        overrides: Overrides,
+        /// Sample files the schema was generated from, these are included in
+        /// the expanded output so that changing them triggers a rebuild.
+        files: Vec<String>,
     }
 
     impl Parse for Schema {
         fn parse(input: ParseStream) -> Result<Self> {
             let name = input.parse::<Ident>()?;
 
-            input.parse::<Token![,]>()?;
+            let lookahead = input.lookahead1();
+
+            let (samples, files) = if lookahead.peek(Token![,]) {
+                input.parse::<Token![,]>()?;
+
+                let inner = input.parse::<LitStr>()?;
+
+                let samples = parse_str_to_json_objects(&inner.value(), inner.span())?
+                    .into_iter()
+                    .map(|sample| (inner.span(), sample))
+                    .collect::<Vec<_>>();
+
+                (samples, vec![])
+            } else if lookahead.peek(Token![=>]) {
+                input.parse::<Token![=>]>()?;
+
+                let paths: Vec<LitStr> = if input.peek(token::Bracket) {
+                    let content;
+                    syn::bracketed!(content in input);
+                    content
+                        .parse_terminated::<LitStr, Token![,]>(|i| i.parse())?
+                        .into_iter()
+                        .collect()
+                } else {
+                    vec![input.parse::<LitStr>()?]
+                };
+
+                let mut samples = vec![];
+                let mut files = vec![];
+
+                for path in &paths {
+                    let (file, file_samples) = read_sample_file(path)?;
+                    files.push(file);
+                    samples.extend(file_samples.into_iter().map(|sample| (path.span(), sample)));
+                }
+
+                (samples, files)
+            } else {
+                return Err(lookahead.error());
+            };
+
+            if samples.is_empty() {
+                return Err(syn::Error::new(name.span(), "expected at least one sample"));
+            }
 
-            let inner = input.parse::<LitStr>()?;
+            // unify the samples one at a time so that a conflict can be
+            // reported against the sample that introduced it
+            let mut unified = Inferred::Null;
 
-            let json_obj = parse_litstr_to_json_object(&inner)?;
+            for (span, sample) in &samples {
+                let name = name.to_string();
+
+                unified = Inferred::from_object(sample, &name)
+                    .and_then(|sample| unified.unify(&sample, &name))
+                    .map_err(|e| syn::Error::new(*span, e))?;
+            }
+
+            let fields = match unified {
+                Inferred::Object(fields) => fields,
+                _ => unreachable!("samples are always parsed into json objects"),
+            };
+
+            let overrides = Overrides::parse_optional(input)?;
+            overrides.validate(&fields)?;
 
             Ok(Schema {
                 name,
-                inner,
-                json_obj,
+                fields,
+                overrides,
+                files,
             })
         }
     }
 
     #[derive(Debug)]
     struct Item {
-        paren_token: syn::token::Paren,
         schema: Schema,
     }
 
     impl Parse for Item {
         fn parse(input: ParseStream) -> Result<Self> {
-            let content;
+            if input.peek(token::Paren) {
+                let content;
+                syn::parenthesized!(content in input);
 
-            Ok(Item {
-                paren_token: syn::parenthesized!(content in input),
-                schema: content.parse::<Schema>()?,
-            })
+                Ok(Item {
+                    schema: content.parse::<Schema>()?,
+                })
+            } else {
+                Ok(Item {
+                    schema: input.parse::<Schema>()?,
+                })
+            }
         }
     }
 
     #[derive(Debug)]
     struct List {
-        bracket_token: token::Bracket,
+        _bracket_token: token::Bracket,
         items: syn::punctuated::Punctuated<Item, syn::Token![,]>,
     }
 
@@ -256,7 +747,7 @@ pub fn generate_schema(input: TokenStream) -> TokenStream {
             let content;
 
             Ok(List {
-                bracket_token: syn::bracketed!(content in input),
+                _bracket_token: syn::bracketed!(content in input),
                 items: content.parse_terminated(Item::parse)?,
             })
         }
@@ -292,9 +783,16 @@ pub fn generate_schema(input: TokenStream) -> TokenStream {
     schema_list
         .items
         .iter()
-        .for_each(|s| builder.create_object_from_map(&s.name.to_string(), &s.json_obj));
+        .for_each(|s| builder.create_object(&s.name.to_string(), &s.fields, &s.overrides));
 
-    TokenStream::from(builder.expand())
+    let files = schema_list.items.iter().flat_map(|s| s.files.iter());
+    let expanded = builder.expand();
+
+    TokenStream::from(quote! {
+        #(const _: &str = include_str!(#files);)*
+
+        #expanded
+    })
 }
 
 #[proc_macro]
@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+
+use inflector::Inflector;
+use syn::{
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
+    LitStr, NestedMeta, Result, Token, Type,
+};
+
+use crate::infer::Inferred;
+
+/// A customization of a single generated field, declared alongside the schema
+/// e.g. `Task => "schemas/task.json" { rename "content" => "title" }`.
+#[derive(Debug)]
+pub(crate) struct Override {
+    /// The field being customized, either its name in the sample JSON or the
+    /// snake cased field name.
+    pub(crate) key: LitStr,
+    pub(crate) kind: OverrideKind,
+}
+
+#[derive(Debug)]
+pub(crate) enum OverrideKind {
+    /// `rename "content" => "title"`
+    Rename(LitStr),
+    /// `required "id"`, the field is no longer wrapped in an `Option`.
+    Required,
+    /// `skip "DLM"`, the field is omitted from the struct.
+    Skip,
+    /// `type "id" => u64`, replaces the inferred type.
+    Type(Box<Type>),
+    /// `serde "id" => (default, skip_serializing_if = "Option::is_none")`,
+    /// additional attributes added to the field's `#[serde(...)]`.
+    Serde(Vec<NestedMeta>),
+}
+
+impl Override {
+    pub(crate) fn matches(&self, old_name: &str) -> bool {
+        let key = self.key.value();
+        key == old_name || key == old_name.to_snake_case()
+    }
+}
+
+impl Parse for Override {
+    fn parse(input: ParseStream) -> Result<Self> {
+        if input.peek(Token![type]) {
+            input.parse::<Token![type]>()?;
+            let key = input.parse::<LitStr>()?;
+            input.parse::<Token![=>]>()?;
+
+            return Ok(Override {
+                key,
+                kind: OverrideKind::Type(Box::new(input.parse()?)),
+            });
+        }
+
+        let directive = input.parse::<syn::Ident>()?;
+        let key = input.parse::<LitStr>()?;
+
+        let kind = match directive.to_string().as_str() {
+            "rename" => {
+                input.parse::<Token![=>]>()?;
+                OverrideKind::Rename(input.parse()?)
+            }
+            "required" => OverrideKind::Required,
+            "skip" => OverrideKind::Skip,
+            "serde" => {
+                input.parse::<Token![=>]>()?;
+                let content;
+                syn::parenthesized!(content in input);
+                let attrs = content.parse_terminated::<NestedMeta, Token![,]>(NestedMeta::parse)?;
+                OverrideKind::Serde(attrs.into_iter().collect())
+            }
+            _ => {
+                return Err(syn::Error::new(
+                    directive.span(),
+                    "expected one of `rename`, `required`, `skip`, `type` or `serde`",
+                ))
+            }
+        };
+
+        Ok(Override { key, kind })
+    }
+}
+
+/// Either a field override or a flag that applies to the whole struct.
+enum Entry {
+    Flag(syn::Ident),
+    Field(Override),
+}
+
+impl Parse for Entry {
+    fn parse(input: ParseStream) -> Result<Self> {
+        if input.peek(syn::Ident) && !input.peek2(LitStr) {
+            Ok(Entry::Flag(input.parse()?))
+        } else {
+            Ok(Entry::Field(input.parse()?))
+        }
+    }
+}
+
+/// The overrides declared for a schema.
+#[derive(Debug, Default)]
+pub(crate) struct Overrides {
+    pub(crate) items: Vec<Override>,
+    /// `lenient`, type mismatches of optional fields are recorded as drift and
+    /// the field is deserialized as `None` rather than failing.
+    pub(crate) lenient: bool,
+    /// `extra`, fields missing from the samples are retained in an `extra`
+    /// map rather than being discarded.
+    pub(crate) extra: bool,
+}
+
+impl Overrides {
+    /// Parses the optional `{ ... }` block following a schema's samples.
+    pub(crate) fn parse_optional(input: ParseStream) -> Result<Self> {
+        if !input.peek(syn::token::Brace) {
+            return Ok(Overrides::default());
+        }
+
+        let content;
+        syn::braced!(content in input);
+
+        let entries: Punctuated<Entry, Token![,]> = content.parse_terminated(Entry::parse)?;
+
+        let mut overrides = Overrides::default();
+
+        for entry in entries {
+            match entry {
+                Entry::Field(item) => overrides.items.push(item),
+                Entry::Flag(flag) if flag == "lenient" => overrides.lenient = true,
+                Entry::Flag(flag) if flag == "extra" => overrides.extra = true,
+                Entry::Flag(flag) => {
+                    return Err(syn::Error::new(
+                        flag.span(),
+                        "expected one of `lenient` or `extra`",
+                    ))
+                }
+            }
+        }
+
+        Ok(overrides)
+    }
+
+    /// Ensures that every override refers to a field found in the samples, and
+    /// that the overrides of a field don't contradict each other.
+    pub(crate) fn validate(&self, fields: &BTreeMap<String, Inferred>) -> Result<()> {
+        for item in &self.items {
+            if !fields.keys().any(|old_name| item.matches(old_name)) {
+                return Err(syn::Error::new(
+                    item.key.span(),
+                    format!("no field named `{}` in the samples", item.key.value()),
+                ));
+            }
+        }
+
+        for old_name in fields.keys() {
+            let items: Vec<&Override> = self
+                .items
+                .iter()
+                .filter(|item| item.matches(old_name))
+                .collect();
+
+            for (i, item) in items.iter().enumerate() {
+                let conflict =
+                    items[..i]
+                        .iter()
+                        .find_map(|previous| match (&previous.kind, &item.kind) {
+                            (OverrideKind::Skip, _) | (_, OverrideKind::Skip) => {
+                                Some("a skipped field can't have other overrides")
+                            }
+                            (OverrideKind::Rename(_), OverrideKind::Rename(_)) => {
+                                Some("the field is already renamed")
+                            }
+                            (OverrideKind::Type(_), OverrideKind::Type(_)) => {
+                                Some("the field's type is already overridden")
+                            }
+                            (OverrideKind::Required, OverrideKind::Type(_))
+                            | (OverrideKind::Type(_), OverrideKind::Required) => Some(
+                                "a field with an overridden type can't be required, the type \
+                             decides whether it's optional",
+                            ),
+                            _ => None,
+                        });
+
+                if let Some(conflict) = conflict {
+                    return Err(syn::Error::new(
+                        item.key.span(),
+                        format!("conflicting overrides of `{}`: {}", old_name, conflict),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn for_field<'a>(
+        &'a self,
+        old_name: &'a str,
+    ) -> impl Iterator<Item = &'a OverrideKind> {
+        self.items
+            .iter()
+            .filter(move |item| item.matches(old_name))
+            .map(|item| &item.kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::parse::Parser;
+
+    use super::*;
+
+    fn validate(overrides: &str) -> Result<Overrides> {
+        let overrides = Overrides::parse_optional.parse_str(overrides)?;
+
+        let fields = ["id", "content", "DLM", "created-on"]
+            .iter()
+            .map(|name| (name.to_string(), Inferred::String))
+            .collect();
+
+        overrides.validate(&fields).map(|_| overrides)
+    }
+
+    #[test]
+    fn parses_overrides_and_flags() {
+        let overrides = validate(
+            r#"{
+                lenient,
+                rename "created_on" => "created_at",
+                required "id",
+                skip "DLM",
+                type "content" => Option<u32>,
+                serde "content" => (default),
+            }"#,
+        )
+        .unwrap();
+
+        assert!(overrides.lenient);
+        assert!(!overrides.extra);
+        assert_eq!(overrides.items.len(), 5);
+        assert!(matches!(
+            overrides.for_field("created-on").collect::<Vec<_>>()[..],
+            [OverrideKind::Rename(ref name)] if name.value() == "created_at"
+        ));
+        assert_eq!(overrides.for_field("content").count(), 2);
+    }
+
+    #[test]
+    fn rejects_unknown_fields_and_directives() {
+        let message = |overrides: &str| validate(overrides).unwrap_err().to_string();
+
+        assert_eq!(
+            message(r#"{ required "title" }"#),
+            "no field named `title` in the samples"
+        );
+        assert_eq!(
+            message(r#"{ strict }"#),
+            "expected one of `lenient` or `extra`"
+        );
+        assert!(message(r#"{ optional "id" }"#).starts_with("expected one of `rename`"));
+    }
+
+    #[test]
+    fn rejects_contradictory_overrides() {
+        let message = |overrides: &str| validate(overrides).unwrap_err().to_string();
+
+        assert_eq!(
+            message(r#"{ skip "DLM", required "DLM" }"#),
+            "conflicting overrides of `DLM`: a skipped field can't have other overrides"
+        );
+        assert_eq!(
+            message(r#"{ rename "created-on" => "a", rename "created_on" => "b" }"#),
+            "conflicting overrides of `created-on`: the field is already renamed"
+        );
+        assert_eq!(
+            message(r#"{ type "id" => u64, type "id" => u32 }"#),
+            "conflicting overrides of `id`: the field's type is already overridden"
+        );
+        assert!(message(r#"{ required "id", type "id" => u64 }"#)
+            .starts_with("conflicting overrides of `id`: a field with an overridden type"));
+
+        // overrides that complement each other are accepted
+        assert!(
+            validate(r#"{ rename "id" => "key", required "id", serde "id" => (default) }"#).is_ok()
+        );
+    }
+}
@@ -0,0 +1,101 @@
+//! Deserialization helpers referenced by the structs generated with
+//! `generate_schema!`.
+
+use std::collections::BTreeMap;
+
+use serde::{ser::SerializeMap, Deserialize, Deserializer, Serializer};
+
+use crate::drift;
+
+/// Deserializes a value that Teamwork sends as either a string or a number
+/// into an `Option<String>`. Empty strings are treated as `None`.
+pub fn string_or_number<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrNumber {
+        String(String),
+        Integer(i64),
+        Float(f64),
+    }
+
+    Ok(match Option::<StringOrNumber>::deserialize(deserializer)? {
+        Some(StringOrNumber::String(s)) if s.is_empty() => None,
+        Some(StringOrNumber::String(s)) => Some(s),
+        Some(StringOrNumber::Integer(n)) => Some(n.to_string()),
+        Some(StringOrNumber::Float(n)) => Some(n.to_string()),
+        None => None,
+    })
+}
+
+/// Deserializes a required value that Teamwork sends as either a string or a
+/// number into a `String`.
+pub fn required_string_or_number<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    string_or_number(deserializer)?
+        .ok_or_else(|| serde::de::Error::custom("expected a non-empty string or number"))
+}
+
+/// Deserializes a field of a lenient struct. When the value doesn't match the
+/// field's type the mismatch is recorded as drift and `None` is returned rather
+/// than failing to deserialize the entire struct.
+pub fn lenient<'de, D, T, F>(
+    deserializer: D,
+    schema: &'static str,
+    field: &'static str,
+    inner: F,
+) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    F: FnOnce(serde_json::Value) -> Result<Option<T>, serde_json::Error>,
+{
+    let value = serde_json::Value::deserialize(deserializer)?;
+
+    match inner(value.clone()) {
+        Ok(v) => Ok(v),
+        Err(e) => {
+            drift::record_mismatch(schema, field, &value, &e);
+            Ok(None)
+        }
+    }
+}
+
+/// Deserializes the fields that aren't part of a struct's schema, recording
+/// each of them as drift. Fields in `skipped` were deliberately omitted from
+/// the schema and are discarded.
+pub fn extra<'de, D>(
+    deserializer: D,
+    schema: &'static str,
+    skipped: &[&str],
+) -> Result<BTreeMap<String, serde_json::Value>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut fields = BTreeMap::<String, serde_json::Value>::deserialize(deserializer)?;
+
+    fields.retain(|field, _| !skipped.contains(&field.as_str()));
+
+    for (field, value) in &fields {
+        drift::record_unknown(schema, field, value);
+    }
+
+    Ok(fields)
+}
+
+/// Serializes the retained `extra` fields under the `extra` key rather than
+/// flattening them into the struct.
+pub fn serialize_extra<S>(
+    extra: &BTreeMap<String, serde_json::Value>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut map = serializer.serialize_map(Some(1))?;
+    map.serialize_entry("extra", extra)?;
+    map.end()
+}
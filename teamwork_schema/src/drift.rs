@@ -0,0 +1,136 @@
+//! Records schema drift observed while deserializing lenient structs, e.g. a
+//! field Teamwork changed from a number to a string.
+
+use std::{cell::RefCell, collections::BTreeMap, sync::Mutex};
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DriftKind {
+    /// The field's value didn't match the type in the schema.
+    TypeMismatch,
+    /// The field isn't part of the schema.
+    UnknownField,
+}
+
+/// A single occurrence of drift.
+#[derive(Debug, Clone, Serialize)]
+pub struct DriftEvent {
+    pub schema: &'static str,
+    pub field: String,
+    pub kind: DriftKind,
+    /// The JSON type of the value that was received.
+    pub found: &'static str,
+    pub message: Option<String>,
+}
+
+/// The drift observed for a field, either since the process started or while
+/// running `collect`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DriftSummary {
+    pub schema: &'static str,
+    pub field: String,
+    pub kind: DriftKind,
+    pub count: u64,
+    pub last_found: &'static str,
+    pub last_message: Option<String>,
+}
+
+type Summaries = BTreeMap<(&'static str, String, DriftKind), DriftSummary>;
+
+static REGISTRY: Mutex<Summaries> = Mutex::new(BTreeMap::new());
+
+thread_local! {
+    /// The drift observed by the innermost `collect` running on the thread.
+    static COLLECTOR: RefCell<Option<Summaries>> = const { RefCell::new(None) };
+}
+
+fn json_type(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "bool",
+        serde_json::Value::Number(_) => "number",
+        serde_json::Value::String(_) => "string",
+        serde_json::Value::Array(_) => "array",
+        serde_json::Value::Object(_) => "object",
+    }
+}
+
+fn summarize(summaries: &mut Summaries, event: &DriftEvent) {
+    let summary = summaries
+        .entry((event.schema, event.field.clone(), event.kind))
+        .or_insert_with(|| DriftSummary {
+            schema: event.schema,
+            field: event.field.clone(),
+            kind: event.kind,
+            count: 0,
+            last_found: event.found,
+            last_message: None,
+        });
+
+    summary.count += 1;
+    summary.last_found = event.found;
+    summary.last_message = event.message.clone();
+}
+
+fn record(event: DriftEvent) {
+    summarize(
+        &mut REGISTRY.lock().unwrap_or_else(|e| e.into_inner()),
+        &event,
+    );
+
+    COLLECTOR.with(|collector| {
+        if let Some(summaries) = collector.borrow_mut().as_mut() {
+            summarize(summaries, &event);
+        }
+    });
+}
+
+pub(crate) fn record_mismatch(
+    schema: &'static str,
+    field: &str,
+    value: &serde_json::Value,
+    error: &serde_json::Error,
+) {
+    record(DriftEvent {
+        schema,
+        field: field.to_string(),
+        kind: DriftKind::TypeMismatch,
+        found: json_type(value),
+        message: Some(error.to_string()),
+    });
+}
+
+pub(crate) fn record_unknown(schema: &'static str, field: &str, value: &serde_json::Value) {
+    record(DriftEvent {
+        schema,
+        field: field.to_string(),
+        kind: DriftKind::UnknownField,
+        found: json_type(value),
+        message: None,
+    });
+}
+
+/// Runs `decode`, returning the drift it observed by field alongside its
+/// result. Drift recorded by other decodes, e.g. those of concurrent requests,
+/// isn't included.
+pub fn collect<T>(decode: impl FnOnce() -> T) -> (T, Vec<DriftSummary>) {
+    let outer = COLLECTOR.with(|collector| collector.replace(Some(Summaries::new())));
+    let result = decode();
+    let summaries = COLLECTOR.with(|collector| collector.replace(outer));
+
+    (
+        result,
+        summaries
+            .map(|summaries| summaries.into_values().collect())
+            .unwrap_or_default(),
+    )
+}
+
+/// Returns the drift observed for every field since the process started.
+pub fn summaries() -> Vec<DriftSummary> {
+    let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+
+    registry.values().cloned().collect()
+}
@@ -1,148 +1,240 @@
 use serde::{Deserialize, Serialize};
 
+pub mod de;
+pub mod drift;
+pub mod meta;
+
 teamwork_macros::generate_schema!([
-    (
-        Task,
-        r#"{
-      "id": 1,
-      "boardColumn": {
-        "id": 1,
-        "name": "testing",
-        "color": "E74C3C"
-      },
-      "canComplete": true,
-      "comments-count": 0,
-      "description": "",
-      "has-reminders": false,
-      "has-unread-comments": false,
-      "private": 0,
-      "content": "adawa",
-      "order": 1,
-      "project-id": 1,
-      "project-name": "Project 2",
-      "todo-list-id": 1,
-      "todo-list-name": "Task List - Added on 03 December",
-      "tasklist-private": false,
-      "tasklist-isTemplate": false,
-      "status": "new",
-      "company-name": "MCG Company",
-      "company-id": 1,
-      "creator-id": 1,
-      "creator-firstname": "Holly",
-      "creator-lastname": "Bracken",
-      "updater-id": 0,
-      "updater-firstname": "",
-      "updater-lastname": "",
-      "completed": false,
-      "start-date": "",
-      "due-date-base": "",
-      "due-date": "",
-      "created-on": "2018-12-12T10:06:31Z",
-      "last-changed-on": "2019-01-16T11:00:44Z",
-      "position": 2001,
-      "estimated-minutes": 0,
-      "priority": "",
-      "progress": 0,
-      "harvest-enabled": false,
-      "parentTaskId": "",
-      "lockdownId": "",
-      "tasklist-lockdownId": "",
-      "has-dependencies": 0,
-      "has-predecessors": 0,
-      "hasTickets": false,
-      "timeIsLogged": "0",
-      "attachments-count": 0,
-      "predecessors": [],
-      "canEdit": true,
-      "viewEstimatedTime": true,
-      "creator-avatar-url": "",
-      "canLogTime": true,
-      "userFollowingComments": false,
-      "userFollowingChanges": false,
-      "DLM": 0,
-      "tags": [
-        {
-          "id": 32661,
-          "name": "On Hold",
-          "color": "f4bd38",
-          "projectId": 0
+    Task => ["schemas/task.json", "schemas/task_assigned.json"] {
+        lenient,
+        extra,
+        rename "created-on" => "created_at",
+        rename "last-changed-on" => "updated_at",
+    },
+    TimeEntry => "schemas/time_entry.json" {
+        lenient,
+        extra,
+    },
+    TaskList => "schemas/task_list.json" {
+        lenient,
+        extra,
+    },
+    Project => "schemas/project.json" {
+        lenient,
+        extra,
+        rename "created-on" => "created_at",
+        rename "last-changed-on" => "updated_at",
+    },
+]);
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    use crate::drift::{self, DriftKind};
+
+    teamwork_macros::generate_schema!([
+        (
+            Sample,
+            r#"{"id": 1, "content": "Write docs", "created-on": "2020-01-01", "hours": 1.5, "done": false, "tags": [{"name": "docs"}], "parent": null}"#
+        ),
+        (
+            Overridden,
+            r#"{"id": 1, "content": "Write docs", "DLM": 0, "estimated-minutes": 30, "status": "new"}"#
+            {
+                rename "content" => "title",
+                required "id",
+                skip "DLM",
+                type "estimated-minutes" => Option<u32>,
+                serde "status" => (skip_serializing_if = "Option::is_none"),
+            }
+        ),
+        (
+            Lenient,
+            r#"{"id": 1, "name": "Write docs", "DLM": 0}"#
+            {
+                lenient,
+                extra,
+                skip "DLM",
+            }
+        ),
+    ]);
+
+    /// Checks a serialized struct against the top level of its JSON Schema:
+    /// the required and allowed properties and their types.
+    fn validate(schema: &serde_json::Value, value: &serde_json::Value) -> Result<(), String> {
+        let object = value.as_object().ok_or("not an object")?;
+
+        for key in schema["required"].as_array().unwrap() {
+            if !object.contains_key(key.as_str().unwrap()) {
+                return Err(format!("missing {}", key));
+            }
+        }
+
+        for (key, value) in object {
+            let property = schema["properties"]
+                .get(key)
+                .ok_or_else(|| format!("unexpected {}", key))?;
+
+            let types: Vec<&str> = match &property["type"] {
+                serde_json::Value::String(ty) => vec![ty.as_str()],
+                serde_json::Value::Array(types) => {
+                    types.iter().filter_map(|t| t.as_str()).collect()
+                }
+                _ => continue,
+            };
+
+            let found = match value {
+                serde_json::Value::Null => "null",
+                serde_json::Value::Bool(_) => "boolean",
+                serde_json::Value::Number(n) if n.is_f64() => "number",
+                serde_json::Value::Number(_) => "integer",
+                serde_json::Value::String(_) => "string",
+                serde_json::Value::Array(_) => "array",
+                serde_json::Value::Object(_) => "object",
+            };
+
+            if !types.contains(&found) && (found != "integer" || !types.contains(&"number")) {
+                return Err(format!("{} is {}, expected {:?}", key, found, types));
+            }
         }
-      ],
-      "parent-task": {
-        "content": "ParentTask",
-        "id": "17774182"
-      }
+
+        Ok(())
     }
-"#
-    ),
-    (
-        TimeEntry,
-        r#"
-{
-      "project-id": "1",
-      "isbillable": "0",
-      "tasklistId": "",
-      "todo-list-name": "",
-      "todo-item-name": "",
-      "isbilled": "0",
-      "updated-date": "2017-11-13T13:08:23Z",
-      "todo-list-id": "",
-      "tags": [],
-      "canEdit": false,
-      "taskEstimatedTime": "0",
-      "company-name": "MCG Cleaning Services",
-      "id": "1",
-      "invoiceNo": "",
-      "person-last-name": "McGill",
-      "parentTaskName": "",
-      "dateUserPerspective": "2014-03-30T10:10:00Z",
-      "minutes": "15",
-      "person-first-name": "Holly",
-      "description": "",
-      "ticket-id": "",
-      "createdAt": "2017-11-13T13:08:23Z",
-      "taskIsPrivate": "0",
-      "parentTaskId": "0",
-      "company-id": "1",
-      "project-status": "archived",
-      "person-id": "1",
-      "project-name": "Website rewrite!",
-      "task-tags": [],
-      "taskIsSubTask": "0",
-      "todo-item-id": "",
-      "date": "2014-03-30T09:10:00Z",
-      "has-start-time": "1",
-      "hours": "1"
+
+    #[test]
+    fn emits_the_json_schema_of_generated_structs() {
+        let schema = Sample::json_schema();
+
+        assert_eq!(schema["title"], "Sample");
+        assert_eq!(schema["additionalProperties"], false);
+        assert_eq!(
+            schema["required"],
+            json!([
+                "content",
+                "created_on",
+                "done",
+                "hours",
+                "id",
+                "parent",
+                "tags"
+            ])
+        );
+
+        // every field is optional, so nullable, and renamed as it's serialized
+        let properties = &schema["properties"];
+        assert_eq!(properties["id"], json!({"type": ["integer", "null"]}));
+        assert_eq!(properties["hours"], json!({"type": ["number", "null"]}));
+        assert_eq!(properties["done"], json!({"type": ["boolean", "null"]}));
+        assert_eq!(
+            properties["created_on"],
+            json!({"type": ["string", "null"]})
+        );
+        assert_eq!(properties["parent"], json!({}));
+
+        // nested structs are embedded, and array elements aren't nullable
+        assert_eq!(properties["tags"]["type"], json!(["array", "null"]));
+        assert_eq!(properties["tags"]["items"], Tag::json_schema());
+        assert_eq!(
+            Tag::json_schema()["properties"]["name"],
+            json!({"type": ["string", "null"]})
+        );
+
+        let record: Sample = serde_json::from_value(json!({"id": 1, "hours": 2})).unwrap();
+        validate(&schema, &serde_json::to_value(&record).unwrap()).unwrap();
     }
-"#
-    ),
-    (
-        TaskList,
-        r#"
-    {
-      "id": "1",
-      "name": "task list 1",
-      "description": "",
-      "position": 1,
-      "projectId": "1",
-      "projectName": "My testing project",
-      "updatedAfter": "2018-09-13T14:57:03Z",
-      "private": false,
-      "isTemplate": false,
-      "tagged": [
-        {
-          "id": 32661,
-          "name": "On Hold",
-          "color": "f4bd38",
-          "projectId": 0
-        }
-      ],
-      "milestone-id": "",
-      "pinned": false,
-      "complete": false,
-      "uncompleted-count": 17,
-      "status": "new"
+
+    #[test]
+    fn applies_field_overrides() {
+        let record: Overridden = serde_json::from_value(json!({
+            "id": 7,
+            "content": "Write docs",
+            "DLM": 1,
+            "estimated-minutes": 30,
+        }))
+        .unwrap();
+
+        assert_eq!(record.id, 7);
+        assert_eq!(record.title.as_deref(), Some("Write docs"));
+        assert_eq!(record.estimated_minutes, Some(30u32));
+        assert_eq!(
+            serde_json::to_value(&record).unwrap(),
+            json!({"id": 7, "title": "Write docs", "estimated_minutes": 30})
+        );
+
+        // required fields can't be missing
+        assert!(serde_json::from_value::<Overridden>(json!({"content": "Write docs"})).is_err());
+
+        let schema = Overridden::json_schema();
+        assert_eq!(schema["properties"]["id"], json!({"type": "integer"}));
+        assert!(schema["properties"].get("dlm").is_none());
+
+        // fields serde may skip aren't required, so the output validates
+        assert_eq!(
+            schema["required"],
+            json!(["title", "estimated_minutes", "id"])
+        );
+        validate(&schema, &serde_json::to_value(&record).unwrap()).unwrap();
+        assert!(validate(&schema, &json!({"id": 7, "title": "Write docs"})).is_err());
     }
-  "#
-    )
-]);
+
+    #[test]
+    fn records_drift_rather_than_failing() {
+        let (records, drift) = drift::collect(|| {
+            serde_json::from_value::<Vec<Lenient>>(json!([
+                {"id": "one", "name": "Write docs", "DLM": 1, "color": "red"},
+                {"id": 2, "name": 3, "color": "blue"},
+                {"id": "three", "color": null},
+            ]))
+        });
+        let records = records.unwrap();
+
+        assert_eq!(records[0].id, None);
+        assert_eq!(records[0].name.as_deref(), Some("Write docs"));
+        assert_eq!(
+            serde_json::to_value(&records[0].extra).unwrap(),
+            json!({"color": "red"})
+        );
+        assert_eq!(records[1].id, Some(2));
+        assert_eq!(records[1].name, None);
+
+        // drift is summarized once per field
+        let drift: Vec<_> = drift
+            .iter()
+            .map(|d| (d.field.as_str(), d.kind, d.count, d.last_found))
+            .collect();
+        assert_eq!(
+            drift,
+            [
+                ("color", DriftKind::UnknownField, 3, "null"),
+                ("id", DriftKind::TypeMismatch, 2, "string"),
+                ("name", DriftKind::TypeMismatch, 1, "number"),
+            ]
+        );
+
+        assert!(drift::summaries()
+            .iter()
+            .any(|s| s.schema == "Lenient" && s.field == "color" && s.count >= 3));
+    }
+
+    #[test]
+    fn collects_only_the_drift_of_its_own_decode() {
+        let decode = || serde_json::from_value::<Lenient>(json!({"id": "one"})).unwrap();
+
+        // drift recorded outside of `collect`, or on other threads, such as
+        // by concurrent requests, isn't collected
+        decode();
+        let (_, drift) = drift::collect(|| std::thread::spawn(decode).join().unwrap());
+        assert!(drift.is_empty());
+
+        let (_, drift) = drift::collect(|| {
+            let (_, inner) = drift::collect(decode);
+            assert_eq!(inner.len(), 1);
+
+            serde_json::from_value::<Lenient>(json!({"id": 1, "name": 1})).unwrap()
+        });
+        assert_eq!(drift.len(), 1);
+        assert_eq!(drift[0].field, "name");
+    }
+}
@@ -0,0 +1,382 @@
+//! Metadata describing the structs generated by `generate_schema!`, along with
+//! comparing the metadata against records returned by Teamwork.
+
+use std::collections::BTreeSet;
+
+use serde::Serialize;
+
+/// The type of a generated field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    String,
+    /// A value Teamwork sends as either a string or a number, normalized to a
+    /// string.
+    StringOrNumber,
+    Integer,
+    Float,
+    Bool,
+    /// A nested struct, referenced by name.
+    Object(&'static str),
+    Array(&'static FieldKind),
+    /// The type couldn't be inferred from the samples.
+    Any,
+}
+
+impl FieldKind {
+    /// Whether a JSON value can be deserialized as this kind. `null` is only
+    /// accepted by `Any`, whether a field can be `null` depends on whether it's
+    /// required.
+    pub fn accepts(&self, value: &serde_json::Value) -> bool {
+        use serde_json::Value;
+
+        match (self, value) {
+            (FieldKind::Any, _) => true,
+            (FieldKind::String, Value::String(_)) => true,
+            (FieldKind::StringOrNumber, Value::String(_) | Value::Number(_)) => true,
+            (FieldKind::Integer, Value::Number(n)) => n.is_i64() || n.is_u64(),
+            (FieldKind::Float, Value::Number(_)) => true,
+            (FieldKind::Bool, Value::Bool(_)) => true,
+            (FieldKind::Object(_), Value::Object(_)) => true,
+            (FieldKind::Array(inner), Value::Array(items)) => items
+                .iter()
+                .all(|item| !item.is_null() && inner.accepts(item)),
+            (_, Value::Null) => false,
+            _ => false,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            FieldKind::String => "string".into(),
+            FieldKind::StringOrNumber => "string or number".into(),
+            FieldKind::Integer => "integer".into(),
+            FieldKind::Float => "float".into(),
+            FieldKind::Bool => "bool".into(),
+            FieldKind::Object(name) => format!("object ({})", name),
+            FieldKind::Array(inner) => format!("array of {}", inner.describe()),
+            FieldKind::Any => "any".into(),
+        }
+    }
+}
+
+/// A field of a generated struct.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldMeta {
+    /// The name of the field in the normalized response.
+    pub name: &'static str,
+    /// The name of the field in the Teamwork response.
+    pub source: &'static str,
+    pub kind: FieldKind,
+    /// Whether the field is required, rather than optional.
+    pub required: bool,
+}
+
+impl FieldMeta {
+    /// Whether a JSON value can be deserialized into the field, `null` being
+    /// accepted when the field is optional.
+    pub fn accepts(&self, value: &serde_json::Value) -> bool {
+        (value.is_null() && !self.required) || self.kind.accepts(value)
+    }
+}
+
+/// A struct generated from the samples.
+#[derive(Debug, Clone, Copy)]
+pub struct SchemaMeta {
+    pub name: &'static str,
+    pub fields: &'static [FieldMeta],
+    /// Fields in the samples that were deliberately omitted from the struct.
+    pub skipped: &'static [&'static str],
+    /// Whether unknown fields are retained in an `extra` map.
+    pub extra: bool,
+}
+
+impl SchemaMeta {
+    /// A normalized record keyed by its Teamwork field names again, so that it
+    /// can be deserialized back into the generated struct. Fields the struct
+    /// doesn't have, such as `extra`, are dropped.
+    pub fn denormalize(&self, record: &serde_json::Value) -> serde_json::Value {
+        let object = match record.as_object() {
+            Some(object) => object,
+            None => return record.clone(),
+        };
+
+        let fields = self.fields.iter().filter_map(|field| {
+            let value = object.get(field.name)?;
+
+            let nested = match field.kind {
+                FieldKind::Object(name) | FieldKind::Array(&FieldKind::Object(name)) => find(name),
+                _ => None,
+            };
+
+            let value = match (nested, value) {
+                (Some(nested), serde_json::Value::Array(items)) => {
+                    items.iter().map(|item| nested.denormalize(item)).collect()
+                }
+                (Some(nested), value) => nested.denormalize(value),
+                (None, value) => value.clone(),
+            };
+
+            Some((field.source.to_string(), value))
+        });
+
+        serde_json::Value::Object(fields.collect())
+    }
+}
+
+/// Implemented for every struct generated by `generate_schema!`.
+pub trait Schema {
+    const META: SchemaMeta;
+}
+
+/// Finds the metadata of the generated struct named `name`.
+pub fn find(name: &str) -> Option<&'static SchemaMeta> {
+    crate::SCHEMAS.iter().find(|s| s.name == name)
+}
+
+/// A difference between a generated struct and the records returned by
+/// Teamwork. Fields are identified by their path in the Teamwork response,
+/// e.g. `boardColumn.name`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum FieldDiff {
+    /// The field is returned by Teamwork but isn't part of the struct.
+    Added { field: String, found: String },
+    /// The field is part of the struct but wasn't returned in any record.
+    Removed { field: String },
+    /// The field's value doesn't match the struct's type.
+    TypeChanged {
+        field: String,
+        expected: String,
+        found: String,
+    },
+}
+
+fn json_type(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "bool",
+        serde_json::Value::Number(n) if n.is_f64() => "float",
+        serde_json::Value::Number(_) => "integer",
+        serde_json::Value::String(_) => "string",
+        serde_json::Value::Array(_) => "array",
+        serde_json::Value::Object(_) => "object",
+    }
+}
+
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Compares the records returned by Teamwork against the generated struct,
+/// returning the sorted and de-duplicated differences.
+pub fn compare(schema: &SchemaMeta, records: &[serde_json::Value]) -> Vec<FieldDiff> {
+    let mut diffs = BTreeSet::new();
+
+    let objects: Vec<&serde_json::Map<String, serde_json::Value>> =
+        records.iter().filter_map(|r| r.as_object()).collect();
+
+    compare_objects(schema, &objects, "", &mut diffs);
+
+    diffs.into_iter().collect()
+}
+
+fn compare_objects(
+    schema: &SchemaMeta,
+    objects: &[&serde_json::Map<String, serde_json::Value>],
+    path: &str,
+    diffs: &mut BTreeSet<FieldDiff>,
+) {
+    if objects.is_empty() {
+        return;
+    }
+
+    for object in objects {
+        for (key, value) in object.iter() {
+            let known = schema.fields.iter().any(|f| f.source == key)
+                || schema.skipped.contains(&key.as_str());
+
+            if !known {
+                diffs.insert(FieldDiff::Added {
+                    field: join_path(path, key),
+                    found: json_type(value).to_string(),
+                });
+            }
+        }
+    }
+
+    for field in schema.fields {
+        let field_path = join_path(path, field.source);
+        let values: Vec<&serde_json::Value> =
+            objects.iter().filter_map(|o| o.get(field.source)).collect();
+
+        if values.is_empty() {
+            diffs.insert(FieldDiff::Removed { field: field_path });
+            continue;
+        }
+
+        for value in &values {
+            if !field.accepts(value) {
+                diffs.insert(FieldDiff::TypeChanged {
+                    field: field_path.clone(),
+                    expected: field.kind.describe(),
+                    found: json_type(value).to_string(),
+                });
+            }
+        }
+
+        // recurse into nested structs so that drift in e.g. `boardColumn` is
+        // reported as well
+        let nested = match field.kind {
+            FieldKind::Object(name) | FieldKind::Array(&FieldKind::Object(name)) => find(name),
+            _ => None,
+        };
+
+        if let Some(nested) = nested {
+            let nested_objects: Vec<&serde_json::Map<String, serde_json::Value>> = values
+                .iter()
+                .flat_map(|v| match v {
+                    serde_json::Value::Array(items) => items.iter().collect(),
+                    v => vec![*v],
+                })
+                .filter_map(|v| v.as_object())
+                .collect();
+
+            compare_objects(nested, &nested_objects, &field_path, diffs);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const FIELDS: &[FieldMeta] = &[
+        FieldMeta {
+            name: "id",
+            source: "id",
+            kind: FieldKind::Integer,
+            required: true,
+        },
+        FieldMeta {
+            name: "content",
+            source: "content",
+            kind: FieldKind::String,
+            required: false,
+        },
+        FieldMeta {
+            name: "tags",
+            source: "tags",
+            kind: FieldKind::Array(&FieldKind::String),
+            required: false,
+        },
+        FieldMeta {
+            name: "board_column",
+            source: "boardColumn",
+            kind: FieldKind::Object("BoardColumn"),
+            required: false,
+        },
+        FieldMeta {
+            name: "estimate",
+            source: "estimate",
+            kind: FieldKind::Float,
+            required: false,
+        },
+    ];
+
+    const SCHEMA: SchemaMeta = SchemaMeta {
+        name: "Sample",
+        fields: FIELDS,
+        skipped: &["DLM"],
+        extra: false,
+    };
+
+    #[test]
+    fn compares_records_with_the_schema() {
+        let diffs = compare(
+            &SCHEMA,
+            &[
+                json!({
+                    "id": 1,
+                    "content": null,
+                    "tags": ["docs"],
+                    "boardColumn": {"id": 1, "name": "Doing", "color": 3, "limit": 5},
+                    "DLM": 0,
+                    "priority": "high",
+                }),
+                json!({"id": null, "content": 2, "tags": ["docs", null]}),
+            ],
+        );
+
+        assert_eq!(
+            diffs,
+            [
+                FieldDiff::Added {
+                    field: "boardColumn.limit".into(),
+                    found: "integer".into(),
+                },
+                FieldDiff::Added {
+                    field: "priority".into(),
+                    found: "string".into(),
+                },
+                FieldDiff::Removed {
+                    field: "estimate".into(),
+                },
+                FieldDiff::TypeChanged {
+                    field: "boardColumn.color".into(),
+                    expected: "string".into(),
+                    found: "integer".into(),
+                },
+                FieldDiff::TypeChanged {
+                    field: "content".into(),
+                    expected: "string".into(),
+                    found: "integer".into(),
+                },
+                // required fields can't be null, optional ones can
+                FieldDiff::TypeChanged {
+                    field: "id".into(),
+                    expected: "integer".into(),
+                    found: "null".into(),
+                },
+                FieldDiff::TypeChanged {
+                    field: "tags".into(),
+                    expected: "array of string".into(),
+                    found: "array".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn denormalizes_records_to_the_teamwork_names() {
+        assert_eq!(
+            SCHEMA.denormalize(&json!({
+                "id": 1,
+                "content": null,
+                "board_column": {"id": 2, "name": "Doing"},
+                "extra": {"priority": "high"},
+            })),
+            json!({
+                "id": 1,
+                "content": null,
+                "boardColumn": {"id": 2, "name": "Doing"},
+            })
+        );
+    }
+
+    #[test]
+    fn matches_values_to_kinds() {
+        assert!(FieldKind::StringOrNumber.accepts(&json!(1)));
+        assert!(FieldKind::Float.accepts(&json!(1)));
+        assert!(!FieldKind::Integer.accepts(&json!(1.5)));
+        assert!(!FieldKind::String.accepts(&json!(null)));
+        assert!(FieldKind::Any.accepts(&json!(null)));
+        assert!(FIELDS[1].accepts(&json!(null)));
+        assert!(!FIELDS[0].accepts(&json!(null)));
+    }
+}